@@ -15,4 +15,7 @@ cargo clippy --all-targets --all-features -- -D warnings
 echo "==> Running cargo check"
 cargo check --all-targets --all-features
 
+echo "==> Running tests (headless compositor)"
+cargo test --all-targets --all-features
+
 echo "✅ Local CI checks passed"
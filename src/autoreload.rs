@@ -0,0 +1,175 @@
+//! `auto_reload`: reloading the config once an editor is done saving it. The directory is
+//! watched rather than the file, editors that write a new file and rename it over the old
+//! one replace the inode a watch on the file would be attached to.
+
+use std::{ffi::OsStr, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::Path, time::Duration};
+
+use smithay::reexports::{
+    calloop::{
+        Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+        generic::Generic,
+        timer::{TimeoutAction, Timer},
+    },
+    rustix::{
+        fd::OwnedFd,
+        fs::inotify::{self, CreateFlags, WatchFlags},
+        io::Errno,
+    },
+};
+
+use crate::Smallvil;
+
+/// How long the file has to stay quiet before it is reloaded, editors write and rename
+/// in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+pub struct ConfigWatch {
+    loop_handle: LoopHandle<'static, Smallvil>,
+    inotify: RegistrationToken,
+    /// Armed by the last event, reloads when it fires.
+    debounce: Option<RegistrationToken>,
+    /// The contents last loaded. Events that leave them as they were, such as the default
+    /// config being written, don't reload.
+    contents: Option<Vec<u8>>,
+}
+
+impl ConfigWatch {
+    /// Starts watching the directory of `config_path`.
+    fn new(
+        loop_handle: &LoopHandle<'static, Smallvil>,
+        config_path: &Path,
+    ) -> Result<Self, String> {
+        let Some(dir) = config_path.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+            return Err(format!("{} has no directory to watch", config_path.display()));
+        };
+        let Some(file_name) = config_path.file_name().map(OsStr::to_os_string) else {
+            return Err(format!("{} is not a file", config_path.display()));
+        };
+
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)
+            .map_err(|err| format!("inotify: {err}"))?;
+        inotify::add_watch(
+            &fd,
+            dir,
+            WatchFlags::CLOSE_WRITE
+                | WatchFlags::MOVED_TO
+                | WatchFlags::CREATE
+                | WatchFlags::DELETE
+                | WatchFlags::DELETE_SELF
+                | WatchFlags::MOVE_SELF,
+        )
+        .map_err(|err| format!("failed to watch {}: {err}", dir.display()))?;
+
+        let inotify = loop_handle
+            .insert_source(Generic::new(fd, Interest::READ, Mode::Level), move |_, fd, state| {
+                if read_events(fd, &file_name) {
+                    state.schedule_config_reload();
+                }
+                Ok(PostAction::Continue)
+            })
+            .map_err(|err| format!("failed to watch the config: {err}"))?;
+
+        Ok(Self {
+            loop_handle: loop_handle.clone(),
+            inotify,
+            debounce: None,
+            contents: std::fs::read(config_path).ok(),
+        })
+    }
+}
+
+impl Drop for ConfigWatch {
+    fn drop(&mut self) {
+        self.loop_handle.remove(self.inotify);
+        if let Some(debounce) = self.debounce.take() {
+            self.loop_handle.remove(debounce);
+        }
+    }
+}
+
+/// Drains the pending events, returns whether any was about the file named `file_name`.
+fn read_events(fd: &OwnedFd, file_name: &OsStr) -> bool {
+    let mut buffer = [MaybeUninit::uninit(); 4096];
+    let mut reader = inotify::Reader::new(fd, &mut buffer);
+    let mut changed = false;
+    loop {
+        match reader.next() {
+            Ok(event) => {
+                // Events about the directory itself carry no name.
+                changed |=
+                    event.file_name().is_none_or(|name| name.to_bytes() == file_name.as_bytes());
+            }
+            Err(Errno::AGAIN) => break,
+            Err(Errno::INTR) => {}
+            Err(err) => {
+                tracing::warn!("Failed to read config file events: {err}");
+                break;
+            }
+        }
+    }
+    changed
+}
+
+impl Smallvil {
+    /// Starts or stops watching the config file, after `auto_reload` changed.
+    pub fn set_auto_reload(&mut self, enabled: bool) {
+        if !enabled {
+            self.config_watch = None;
+            return;
+        }
+        if self.config_watch.is_some() {
+            return;
+        }
+
+        match ConfigWatch::new(&self.loop_handle, &self.config_path) {
+            Ok(watch) => {
+                tracing::info!("Reloading {} when it changes", self.config_path.display());
+                self.config_watch = Some(watch);
+            }
+            Err(err) => tracing::warn!("Failed to enable auto_reload: {err}"),
+        }
+    }
+
+    /// Restarts the quiet period after an event, the config is reloaded at its end.
+    fn schedule_config_reload(&mut self) {
+        let Some(watch) = self.config_watch.as_mut() else {
+            return;
+        };
+        if let Some(debounce) = watch.debounce.take() {
+            self.loop_handle.remove(debounce);
+        }
+
+        let timer =
+            self.loop_handle.insert_source(Timer::from_duration(DEBOUNCE), |_, (), state| {
+                if let Some(watch) = state.config_watch.as_mut() {
+                    watch.debounce = None;
+                }
+                state.reload_changed_config();
+                TimeoutAction::Drop
+            });
+        match timer {
+            Ok(timer) => watch.debounce = Some(timer),
+            Err(err) => tracing::warn!("Failed to schedule config reload: {err}"),
+        }
+    }
+
+    /// Reloads the config if its contents changed since they were last loaded. A missing
+    /// file is left alone, it is likely about to be replaced.
+    fn reload_changed_config(&mut self) {
+        let Ok(contents) = std::fs::read(&self.config_path) else {
+            return;
+        };
+        let Some(watch) = self.config_watch.as_mut() else {
+            return;
+        };
+        if watch.contents.as_ref() == Some(&contents) {
+            return;
+        }
+        watch.contents = Some(contents);
+
+        tracing::info!("{} changed, reloading it", self.config_path.display());
+        if let Err(err) = self.reload_config() {
+            tracing::warn!("Failed to reload the config: {err}");
+        }
+    }
+}
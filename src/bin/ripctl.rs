@@ -1,11 +1,45 @@
-use std::{io::Write, os::unix::net::UnixStream, path::PathBuf};
+use std::{
+    ffi::OsString,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::OnceLock,
+};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = std::env::args().skip(1);
+use serde_json::{Value, json};
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("ripctl: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "--socket").is_some() {
+        let Some(path) = args.next() else {
+            return Err("Missing value for --socket <path>".into());
+        };
+        let _ = SOCKET.set(PathBuf::from(path));
+    }
 
     match args.next().as_deref() {
         Some("reload") => send_reload(),
         Some("keyboard") => send_keyboard(args),
+        Some("minimized") => list_minimized(),
+        Some("windows") => list_windows(args),
+        Some("outputs") => list_outputs(args),
+        Some("workspace") => send_workspace(args),
+        Some("output") => send_output(args),
+        Some("exec") => send_exec(args),
+        Some("wallpaper") => send_wallpaper(args),
+        Some("border") => send_border(args),
+        Some("focus") => send_focus(args),
+        Some("move") => send_move(args),
+        Some("unminimize") => send_unminimize(args),
+        Some("debug") => send_debug(args),
+        Some("subscribe") => subscribe(args),
         Some("-h" | "--help") | None => {
             print_help();
             Ok(())
@@ -16,13 +50,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 fn print_help() {
     println!(
-        "ripctl\n\nUsage:\n  ripctl reload\n  ripctl keyboard <layout> [variant]\n\nCommands:\n  reload                       Ask a running ripwm instance to reload configuration\n  keyboard <layout> [variant]  Set keyboard layout/variant on a running ripwm instance"
+        "ripctl\n\nUsage:\n  ripctl reload\n  ripctl keyboard <layout> [variant]\n  ripctl minimized\n  ripctl windows [--json]\n  ripctl outputs [--json]\n  ripctl output <name> <mode WxH[@Hz]|scale <s>|transform <t>|position <x> <y>|on|off> [--save]\n  ripctl workspace <n|next|prev|list>\n  ripctl exec <program> [args...]\n  ripctl wallpaper <path|off|#RRGGBB> [output] [--save]\n  ripctl border [--active <color>] [--inactive <color>] [--width <px>]\n  ripctl focus [<id>] [--app-id <app_id>] [--title <text>] [--regex] [--warp]\n  ripctl move [--app-id <app_id>] (--workspace <n> | --output <name>)\n  ripctl unminimize <app_id>\n  ripctl debug [--redact-titles]\n  ripctl debug overlay <on|off|toggle>\n  ripctl subscribe\n\nOptions:\n  --socket <path>              Talk to the instance listening on <path>, needed when\n                               several run and neither $RIPWM_IPC_SOCKET nor\n                               $WAYLAND_DISPLAY tells which\n\nCommands:\n  reload                       Ask a running ripwm instance to reload configuration\n  keyboard <layout> [variant]  Set keyboard layout/variant on a running ripwm instance\n  minimized                    List the app_ids of minimized windows\n  windows [--json]             Print the mapped windows as JSON, on one line with --json\n  outputs [--json]             Print a table of outputs, or them as JSON with --json\n  output <name> <setting> [--save]\n                               Change an output until the next reload, --save writes it to the config\n  workspace <n|next|prev>      Switch workspaces, relative ones wrap around\n  workspace list               Print the workspaces as JSON\n  exec <program> [args...]     Start a program inside the session and print its PID\n  wallpaper <path|off|#RRGGBB> [output] [--save]\n                               Change the wallpaper of one or every output, --save writes it to the config\n  border [--active <color>] [--inactive <color>] [--width <px>]\n                               Change the border colours (#RRGGBB or #RRGGBBAA) and width, 0 hides borders\n  focus [<id>] [--app-id <app_id>] [--title <text>] [--regex] [--warp]\n                               Focus the most recently used matching window, fails if none matches\n  move [--app-id <app_id>] (--workspace <n> | --output <name>)\n                               Send the focused window, or the one with app_id, to a workspace or output\n  unminimize <app_id>          Restore the most recently minimized window with this app_id\n  debug [--redact-titles]      Print the compositor state as JSON, for bug reports\n  debug overlay <on|off|toggle> Show or hide the FPS and frame timing overlay\n  subscribe                    Print events, such as moved windows, as lines of JSON until ripwm exits"
     );
 }
 
 fn send_reload() -> Result<(), Box<dyn std::error::Error>> {
-    send_ipc_command("reload\n")?;
-    println!("Sent reload request to ripwm");
+    send_request(&json!({ "cmd": "reload" }))?;
+    println!("Reloaded ripwm configuration");
     Ok(())
 }
 
@@ -36,19 +70,417 @@ fn send_keyboard(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn s
         return Err("Too many arguments. Usage: ripctl keyboard <layout> [variant]".into());
     }
 
-    let command = if variant.is_empty() {
-        format!("keyboard {layout}\n")
+    send_request(&json!({ "cmd": "keyboard", "layout": layout, "variant": variant }))?;
+    println!("Set keyboard layout: layout={layout}, variant={variant}");
+    Ok(())
+}
+
+fn list_minimized() -> Result<(), Box<dyn std::error::Error>> {
+    let response = send_request(&json!({ "cmd": "minimized" }))?;
+    let app_ids = response.get("app_ids").and_then(Value::as_array).into_iter().flatten();
+    for app_id in app_ids.filter_map(Value::as_str) {
+        println!("{app_id}");
+    }
+    Ok(())
+}
+
+fn list_windows(args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let raw = json_flag(args, "Usage: ripctl windows [--json]")?;
+
+    let response = send_request(&json!({ "cmd": "windows" }))?;
+    let windows = response.get("windows").cloned().unwrap_or_else(|| json!([]));
+    if raw {
+        println!("{windows}");
     } else {
-        format!("keyboard {layout} {variant}\n")
+        println!("{}", serde_json::to_string_pretty(&windows)?);
+    }
+    Ok(())
+}
+
+fn list_outputs(args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let raw = json_flag(args, "Usage: ripctl outputs [--json]")?;
+
+    let response = send_request(&json!({ "cmd": "outputs" }))?;
+    let outputs = response.get("outputs").cloned().unwrap_or_else(|| json!([]));
+    if raw {
+        println!("{outputs}");
+        return Ok(());
+    }
+
+    println!(
+        "{:<12} {:<8} {:<20} {:<12} {:<6} {:<12} MONITOR",
+        "NAME", "STATE", "MODE", "POSITION", "SCALE", "TRANSFORM"
+    );
+    for output in outputs.as_array().into_iter().flatten() {
+        let text = |key: &str| output.get(key).and_then(Value::as_str).unwrap_or_default();
+        let number = |value: Option<&Value>, key: &str| {
+            value.and_then(|value| value.get(key)).and_then(Value::as_f64).unwrap_or_default()
+        };
+
+        let state = if output.get("enabled").and_then(Value::as_bool) != Some(true) {
+            "disabled"
+        } else if output.get("powered").and_then(Value::as_bool) == Some(false) {
+            "off"
+        } else {
+            "on"
+        };
+        let mode = output.get("current_mode").filter(|mode| !mode.is_null());
+        let mode = mode.map_or_else(String::new, |mode| {
+            format!(
+                "{}x{}@{:.3}",
+                number(Some(mode), "width"),
+                number(Some(mode), "height"),
+                number(Some(mode), "refresh")
+            )
+        });
+        let geometry = output.get("geometry").filter(|geometry| !geometry.is_null());
+        let position = geometry.map_or_else(String::new, |geometry| {
+            format!("{},{}", number(Some(geometry), "x"), number(Some(geometry), "y"))
+        });
+        let scale = output.get("scale").and_then(Value::as_f64).unwrap_or(1.0);
+        let mut monitor = format!("{} {}", text("make"), text("model"));
+        if let Some(serial) = output.get("serial").and_then(Value::as_str) {
+            monitor = format!("{monitor} {serial}");
+        }
+        if output.get("pointer").and_then(Value::as_bool) == Some(true) {
+            monitor = format!("{monitor} (pointer)");
+        }
+
+        println!(
+            "{:<12} {:<8} {:<20} {:<12} {:<6} {:<12} {}",
+            text("name"),
+            state,
+            mode,
+            position,
+            scale,
+            text("transform"),
+            monitor.trim()
+        );
+    }
+    Ok(())
+}
+
+/// Reads the optional `--json` flag of a listing command.
+fn json_flag(mut args: impl Iterator<Item = String>, usage: &str) -> Result<bool, String> {
+    match args.next().as_deref() {
+        Some("--json") => {}
+        None => return Ok(false),
+        Some(other) => return Err(format!("Unknown argument: {other}. {usage}")),
+    }
+    if args.next().is_some() {
+        return Err(format!("Too many arguments. {usage}"));
+    }
+    Ok(true)
+}
+
+fn send_output(args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str = "Usage: ripctl output <name> <mode WxH[@Hz]|scale <s>|transform <t>|position <x> <y>|on|off> [--save]";
+
+    let (save, args): (Vec<String>, Vec<String>) = args.partition(|arg| arg == "--save");
+    let mut args = args.into_iter();
+    let Some(name) = args.next() else {
+        return Err(format!("Missing <name>. {USAGE}").into());
+    };
+    let mut request = json!({ "cmd": "output", "name": name, "save": !save.is_empty() });
+
+    let Some(setting) = args.next() else {
+        return Err(format!("Missing setting. {USAGE}").into());
+    };
+    let mut value = || args.next().ok_or_else(|| format!("Missing value for {setting}. {USAGE}"));
+    match setting.as_str() {
+        "on" | "off" => request["enabled"] = json!(setting == "on"),
+        "mode" | "transform" => request[setting.as_str()] = json!(value()?),
+        "scale" => {
+            let scale = value()?;
+            let scale: f64 =
+                scale.parse().map_err(|_| format!("Invalid scale: {scale}. {USAGE}"))?;
+            request["scale"] = json!(scale);
+        }
+        "position" => {
+            let (x, y) = (value()?, value()?);
+            let coordinate = |raw: &str| {
+                raw.parse::<i32>().map_err(|_| format!("Invalid position: {x} {y}. {USAGE}"))
+            };
+            request["position"] = json!([coordinate(&x)?, coordinate(&y)?]);
+        }
+        other => return Err(format!("Unknown setting: {other}. {USAGE}").into()),
+    }
+    if args.next().is_some() {
+        return Err(format!("Too many arguments. {USAGE}").into());
+    }
+
+    send_request(&request)?;
+    Ok(())
+}
+
+fn send_workspace(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str = "Usage: ripctl workspace <n|next|prev|list>";
+
+    let Some(target) = args.next() else {
+        return Err(format!("Missing workspace. {USAGE}").into());
+    };
+    if args.next().is_some() {
+        return Err(format!("Too many arguments. {USAGE}").into());
+    }
+
+    let request = match target.as_str() {
+        "list" => {
+            let response = send_request(&json!({ "cmd": "workspaces" }))?;
+            let workspaces = response.get("workspaces").cloned().unwrap_or_default();
+            println!("{}", serde_json::to_string_pretty(&workspaces)?);
+            return Ok(());
+        }
+        "next" | "prev" => json!({ "cmd": "workspace", "direction": target }),
+        id => {
+            let id: u32 = id.parse().map_err(|_| format!("Invalid workspace: {id}. {USAGE}"))?;
+            json!({ "cmd": "workspace", "id": id })
+        }
+    };
+    send_request(&request)?;
+    Ok(())
+}
+
+fn send_exec(args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let argv: Vec<String> = args.collect();
+    if argv.is_empty() {
+        return Err("Missing <program>. Usage: ripctl exec <program> [args...]".into());
+    }
+
+    let response = send_request(&json!({ "cmd": "exec", "argv": argv }))?;
+    let pid = response.get("pid").and_then(Value::as_u64).unwrap_or_default();
+    println!("{pid}");
+    Ok(())
+}
+
+fn send_wallpaper(args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str = "Usage: ripctl wallpaper <path|off|#RRGGBB> [output] [--save]";
+
+    let mut save = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--save" => save = true,
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown argument: {flag}. {USAGE}").into());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let Some(mut value) = positional.next() else {
+        return Err(format!("Missing <path|off|#RRGGBB>. {USAGE}").into());
+    };
+    let output = positional.next();
+    if positional.next().is_some() {
+        return Err(format!("Too many arguments. {USAGE}").into());
+    }
+
+    // ripwm runs in another directory, relative paths are resolved here.
+    let is_path = !value.eq_ignore_ascii_case("off") && !value.starts_with('#');
+    if is_path && !value.starts_with('~') {
+        value = std::path::absolute(&value)?.to_string_lossy().into_owned();
+    }
+
+    send_request(&json!({ "cmd": "wallpaper", "value": value, "output": output, "save": save }))?;
+    match output {
+        Some(output) => println!("Wallpaper of {output}: {value}"),
+        None => println!("Wallpaper: {value}"),
+    }
+    Ok(())
+}
+
+fn send_border(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "Usage: ripctl border [--active <color>] [--inactive <color>] [--width <px>]";
+
+    let mut request = json!({ "cmd": "border" });
+    while let Some(flag) = args.next() {
+        let key = match flag.as_str() {
+            "--active" => "active",
+            "--inactive" => "inactive",
+            "--width" => "width",
+            other => return Err(format!("Unknown argument: {other}. {USAGE}").into()),
+        };
+        let Some(value) = args.next() else {
+            return Err(format!("Missing value for {flag}. {USAGE}").into());
+        };
+        request[key] = if key == "width" {
+            let width: u32 =
+                value.parse().map_err(|_| format!("Invalid width: {value}. {USAGE}"))?;
+            json!(width)
+        } else {
+            json!(value)
+        };
+    }
+    if request.as_object().is_some_and(|fields| fields.len() == 1) {
+        return Err(format!("Nothing to change. {USAGE}").into());
+    }
+
+    send_request(&request)?;
+    println!("Updated borders");
+    Ok(())
+}
+
+fn send_focus(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "Usage: ripctl focus [<id>] [--app-id <app_id>] [--title <text>] [--regex] [--warp]";
+
+    let mut request = json!({ "cmd": "focus" });
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--app-id" | "--title" => {
+                let Some(value) = args.next() else {
+                    return Err(format!("Missing value for {arg}. {USAGE}").into());
+                };
+                let key = if arg == "--app-id" { "app_id" } else { "title" };
+                request[key] = json!(value);
+            }
+            "--regex" => request["regex"] = json!(true),
+            "--warp" => request["warp"] = json!(true),
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown argument: {flag}. {USAGE}").into());
+            }
+            id => {
+                if request.get("id").is_some() {
+                    return Err(format!("Too many arguments. {USAGE}").into());
+                }
+                let id: u64 =
+                    id.parse().map_err(|_| format!("Invalid window id: {id}. {USAGE}"))?;
+                request["id"] = json!(id);
+            }
+        }
+    }
+    if ["id", "app_id", "title"].iter().all(|key| request.get(key).is_none()) {
+        return Err(format!("Missing window to focus. {USAGE}").into());
+    }
+
+    send_request(&request)?;
+    Ok(())
+}
+
+fn send_move(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "Usage: ripctl move [--app-id <app_id>] (--workspace <n> | --output <name>)";
+
+    let mut request = json!({ "cmd": "move" });
+    while let Some(flag) = args.next() {
+        let key = match flag.as_str() {
+            "--app-id" => "app_id",
+            "--workspace" => "workspace",
+            "--output" => "output",
+            other => return Err(format!("Unknown argument: {other}. {USAGE}").into()),
+        };
+        let Some(value) = args.next() else {
+            return Err(format!("Missing value for {flag}. {USAGE}").into());
+        };
+        request[key] = if key == "workspace" {
+            let workspace: u32 =
+                value.parse().map_err(|_| format!("Invalid workspace: {value}. {USAGE}"))?;
+            json!(workspace)
+        } else {
+            json!(value)
+        };
+    }
+    if request.get("workspace").is_some() == request.get("output").is_some() {
+        return Err(format!("Expected either --workspace or --output. {USAGE}").into());
+    }
+
+    send_request(&request)?;
+    Ok(())
+}
+
+fn send_unminimize(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(app_id) = args.next() else {
+        return Err("Missing <app_id>. Usage: ripctl unminimize <app_id>".into());
     };
+    if args.next().is_some() {
+        return Err("Too many arguments. Usage: ripctl unminimize <app_id>".into());
+    }
 
-    send_ipc_command(&command)?;
-    println!("Sent keyboard update to ripwm: layout={layout}, variant={variant}");
+    send_request(&json!({ "cmd": "unminimize", "app_id": app_id }))?;
+    println!("Restored window: app_id={app_id}");
     Ok(())
 }
 
-fn send_ipc_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let socket_path = ipc_socket_path();
+fn send_debug(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    const USAGE: &str =
+        "Usage: ripctl debug [--redact-titles] | ripctl debug overlay <on|off|toggle>";
+
+    match args.next().as_deref() {
+        Some("overlay") => {}
+        first @ (Some("--redact-titles") | None) => {
+            let redact_titles = first.is_some();
+            if args.next().is_some() {
+                return Err(format!("Too many arguments. {USAGE}").into());
+            }
+            let request = json!({ "cmd": "debug", "redact_titles": redact_titles });
+            let response = send_request(&request)?;
+            let debug = response.get("debug").cloned().unwrap_or_default();
+            println!("{}", serde_json::to_string_pretty(&debug)?);
+            return Ok(());
+        }
+        Some(other) => return Err(format!("Unknown argument: {other}. {USAGE}").into()),
+    }
+    let Some(setting) = args.next() else {
+        return Err(format!("Missing <on|off|toggle>. {USAGE}").into());
+    };
+    if !matches!(setting.as_str(), "on" | "off" | "toggle") {
+        return Err(format!("Invalid setting: {setting}. {USAGE}").into());
+    }
+    if args.next().is_some() {
+        return Err(format!("Too many arguments. {USAGE}").into());
+    }
+
+    send_request(&json!({ "cmd": "debug_overlay", "state": setting }))?;
+    println!("Debug overlay: {setting}");
+    Ok(())
+}
+
+fn subscribe(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    if args.next().is_some() {
+        return Err("Too many arguments. Usage: ripctl subscribe".into());
+    }
+
+    let socket_path = ipc_socket_path()?;
+    let mut stream = UnixStream::connect(&socket_path).map_err(|err| {
+        format!("Failed to connect to ripwm IPC socket at {}: {err}", socket_path.display())
+    })?;
+    stream.write_all(format!("{}\n", json!({ "cmd": "subscribe" })).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let response: Value = serde_json::from_str(&lines.next().transpose()?.unwrap_or_default())
+        .map_err(|err| format!("Invalid response from ripwm: {err}"))?;
+    if response.get("ok").and_then(Value::as_bool) != Some(true) {
+        let error = response.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+        return Err(error.into());
+    }
+    for line in lines {
+        println!("{}", line?);
+    }
+    Ok(())
+}
+
+/// Sends `request` and returns the response, or the error ripwm answered with.
+fn send_request(request: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let response = send_ipc_command(&format!("{request}\n"))?;
+    let response: Value = serde_json::from_str(&response)
+        .map_err(|err| format!("Invalid response from ripwm: {err}"))?;
+
+    if response.get("ok").and_then(Value::as_bool) == Some(true) {
+        return Ok(response);
+    }
+    let error = response.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+    Err(error.into())
+}
+
+/// Sends `command` and returns whatever ripwm answers before closing the connection.
+fn send_ipc_command(command: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let socket_path = ipc_socket_path()?;
 
     let mut stream = UnixStream::connect(&socket_path).map_err(|err| {
         format!("Failed to connect to ripwm IPC socket at {}: {err}", socket_path.display())
@@ -56,21 +488,71 @@ fn send_ipc_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
 
     stream.write_all(command.as_bytes())?;
     stream.shutdown(std::net::Shutdown::Write)?;
-    Ok(())
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
 }
 
-fn ipc_socket_path() -> PathBuf {
+/// `--socket`, which picks the instance to talk to.
+static SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
+/// Finds the socket of the instance to talk to: `--socket`, `$RIPWM_IPC_SOCKET`, the one
+/// named after `$WAYLAND_DISPLAY`, or the only instance running. Sockets nobody answers
+/// on any more are removed on the way.
+fn ipc_socket_path() -> Result<PathBuf, String> {
+    if let Some(path) = SOCKET.get() {
+        return Ok(path.clone());
+    }
     if let Some(path) = std::env::var_os("RIPWM_IPC_SOCKET") {
-        return PathBuf::from(path);
+        return Ok(PathBuf::from(path));
     }
 
-    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
-        return PathBuf::from(runtime_dir).join("ripwm.sock");
+    let dir = if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        PathBuf::from(runtime_dir)
+    } else if let Some(home) = std::env::var_os("HOME") {
+        PathBuf::from(home).join(".config/ripwm")
+    } else {
+        PathBuf::from("/tmp")
+    };
+
+    if let Some(display) = std::env::var_os("WAYLAND_DISPLAY") {
+        let mut file_name = OsString::from("ripwm-");
+        file_name.push(display);
+        file_name.push(".sock");
+        let path = dir.join(file_name);
+        if path.exists() {
+            return Ok(path);
+        }
     }
 
-    if let Some(home) = std::env::var_os("HOME") {
-        return PathBuf::from(home).join(".config/ripwm/ripwm.sock");
+    let mut live = Vec::new();
+    for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with("ripwm-") || !name.ends_with(".sock") {
+            continue;
+        }
+        match UnixStream::connect(&path) {
+            Ok(_) => live.push(path),
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                let _ = std::fs::remove_file(&path);
+            }
+            Err(_) => {}
+        }
     }
 
-    PathBuf::from("/tmp/ripwm.sock")
+    match live.as_slice() {
+        // Instances from before sockets were named after the Wayland socket.
+        [] => Ok(dir.join("ripwm.sock")),
+        [path] => Ok(path.clone()),
+        _ => {
+            let sockets: Vec<_> = live.iter().map(|path| path.display().to_string()).collect();
+            Err(format!(
+                "Several ripwm instances are running, pick one with --socket <path>:\n  {}",
+                sockets.join("\n  ")
+            ))
+        }
+    }
 }
@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Winit,
+    TtyUdev,
+    Headless,
+}
+
+/// Command line options. Flags may come in any order, except that `-c` takes everything
+/// after it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub backend: Option<Backend>,
+    pub drm_device: Option<PathBuf>,
+    /// Number of virtual outputs on the headless backend.
+    pub headless_outputs: Option<u32>,
+    /// Size of each headless output in pixels.
+    pub headless_size: Option<(i32, i32)>,
+    /// Draw headless outputs into offscreen buffers instead of skipping rendering.
+    pub headless_render: bool,
+    /// Number of outputs the nested window is split into.
+    pub winit_outputs: Option<u32>,
+    /// Draw the pointer into the nested window instead of using the host cursor.
+    pub software_cursor: bool,
+    /// Config file to use instead of `~/.config/ripwm/ripwm.toml`.
+    pub config: Option<PathBuf>,
+    /// The first client's argv, from `-c`.
+    pub command: Option<Vec<String>>,
+    /// Start no first client, neither `-c` nor the configured `startup_command`.
+    pub no_spawn: bool,
+    /// Validate the config and exit instead of starting.
+    pub check_config: bool,
+    pub help: bool,
+}
+
+impl Options {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tty-udev" => options.set_backend(Backend::TtyUdev)?,
+                "--winit" => options.set_backend(Backend::Winit)?,
+                "--headless" => options.set_backend(Backend::Headless)?,
+                "--headless-outputs" => options.headless_outputs = Some(count(&mut args, &arg)?),
+                "--headless-size" => {
+                    let value = value(&mut args, &arg)?;
+                    let size = parse_size(&value).ok_or_else(|| {
+                        format!("Invalid value for {arg}: {value}, expected WIDTHxHEIGHT")
+                    })?;
+                    options.headless_size = Some(size);
+                }
+                "--headless-render" => options.headless_render = true,
+                "--winit-outputs" => options.winit_outputs = Some(count(&mut args, &arg)?),
+                "--software-cursor" => options.software_cursor = true,
+                "--drm-device" => {
+                    options.drm_device = Some(PathBuf::from(value(&mut args, &arg)?));
+                }
+                "--config" => options.config = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--check-config" => {
+                    options.check_config = true;
+                    // The path is optional, like with --config it replaces the default.
+                    if let Some(path) = args.next_if(|arg| !arg.starts_with('-')) {
+                        options.config = Some(PathBuf::from(path));
+                    }
+                }
+                "-c" | "--command" => {
+                    options.command = Some(command(args.by_ref().collect(), &arg)?);
+                }
+                "--no-spawn" => options.no_spawn = true,
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("Unknown argument: {arg}. See ripwm --help")),
+            }
+        }
+
+        if options.no_spawn && options.command.is_some() {
+            return Err("--no-spawn and -c conflict: use only one of them".into());
+        }
+        Ok(options)
+    }
+
+    fn set_backend(&mut self, backend: Backend) -> Result<(), String> {
+        if self.backend.is_some_and(|existing| existing != backend) {
+            return Err(
+                "Conflicting backend flags: use only one of --tty-udev, --winit or --headless"
+                    .into(),
+            );
+        }
+        self.backend = Some(backend);
+        Ok(())
+    }
+
+    /// The GPU to use on the udev backend: `--drm-device`, then `$RIPWM_DRM_DEVICE`, then
+    /// the older `$SMALLVIL_DRM_DEVICE`.
+    pub fn drm_device(&self) -> Option<PathBuf> {
+        self.drm_device.clone().or_else(|| {
+            std::env::var_os("RIPWM_DRM_DEVICE")
+                .or_else(|| std::env::var_os("SMALLVIL_DRM_DEVICE"))
+                .map(PathBuf::from)
+        })
+    }
+
+    /// The config file: `--config`, then `$RIPWM_CONFIG`, then the default location.
+    pub fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .or_else(|| std::env::var_os("RIPWM_CONFIG").map(PathBuf::from))
+            .unwrap_or_else(crate::config::default_config_path)
+    }
+}
+
+/// The argv of `-c`: the arguments after it, or a single one split like a shell would.
+fn command(mut argv: Vec<String>, flag: &str) -> Result<Vec<String>, String> {
+    if argv.len() == 1 {
+        let line = argv.pop().unwrap_or_default();
+        argv = crate::spawn::split_command(&line)
+            .map_err(|err| format!("Invalid value for {flag}: {line}: {err}"))?;
+    }
+    if argv.is_empty() {
+        return Err(format!("Missing value for {flag}"));
+    }
+    Ok(argv)
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("Missing value for {flag}"))
+}
+
+/// The value of `flag`, a positive number.
+fn count(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<u32, String> {
+    let value = value(args, flag)?;
+    value
+        .parse()
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(|| format!("Invalid value for {flag}: {value}, expected a positive number"))
+}
+
+/// Parses `WIDTHxHEIGHT`, both positive.
+fn parse_size(value: &str) -> Option<(i32, i32)> {
+    let (width, height) = value.split_once('x')?;
+    let width = width.trim().parse().ok().filter(|width| *width > 0)?;
+    let height = height.trim().parse().ok().filter(|height| *height > 0)?;
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn argv(args: &[&str]) -> Option<Vec<String>> {
+        Some(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn command_takes_the_remaining_arguments() {
+        let options = parse(&["-c", "foot", "--title", "main shell"]).unwrap();
+        assert_eq!(options.command, argv(&["foot", "--title", "main shell"]));
+    }
+
+    #[test]
+    fn flags_after_command_belong_to_it() {
+        let options = parse(&["-c", "weston-terminal", "--winit", "-h"]).unwrap();
+        assert_eq!(options.backend, None);
+        assert!(!options.help);
+        assert_eq!(options.command, argv(&["weston-terminal", "--winit", "-h"]));
+    }
+
+    #[test]
+    fn a_single_command_argument_is_split() {
+        let options = parse(&["--command", "foot -e 'htop -d 5' \"a b\""]).unwrap();
+        assert_eq!(options.command, argv(&["foot", "-e", "htop -d 5", "a b"]));
+
+        let options = parse(&["-c", "firefox"]).unwrap();
+        assert_eq!(options.command, argv(&["firefox"]));
+    }
+
+    #[test]
+    fn backend_flags_before_command() {
+        let options = parse(&["--winit", "-c", "foot"]).unwrap();
+        assert_eq!(options.backend, Some(Backend::Winit));
+        assert_eq!(options.command, argv(&["foot"]));
+
+        let options = parse(&[
+            "--tty-udev",
+            "--drm-device",
+            "/dev/dri/card1",
+            "-c",
+            "firefox",
+            "--new-window",
+        ])
+        .unwrap();
+        assert_eq!(options.backend, Some(Backend::TtyUdev));
+        assert_eq!(options.drm_device, Some(PathBuf::from("/dev/dri/card1")));
+        assert_eq!(options.command, argv(&["firefox", "--new-window"]));
+    }
+
+    #[test]
+    fn invalid_commands_are_errors() {
+        assert!(parse(&["--winit", "-c"]).is_err());
+        assert!(parse(&["-c", ""]).is_err());
+        assert!(parse(&["-c", "foot 'unterminated"]).is_err());
+    }
+
+    #[test]
+    fn no_spawn_conflicts_with_command() {
+        assert!(parse(&["--no-spawn", "--winit"]).unwrap().no_spawn);
+        assert!(parse(&["--no-spawn", "-c", "foot"]).is_err());
+        // After -c it is an argument of the command.
+        let options = parse(&["-c", "foot", "--no-spawn"]).unwrap();
+        assert!(!options.no_spawn);
+        assert_eq!(options.command, argv(&["foot", "--no-spawn"]));
+    }
+
+    #[test]
+    fn conflicting_backends_are_errors() {
+        assert!(parse(&["--winit", "--tty-udev", "-c", "foot"]).is_err());
+    }
+}
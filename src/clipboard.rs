@@ -0,0 +1,222 @@
+//! `persist_clipboard`: keeping what was copied once the client it came from exits. The
+//! data of a new selection is read right away, in the types the config asks for, and when
+//! its client is gone the compositor offers the copy as the selection instead. Reads and
+//! writes go through non-blocking pipes on the event loop, a slow client can't stall it.
+
+use std::{os::unix::io::OwnedFd, sync::Arc};
+
+use smithay::{
+    reexports::{
+        calloop::{Interest, Mode, PostAction, RegistrationToken, generic::Generic},
+        rustix::{
+            io::{self, Errno},
+            pipe::{PipeFlags, pipe_with},
+        },
+        wayland_server::{Resource, backend::ClientId},
+    },
+    wayland::selection::{
+        SelectionSource,
+        data_device::{request_data_device_client_selection, set_data_device_selection},
+    },
+};
+
+use crate::{Smallvil, config::PersistClipboard};
+
+/// The kept clipboard, by mime type. It is the user data of the selection the compositor
+/// offers, so each offer serves what it was made with.
+pub type ClipboardContents = Arc<Vec<(String, Vec<u8>)>>;
+
+#[derive(Default)]
+pub struct ClipboardCache {
+    /// `None` when `persist_clipboard` is off.
+    settings: Option<PersistClipboard>,
+    /// Bumped by every new selection, reads of an older one are dropped.
+    generation: u64,
+    /// The client whose selection is being kept, until the compositor takes it over.
+    owner: Option<ClientId>,
+    reads: Vec<RegistrationToken>,
+    /// The types read completely so far.
+    contents: Vec<(String, Vec<u8>)>,
+}
+
+impl ClipboardCache {
+    pub fn new(settings: Option<PersistClipboard>) -> Self {
+        Self { settings, ..Self::default() }
+    }
+}
+
+impl Smallvil {
+    /// Applies `persist_clipboard` after a reload. Turning it off drops the copy, a
+    /// selection the compositor already offers stays.
+    pub fn set_persist_clipboard(&mut self, settings: Option<PersistClipboard>) {
+        if settings.is_none() {
+            self.forget_clipboard();
+        }
+        self.clipboard.settings = settings;
+    }
+
+    /// Starts keeping the clipboard `source` a client just set, `None` when it cleared it.
+    pub fn clipboard_selection_changed(&mut self, source: Option<&SelectionSource>) {
+        self.forget_clipboard();
+        let (Some(settings), Some(source)) = (&self.clipboard.settings, source) else {
+            return;
+        };
+
+        let mime_types: Vec<String> =
+            source.mime_types().into_iter().filter(|mime_type| settings.wants(mime_type)).collect();
+        if mime_types.is_empty() {
+            return;
+        }
+
+        // Only the focused client can set the selection.
+        self.clipboard.owner = self
+            .seat
+            .keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .and_then(|surface| self.display_handle.get_client(surface.id()).ok())
+            .map(|client| client.id());
+
+        // The seat takes the new selection once this returns, it is read from there.
+        let generation = self.clipboard.generation;
+        self.loop_handle.insert_idle(move |state| state.read_clipboard(generation, mime_types));
+    }
+
+    /// Drops the copy and the reads still going.
+    fn forget_clipboard(&mut self) {
+        self.clipboard.generation += 1;
+        self.clipboard.owner = None;
+        self.clipboard.contents.clear();
+        for token in self.clipboard.reads.drain(..) {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    fn read_clipboard(&mut self, generation: u64, mime_types: Vec<String>) {
+        let Some(max_size) = self.clipboard.settings.as_ref().map(|settings| settings.max_size)
+        else {
+            return;
+        };
+        if generation != self.clipboard.generation {
+            return;
+        }
+
+        for mime_type in mime_types {
+            let pipe = pipe_with(PipeFlags::CLOEXEC)
+                .and_then(|(read, write)| io::ioctl_fionbio(&read, true).map(|()| (read, write)));
+            let (read, write) = match pipe {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    tracing::warn!("Failed to create a pipe for the clipboard: {err}");
+                    return;
+                }
+            };
+            if let Err(err) =
+                request_data_device_client_selection(&self.seat.seat, mime_type.clone(), write)
+            {
+                tracing::debug!("Failed to read the clipboard as {mime_type}: {err}");
+                continue;
+            }
+
+            let mut data = Vec::new();
+            let source = Generic::new(read, Interest::READ, Mode::Level);
+            let inserted = self.loop_handle.insert_source(source, move |_, fd, state| {
+                let mut buffer = [0; 64 * 1024];
+                match io::read(&**fd, &mut buffer) {
+                    Ok(0) => {
+                        if state.clipboard.generation == generation {
+                            state
+                                .clipboard
+                                .contents
+                                .push((mime_type.clone(), std::mem::take(&mut data)));
+                        }
+                        Ok(PostAction::Remove)
+                    }
+                    Ok(read) => {
+                        data.extend_from_slice(buffer.get(..read).unwrap_or_default());
+                        if data.len() > max_size {
+                            tracing::debug!("Not keeping the clipboard as {mime_type}, too large");
+                            return Ok(PostAction::Remove);
+                        }
+                        Ok(PostAction::Continue)
+                    }
+                    Err(Errno::AGAIN | Errno::INTR) => Ok(PostAction::Continue),
+                    Err(err) => {
+                        tracing::debug!("Failed to read the clipboard as {mime_type}: {err}");
+                        Ok(PostAction::Remove)
+                    }
+                }
+            });
+            match inserted {
+                Ok(token) => self.clipboard.reads.push(token),
+                Err(err) => tracing::warn!("Failed to read the clipboard: {err}"),
+            }
+        }
+    }
+
+    /// Checks, once the current dispatch is done, whether the client whose clipboard is
+    /// kept has exited. Called whenever a client might have, when focus moves or a window
+    /// closes.
+    pub fn schedule_clipboard_check(&self) {
+        if self.clipboard.owner.is_some() {
+            self.loop_handle.insert_idle(|state| state.restore_clipboard());
+        }
+    }
+
+    /// Offers the kept copy as the selection if its client is gone.
+    fn restore_clipboard(&mut self) {
+        let Some(owner) = self.clipboard.owner.clone() else {
+            return;
+        };
+        if self.display_handle.backend_handle().get_client_data(owner).is_ok() {
+            return;
+        }
+
+        let contents = std::mem::take(&mut self.clipboard.contents);
+        self.forget_clipboard();
+        if contents.is_empty() {
+            return;
+        }
+
+        let mime_types = contents.iter().map(|(mime_type, _)| mime_type.clone()).collect();
+        tracing::info!("Keeping the clipboard of a client that exited");
+        set_data_device_selection(
+            &self.display_handle,
+            &self.seat.seat,
+            mime_types,
+            Arc::new(contents),
+        );
+    }
+
+    /// Writes the kept clipboard as `mime_type` to `fd`, for a client pasting it.
+    pub fn send_clipboard(&mut self, mime_type: &str, fd: OwnedFd, contents: &ClipboardContents) {
+        let Some(index) = contents.iter().position(|(kept, _)| kept == mime_type) else {
+            return;
+        };
+        if let Err(err) = io::ioctl_fionbio(&fd, true) {
+            tracing::debug!("Failed to paste the clipboard: {err}");
+            return;
+        }
+
+        let contents = Arc::clone(contents);
+        let mut written = 0;
+        let source = Generic::new(fd, Interest::WRITE, Mode::Level);
+        let inserted = self.loop_handle.insert_source(source, move |_, fd, _| {
+            let data = contents.get(index).map(|(_, data)| data.as_slice()).unwrap_or_default();
+            match io::write(&**fd, data.get(written..).unwrap_or_default()) {
+                Ok(count) => {
+                    written += count;
+                    if count == 0 || written >= data.len() {
+                        return Ok(PostAction::Remove);
+                    }
+                    Ok(PostAction::Continue)
+                }
+                Err(Errno::AGAIN | Errno::INTR) => Ok(PostAction::Continue),
+                // The reader went away, it takes what it got.
+                Err(_) => Ok(PostAction::Remove),
+            }
+        });
+        if let Err(err) = inserted {
+            tracing::warn!("Failed to paste the clipboard: {err}");
+        }
+    }
+}
@@ -1,76 +1,470 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use image::{DynamicImage, imageops::FilterType};
 use serde::Deserialize;
-use smithay::{
-    backend::{
-        allocator::Fourcc,
-        renderer::{
-            ImportMem, Renderer,
-            element::{
-                Kind,
-                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
-            },
-        },
-    },
-    utils::{Physical, Size, Transform},
-};
+use smithay::utils::Transform;
 
 const DEFAULT_CONFIG: &str = r##"# ripwm configuration
 #
 # Set a wallpaper image:
 # wallpaper = ~/Pictures/Wallpaper.png
 #
+# Or a solid colour, no image involved:
+# wallpaper = "#202020"
+#
 # Or disable the wallpaper:
 # wallpaper = off
+#
+# `ripctl wallpaper` changes it, on one output or all, until the next reload. With
+# --save the wallpaper of all outputs is written here too.
 wallpaper = off
 
+# How the wallpaper image covers an output: "fill" scales and crops, "fit" scales
+# to fit inside and letterboxes, "center" draws it unscaled, "tile" repeats it
+wallpaper_mode = "fill"
+# Colour around the image with "fit" and "center", and behind transparent images
+wallpaper_background = "#000000"
+
+# How much to fade windows that don't have keyboard focus, from 0 (not at all) to 1
+inactive_dim = 0.0
+
+# Radius in pixels of window and border corners, 0 keeps them square
+corner_radius = 0
+
+# Width of window borders in pixels, 0 turns them off
+border_width = 2
+# Draw borders around windows instead of over their edges. Tiled windows shrink to
+# leave room for them
+border_outside = false
+
 # Border colors in #RRGGBB or #RRGGBBAA format
 active_border_color = "#4c7899"
 inactive_border_color = "#2f343a"
+# Border of windows whose client stopped answering pings
+unresponsive_border_color = "#cc3333"
+
+# Hide the pointer after this many seconds without pointer input, 0 never hides it
+cursor_timeout = 0
+# Hide the pointer on key presses until it moves again
+hide_cursor_on_typing = false
+# Draw the pointer into the nested window instead of using the host's cursor, like on
+# a tty. Same as --software-cursor, for checking cursor themes and client cursors
+software_cursor = false
+
+# Bits per colour channel of output framebuffers, 8 or 10. With 10, outputs that
+# support it render in a 10-bit format so gradients don't band, others stay at 8.
+# Only applies to outputs connected after a restart
+color_depth = 8
+
+# Turn the displays off after this many seconds without input, 0 keeps them on.
+# Clients inhibiting idleness, such as video players, keep them on too
+dpms_timeout = 0
+
+# Where Print (whole output) and Shift+Print (focused window) save screenshots.
+# Empty means $XDG_PICTURES_DIR, or ~/Pictures without it
+screenshot_dir = ""
 
 # Keyboard configuration
 keyboard_layout = "us"
 keyboard_variant = ""
+
+# Where a window torn off by dragging (e.g. a browser tab) ends up when dropped:
+# "float" keeps it at the drop location, "tile" hands it back to the tiler
+toplevel_drag_drop = "float"
+
+# What to do when a client asks to be maximized: "honour" lets the window cover its
+# output until it is unmaximized, "ignore" acknowledges the request and keeps the tile
+maximize = "honour"
+
+# Switching to the workspace that is already shown goes back to the previous one
+workspace_back_and_forth = false
+
+# Reload this file whenever it is saved, instead of waiting for `ripctl reload`
+auto_reload = false
+
+# Terminal started by Super+Return, a command line with arguments if needed
+terminal = "foot"
+# The first client, started unless ripwm was given -c. Empty starts the terminal,
+# "none" starts nothing
+startup_command = ""
+# Start the first client at all, false when everything comes from `exec`. Same as
+# ripwm --no-spawn
+spawn_terminal_on_start = true
+
+# Programs started once the session is up, as command lines with arguments. `exec`
+# entries run at startup only, `exec_always` ones on every reload too, whether or
+# not they are still running
+# exec = ["waybar", "mako --default-timeout 5000"]
+# exec_always = []
+
+# Also write the log to this file, $RIPWM_LOG overrides it. Empty logs to stderr
+# only. $RIPWM_LOG_FILTER sets what goes to the file like $RUST_LOG does for stderr.
+# Only read at startup
+log_file = ""
+# Size in megabytes at which the log file is rotated to ripwm.log.1 and so on
+log_file_size = 10
+# How many log files are kept, the current one included
+log_file_count = 3
+
+# Keep a copy of what is copied, so it can still be pasted once the program it was
+# copied from exits
+persist_clipboard = false
+# Types of data that are copied, "text/*" matches every text type
+persist_clipboard_types = ["text/*", "UTF8_STRING", "STRING", "TEXT", "image/png"]
+# Largest copy kept per type, in megabytes
+persist_clipboard_max_size = 16
+
+# Outputs are matched by connector name, or by "make model serial" to follow a
+# monitor across connectors, both as printed in the log when they are connected.
+# Outputs without a section use their preferred mode and are placed to the right
+# of the others.
+# [[output]]
+# name = "DP-1"
+# Width x height, optionally @ refresh rate in Hz
+# mode = "2560x1440@144"
+# Top left corner in the global layout, in logical pixels
+# position = "0,0"
+# scale = 1.5
+# Rotation in degrees counter-clockwise: "normal", "90", "180", "270", or
+# "flipped", "flipped-90", "flipped-180", "flipped-270" to mirror it first
+# transform = "90"
+# Leave the output off
+# enabled = false
+# Connectors marked non-desktop (VR headsets, some capture devices) are left
+# off, this turns them on anyway
+# force_enable = true
+
+# Environment variables for every program ripwm starts: the first client, the
+# terminal, exec entries and `ripctl exec`. $VAR and ${VAR} are replaced with ripwm's
+# own environment when the config is loaded. WAYLAND_DISPLAY, DISPLAY and
+# RIPWM_IPC_SOCKET are set by ripwm and can't be changed here
+# [env]
+# MOZ_ENABLE_WAYLAND = "1"
+# QT_QPA_PLATFORM = "wayland"
+# XCURSOR_SIZE = 24
+
+# Outputs that tablets and touchscreens cover, by device name as printed in the log
+# when they are added. Without an entry a touchscreen follows the output udev assigns
+# it, if any, and other devices cover all outputs
+# [map_to_output]
+# "Wacom Intuos S Pen" = "DP-2"
+
+# Window rules are matched against the client's app_id.
+# Opacity is multiplied with any opacity the client requests itself.
+# [[window_rule]]
+# app_id = "foot"
+# opacity = 0.9
 "##;
 
 #[derive(Debug, Clone)]
 pub enum WallpaperSetting {
     Off,
     Path(PathBuf),
+    Color([f32; 4]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallpaperMode {
+    Fill,
+    Fit,
+    Center,
+    Tile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    Eight,
+    Ten,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToplevelDropMode {
+    Float,
+    Tile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaximizeMode {
+    Honour,
+    Ignore,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowRule {
+    pub app_id: String,
+    pub opacity: Option<f32>,
+}
+
+/// A value the config loader rejected, with the key it was given for and what was used
+/// instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The `[[table]]` the key is in and its index among those, `None` at the top level.
+    pub table: Option<(&'static str, usize)>,
+    /// Empty for problems with the file as a whole, or with a whole table.
+    pub key: String,
+    /// 1-based line of the key in the file, when it could be found.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(key: &str, message: impl Into<String>) -> Self {
+        Self { table: None, key: key.to_string(), line: None, message: message.into() }
+    }
+
+    fn in_table(table: &'static str, index: usize, key: &str, message: impl Into<String>) -> Self {
+        Self { table: Some((table, index)), ..Self::new(key, message) }
+    }
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        match (self.table, self.key.is_empty()) {
+            (Some((table, index)), true) => write!(f, "{table}[{index}]: ")?,
+            (Some((table, index)), false) => write!(f, "{table}[{index}].{}: ", self.key)?,
+            (None, false) => write!(f, "{}: ", self.key)?,
+            (None, true) => {}
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A mode asked for by an `[[output]]` section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputMode {
+    pub width: u16,
+    pub height: u16,
+    /// In Hz. `None` picks the highest refresh rate at that size.
+    pub refresh: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    pub name: String,
+    pub enabled: bool,
+    pub mode: Option<OutputMode>,
+    pub position: Option<(i32, i32)>,
+    pub scale: Option<f64>,
+    pub transform: Option<Transform>,
+    /// Light the output up even though its connector is marked non-desktop.
+    pub force_enable: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct RipwmConfig {
     pub wallpaper: WallpaperSetting,
+    pub wallpaper_mode: WallpaperMode,
+    pub wallpaper_background: [f32; 4],
+    pub inactive_dim: f32,
+    pub corner_radius: i32,
+    pub border_width: i32,
+    pub border_outside: bool,
     pub active_border_color: [f32; 4],
     pub inactive_border_color: [f32; 4],
+    pub unresponsive_border_color: [f32; 4],
+    pub color_depth: ColorDepth,
+    pub screenshot_dir: Option<PathBuf>,
+    pub cursor_timeout: Option<Duration>,
+    pub hide_cursor_on_typing: bool,
+    pub software_cursor: bool,
+    pub workspace_back_and_forth: bool,
+    pub auto_reload: bool,
+    pub dpms_timeout: Option<Duration>,
     pub keyboard_layout: String,
     pub keyboard_variant: String,
+    pub window_rules: Vec<WindowRule>,
+    pub outputs: Vec<OutputConfig>,
+    pub toplevel_drag_drop: ToplevelDropMode,
+    pub maximize: MaximizeMode,
+    pub terminal: String,
+    /// `None` when `startup_command = "none"` or `spawn_terminal_on_start = false`.
+    pub startup_command: Option<String>,
+    pub exec: Vec<String>,
+    pub exec_always: Vec<String>,
+    /// Variables set for spawned programs, already expanded.
+    pub env: Vec<(String, String)>,
+    pub log_file: Option<LogFile>,
+    /// Output names of absolute pointer devices, by device name.
+    pub map_to_output: Vec<(String, String)>,
+    /// `None` when `persist_clipboard = false`.
+    pub persist_clipboard: Option<PersistClipboard>,
+}
+
+/// What of the clipboard is kept for when its client exits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistClipboard {
+    /// Mime types copied, `text/*` style patterns included.
+    pub mime_types: Vec<String>,
+    /// Largest copy kept per mime type, in bytes.
+    pub max_size: usize,
+}
+
+impl PersistClipboard {
+    pub fn wants(&self, mime_type: &str) -> bool {
+        self.mime_types.iter().any(|pattern| match pattern.strip_suffix("/*") {
+            Some(prefix) => {
+                mime_type.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+            }
+            None => pattern == mime_type,
+        })
+    }
+}
+
+/// Where the log is written besides stderr, and how it is rotated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// Size in bytes at which the file is rotated.
+    pub max_size: u64,
+    /// Number of files kept, the one being written included.
+    pub count: usize,
 }
 
 #[derive(Debug, Deserialize)]
 struct RawConfig {
     #[serde(default = "default_wallpaper")]
     wallpaper: String,
+    #[serde(default = "default_wallpaper_mode")]
+    wallpaper_mode: String,
+    #[serde(default = "default_wallpaper_background")]
+    wallpaper_background: String,
+    #[serde(default)]
+    inactive_dim: f32,
+    #[serde(default)]
+    corner_radius: i32,
+    #[serde(default = "default_border_width")]
+    border_width: i32,
+    #[serde(default)]
+    border_outside: bool,
     #[serde(default = "default_active_border_color")]
     active_border_color: String,
     #[serde(default = "default_inactive_border_color")]
     inactive_border_color: String,
+    #[serde(default = "default_unresponsive_border_color")]
+    unresponsive_border_color: String,
+    #[serde(default = "default_color_depth")]
+    color_depth: u32,
+    #[serde(default)]
+    screenshot_dir: String,
+    #[serde(default)]
+    cursor_timeout: u64,
+    #[serde(default)]
+    hide_cursor_on_typing: bool,
+    #[serde(default)]
+    software_cursor: bool,
+    #[serde(default)]
+    workspace_back_and_forth: bool,
+    #[serde(default)]
+    auto_reload: bool,
+    #[serde(default)]
+    dpms_timeout: u64,
     #[serde(default = "default_keyboard_layout")]
     keyboard_layout: String,
     #[serde(default = "default_keyboard_variant")]
     keyboard_variant: String,
+    #[serde(default)]
+    window_rule: Vec<RawWindowRule>,
+    #[serde(default)]
+    output: Vec<RawOutput>,
+    #[serde(default = "default_toplevel_drag_drop")]
+    toplevel_drag_drop: String,
+    #[serde(default = "default_maximize")]
+    maximize: String,
+    #[serde(default = "default_terminal")]
+    terminal: String,
+    #[serde(default)]
+    startup_command: String,
+    #[serde(default = "default_spawn_terminal_on_start")]
+    spawn_terminal_on_start: bool,
+    #[serde(default)]
+    exec: Vec<String>,
+    #[serde(default)]
+    exec_always: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    log_file: String,
+    #[serde(default = "default_log_file_size")]
+    log_file_size: u64,
+    #[serde(default = "default_log_file_count")]
+    log_file_count: usize,
+    #[serde(default)]
+    map_to_output: BTreeMap<String, String>,
+    #[serde(default)]
+    persist_clipboard: bool,
+    #[serde(default = "default_persist_clipboard_types")]
+    persist_clipboard_types: Vec<String>,
+    #[serde(default = "default_persist_clipboard_max_size")]
+    persist_clipboard_max_size: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawWindowRule {
+    app_id: String,
+    opacity: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawOutput {
+    name: String,
+    #[serde(default = "default_output_enabled")]
+    enabled: bool,
+    mode: Option<String>,
+    position: Option<String>,
+    scale: Option<f64>,
+    transform: Option<String>,
+    #[serde(default)]
+    force_enable: bool,
 }
 
 impl Default for RawConfig {
     fn default() -> Self {
         Self {
             wallpaper: default_wallpaper(),
+            wallpaper_mode: default_wallpaper_mode(),
+            wallpaper_background: default_wallpaper_background(),
+            inactive_dim: 0.0,
+            corner_radius: 0,
+            border_width: default_border_width(),
+            border_outside: false,
             active_border_color: default_active_border_color(),
             inactive_border_color: default_inactive_border_color(),
+            unresponsive_border_color: default_unresponsive_border_color(),
+            color_depth: default_color_depth(),
+            screenshot_dir: String::new(),
+            cursor_timeout: 0,
+            hide_cursor_on_typing: false,
+            software_cursor: false,
+            workspace_back_and_forth: false,
+            auto_reload: false,
+            dpms_timeout: 0,
             keyboard_layout: default_keyboard_layout(),
             keyboard_variant: default_keyboard_variant(),
+            window_rule: Vec::new(),
+            output: Vec::new(),
+            toplevel_drag_drop: default_toplevel_drag_drop(),
+            maximize: default_maximize(),
+            terminal: default_terminal(),
+            startup_command: String::new(),
+            spawn_terminal_on_start: default_spawn_terminal_on_start(),
+            exec: Vec::new(),
+            exec_always: Vec::new(),
+            env: BTreeMap::new(),
+            log_file: String::new(),
+            log_file_size: default_log_file_size(),
+            log_file_count: default_log_file_count(),
+            map_to_output: BTreeMap::new(),
+            persist_clipboard: false,
+            persist_clipboard_types: default_persist_clipboard_types(),
+            persist_clipboard_max_size: default_persist_clipboard_max_size(),
         }
     }
 }
@@ -79,6 +473,18 @@ fn default_wallpaper() -> String {
     String::from("off")
 }
 
+fn default_wallpaper_mode() -> String {
+    String::from("fill")
+}
+
+fn default_wallpaper_background() -> String {
+    String::from("#000000")
+}
+
+fn default_border_width() -> i32 {
+    2
+}
+
 fn default_active_border_color() -> String {
     String::from("#4c7899")
 }
@@ -87,6 +493,14 @@ fn default_inactive_border_color() -> String {
     String::from("#2f343a")
 }
 
+fn default_unresponsive_border_color() -> String {
+    String::from("#cc3333")
+}
+
+fn default_color_depth() -> u32 {
+    8
+}
+
 fn default_keyboard_layout() -> String {
     String::from("us")
 }
@@ -95,52 +509,304 @@ fn default_keyboard_variant() -> String {
     String::from("")
 }
 
-pub fn load_or_create_config() -> RipwmConfig {
-    let config_path = config_path();
+fn default_terminal() -> String {
+    String::from("foot")
+}
+
+fn default_log_file_size() -> u64 {
+    10
+}
+
+fn default_log_file_count() -> usize {
+    3
+}
+
+fn default_persist_clipboard_types() -> Vec<String> {
+    ["text/*", "UTF8_STRING", "STRING", "TEXT", "image/png"].map(String::from).to_vec()
+}
+
+fn default_persist_clipboard_max_size() -> usize {
+    16
+}
+
+fn default_spawn_terminal_on_start() -> bool {
+    true
+}
+
+fn default_output_enabled() -> bool {
+    true
+}
+
+fn default_toplevel_drag_drop() -> String {
+    String::from("float")
+}
 
-    if let Some(parent) = config_path.parent()
+fn default_maximize() -> String {
+    String::from("honour")
+}
+
+/// Loads the config file, writing the default one first if there is none. A file that
+/// can't be read or parsed gives the defaults.
+pub fn load_or_create_config(config_path: &Path) -> RipwmConfig {
+    match load_config(config_path) {
+        Ok((config, problems)) => {
+            log_problems(config_path, &problems);
+            config
+        }
+        Err(problem) => {
+            tracing::warn!("Invalid config at {}: {problem}", config_path.display());
+            parse_config(RawConfig::default(), &mut Vec::new())
+        }
+    }
+}
+
+/// Warns about each of `problems`, found in the config at `config_path`.
+pub fn log_problems(config_path: &Path, problems: &[ConfigProblem]) {
+    for problem in problems {
+        tracing::warn!("{}: {problem}", config_path.display());
+    }
+}
+
+/// Loads the config file, writing the default one first if there is none. Invalid values
+/// fall back to their defaults and are returned as problems, only a file that can't be
+/// read or parsed is an error.
+pub fn load_config(config_path: &Path) -> Result<(RipwmConfig, Vec<ConfigProblem>), ConfigProblem> {
+    // Only the default location gets its directory created, a path given with --config
+    // or $RIPWM_CONFIG is written to if its directory is already there.
+    if config_path == default_config_path()
+        && let Some(parent) = config_path.parent()
         && let Err(err) = fs::create_dir_all(parent)
     {
         tracing::warn!("Failed to create config directory {}: {err}", parent.display());
     }
 
     if !config_path.exists()
-        && let Err(err) = fs::write(&config_path, DEFAULT_CONFIG)
+        && config_path
+            .parent()
+            .is_none_or(|parent| parent.as_os_str().is_empty() || parent.is_dir())
+        && let Err(err) = fs::write(config_path, DEFAULT_CONFIG)
     {
         tracing::warn!("Failed to write default config {}: {err}", config_path.display());
     }
 
-    let raw = match fs::read_to_string(&config_path) {
-        Ok(contents) => {
-            let normalized = normalize_wallpaper_values(&contents);
-            toml::from_str::<RawConfig>(&normalized).unwrap_or_else(|err| {
-                tracing::warn!("Invalid config at {}: {err}", config_path.display());
-                RawConfig::default()
-            })
-        }
-        Err(err) => {
-            tracing::warn!("Failed to read config {}: {err}", config_path.display());
-            RawConfig::default()
+    read_config(config_path)
+}
+
+/// The log file settings, before logging is set up. Problems with the config are left
+/// for the full load to report.
+pub fn log_file(config_path: &Path) -> Option<LogFile> {
+    let raw = fs::read_to_string(config_path)
+        .ok()
+        .and_then(|contents| toml::from_str(&normalize_wallpaper_values(&contents)).ok())
+        .unwrap_or_default();
+    parse_log_file(&raw, &mut Vec::new())
+}
+
+/// Reads and parses the config file without writing anything.
+fn read_config(config_path: &Path) -> Result<(RipwmConfig, Vec<ConfigProblem>), ConfigProblem> {
+    let contents = fs::read_to_string(config_path)
+        .map_err(|err| ConfigProblem::new("", format!("Failed to read the file: {err}")))?;
+    let normalized = normalize_wallpaper_values(&contents);
+    let raw = toml::from_str::<RawConfig>(&normalized).map_err(|err| {
+        let line = err
+            .span()
+            .and_then(|span| normalized.get(..span.start))
+            .map(|before| before.matches('\n').count() + 1);
+        ConfigProblem { line, ..ConfigProblem::new("", err.message()) }
+    })?;
+
+    let mut problems = Vec::new();
+    let config = parse_config(raw, &mut problems);
+    for problem in &mut problems {
+        problem.line = find_line(&contents, problem.table, &problem.key);
+    }
+    Ok((config, problems))
+}
+
+/// Loads the config at `config_path` like a start would and checks what is only known
+/// when it is used: that the wallpaper image can be decoded, xkb knows the keyboard
+/// layout and command lines can be split. Returns every problem found.
+pub fn check_config(config_path: &Path) -> Vec<ConfigProblem> {
+    let (config, mut problems) = match read_config(config_path) {
+        Ok(loaded) => loaded,
+        Err(problem) => return vec![problem],
+    };
+    let contents = fs::read_to_string(config_path).unwrap_or_default();
+    let mut found = |key: &str, message: String| {
+        let line = find_line(&contents, None, key);
+        problems.push(ConfigProblem { line, ..ConfigProblem::new(key, message) });
+    };
+
+    if let WallpaperSetting::Path(path) = &config.wallpaper
+        && let Err(err) = crate::wallpaper::check_image(path)
+    {
+        found("wallpaper", err);
+    }
+
+    let xkb_config = smithay::input::keyboard::XkbConfig {
+        layout: &config.keyboard_layout,
+        variant: &config.keyboard_variant,
+        ..Default::default()
+    };
+    if let Err(err) = crate::seat::validate_keymap(&xkb_config) {
+        let key =
+            if config.keyboard_variant.is_empty() { "keyboard_layout" } else { "keyboard_variant" };
+        found(key, err);
+    }
+
+    let mut commands = vec![("terminal", &config.terminal)];
+    commands.extend(config.startup_command.iter().map(|command| ("startup_command", command)));
+    commands.extend(config.exec.iter().map(|command| ("exec", command)));
+    commands.extend(config.exec_always.iter().map(|command| ("exec_always", command)));
+    for (key, command) in commands {
+        if let Err(err) = crate::spawn::split_command(command) {
+            found(key, format!("Invalid command line `{command}`: {err}"));
         }
+    }
+
+    problems
+}
+
+/// The 1-based line that sets `key`, in the `[[table]]` at that index or at the top
+/// level. Keys of a plain `[table]` are given as `table.key`. With an empty key, the line
+/// of the `[[table]]` header.
+fn find_line(contents: &str, table: Option<(&str, usize)>, key: &str) -> Option<usize> {
+    let (plain_table, key) = match (table, key.split_once('.')) {
+        (None, Some((plain_table, key))) => (Some(plain_table), key),
+        _ => (None, key),
     };
+    let mut current: Option<(&str, usize)> = None;
+    let mut plain: Option<&str> = None;
+    let mut seen: Vec<(&str, usize)> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("[[").and_then(|rest| rest.strip_suffix("]]")) {
+            let name = name.trim();
+            let count = match seen.iter_mut().find(|(seen, _)| *seen == name) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count
+                }
+                None => {
+                    seen.push((name, 0));
+                    0
+                }
+            };
+            current = Some((name, count));
+            plain = None;
+            if key.is_empty() && current == table {
+                return Some(index + 1);
+            }
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            current = None;
+            plain = Some(name.trim());
+            continue;
+        }
+        if !key.is_empty()
+            && current == table
+            && plain == plain_table
+            && line_key(line) == Some(key)
+        {
+            return Some(index + 1);
+        }
+    }
+    None
+}
 
-    let wallpaper = if raw.wallpaper.trim().eq_ignore_ascii_case("off") {
+fn parse_config(raw: RawConfig, problems: &mut Vec<ConfigProblem>) -> RipwmConfig {
+    let wallpaper = parse_wallpaper(&raw.wallpaper).unwrap_or_else(|err| {
+        problems.push(ConfigProblem::new("wallpaper", format!("{err}. Disabling the wallpaper")));
         WallpaperSetting::Off
+    });
+
+    let wallpaper_mode = match raw.wallpaper_mode.trim() {
+        value if value.eq_ignore_ascii_case("fill") => WallpaperMode::Fill,
+        value if value.eq_ignore_ascii_case("fit") => WallpaperMode::Fit,
+        value if value.eq_ignore_ascii_case("center") || value.eq_ignore_ascii_case("centre") => {
+            WallpaperMode::Center
+        }
+        value if value.eq_ignore_ascii_case("tile") => WallpaperMode::Tile,
+        value => {
+            problems.push(ConfigProblem::new(
+                "wallpaper_mode",
+                format!("Invalid value: {value}. Falling back to fill"),
+            ));
+            WallpaperMode::Fill
+        }
+    };
+    let wallpaper_background = parse_color_or_default(
+        raw.wallpaper_background.trim(),
+        [0.0, 0.0, 0.0, 1.0],
+        "wallpaper_background",
+        problems,
+    );
+
+    let inactive_dim = if raw.inactive_dim.is_finite() && (0.0..=1.0).contains(&raw.inactive_dim) {
+        raw.inactive_dim
+    } else {
+        problems.push(ConfigProblem::new(
+            "inactive_dim",
+            format!("Invalid value: {}. Falling back to 0", raw.inactive_dim),
+        ));
+        0.0
+    };
+
+    let corner_radius = if raw.corner_radius >= 0 {
+        raw.corner_radius
     } else {
-        WallpaperSetting::Path(expand_home(raw.wallpaper.trim()))
+        problems.push(ConfigProblem::new(
+            "corner_radius",
+            format!("Invalid value: {}. Falling back to 0", raw.corner_radius),
+        ));
+        0
+    };
+
+    let border_width = if raw.border_width >= 0 {
+        raw.border_width
+    } else {
+        problems.push(ConfigProblem::new(
+            "border_width",
+            format!("Invalid value: {}. Falling back to 2", raw.border_width),
+        ));
+        default_border_width()
     };
 
     let active_border_color = parse_color_or_default(
         raw.active_border_color.trim(),
         [0.298_039_23, 0.470_588_24, 0.6, 1.0],
         "active_border_color",
+        problems,
     );
     let inactive_border_color = parse_color_or_default(
         raw.inactive_border_color.trim(),
         [0.184_313_73, 0.203_921_57, 0.227_450_98, 1.0],
         "inactive_border_color",
+        problems,
+    );
+    let unresponsive_border_color = parse_color_or_default(
+        raw.unresponsive_border_color.trim(),
+        [0.8, 0.2, 0.2, 1.0],
+        "unresponsive_border_color",
+        problems,
     );
 
+    let color_depth = match raw.color_depth {
+        8 => ColorDepth::Eight,
+        10 => ColorDepth::Ten,
+        value => {
+            problems.push(ConfigProblem::new(
+                "color_depth",
+                format!("Invalid value: {value}. Falling back to 8"),
+            ));
+            ColorDepth::Eight
+        }
+    };
+
+    let screenshot_dir = raw.screenshot_dir.trim();
+    let screenshot_dir = (!screenshot_dir.is_empty()).then(|| expand_home(screenshot_dir));
+
     let keyboard_layout = raw.keyboard_layout.trim();
     let keyboard_layout = if keyboard_layout.is_empty() {
         default_keyboard_layout()
@@ -148,22 +814,558 @@ pub fn load_or_create_config() -> RipwmConfig {
         keyboard_layout.to_string()
     };
 
+    let window_rules = raw
+        .window_rule
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, rule)| {
+            let app_id = rule.app_id.trim();
+            if app_id.is_empty() {
+                problems.push(ConfigProblem::in_table(
+                    "window_rule",
+                    index,
+                    "app_id",
+                    "Empty app_id. Ignoring the rule",
+                ));
+                return None;
+            }
+
+            let opacity = rule.opacity.and_then(|opacity| {
+                if opacity.is_finite() && (0.0..=1.0).contains(&opacity) {
+                    Some(opacity)
+                } else {
+                    problems.push(ConfigProblem::in_table(
+                        "window_rule",
+                        index,
+                        "opacity",
+                        format!("Invalid value: {opacity}. Ignoring it"),
+                    ));
+                    None
+                }
+            });
+
+            Some(WindowRule { app_id: app_id.to_string(), opacity })
+        })
+        .collect();
+
+    let outputs = raw
+        .output
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, output)| parse_output(output, index, problems))
+        .collect();
+
+    let toplevel_drag_drop = match raw.toplevel_drag_drop.trim() {
+        value if value.eq_ignore_ascii_case("float") => ToplevelDropMode::Float,
+        value if value.eq_ignore_ascii_case("tile") => ToplevelDropMode::Tile,
+        value => {
+            problems.push(ConfigProblem::new(
+                "toplevel_drag_drop",
+                format!("Invalid value: {value}. Falling back to float"),
+            ));
+            ToplevelDropMode::Float
+        }
+    };
+
+    let maximize = match raw.maximize.trim() {
+        value if value.eq_ignore_ascii_case("honour") || value.eq_ignore_ascii_case("honor") => {
+            MaximizeMode::Honour
+        }
+        value if value.eq_ignore_ascii_case("ignore") => MaximizeMode::Ignore,
+        value => {
+            problems.push(ConfigProblem::new(
+                "maximize",
+                format!("Invalid value: {value}. Falling back to honour"),
+            ));
+            MaximizeMode::Honour
+        }
+    };
+
+    let terminal = raw.terminal.trim();
+    let terminal = if terminal.is_empty() {
+        problems.push(ConfigProblem::new("terminal", "Empty value. Falling back to foot"));
+        default_terminal()
+    } else {
+        terminal.to_string()
+    };
+    let startup_command = match raw.startup_command.trim() {
+        _ if !raw.spawn_terminal_on_start => None,
+        "" => Some(terminal.clone()),
+        value if value.eq_ignore_ascii_case("none") => None,
+        value => Some(value.to_string()),
+    };
+
+    let exec = parse_exec(raw.exec, "exec", problems);
+    let exec_always = parse_exec(raw.exec_always, "exec_always", problems);
+    let env = parse_env(raw.env, problems);
+    let log_file = parse_log_file(&raw, problems);
+    let map_to_output = parse_map_to_output(raw.map_to_output, problems);
+    let persist_clipboard = parse_persist_clipboard(&raw, problems);
+
     RipwmConfig {
         wallpaper,
+        wallpaper_mode,
+        wallpaper_background,
+        inactive_dim,
+        corner_radius,
+        border_width,
+        border_outside: raw.border_outside,
         active_border_color,
         inactive_border_color,
+        unresponsive_border_color,
+        color_depth,
+        screenshot_dir,
+        cursor_timeout: (raw.cursor_timeout > 0).then(|| Duration::from_secs(raw.cursor_timeout)),
+        hide_cursor_on_typing: raw.hide_cursor_on_typing,
+        software_cursor: raw.software_cursor,
+        workspace_back_and_forth: raw.workspace_back_and_forth,
+        auto_reload: raw.auto_reload,
+        dpms_timeout: (raw.dpms_timeout > 0).then(|| Duration::from_secs(raw.dpms_timeout)),
         keyboard_layout,
         keyboard_variant: raw.keyboard_variant.trim().to_string(),
+        window_rules,
+        outputs,
+        toplevel_drag_drop,
+        maximize,
+        terminal,
+        startup_command,
+        exec,
+        exec_always,
+        env,
+        log_file,
+        map_to_output,
+        persist_clipboard,
     }
 }
 
-pub(crate) fn config_path() -> PathBuf {
+fn parse_persist_clipboard(
+    raw: &RawConfig,
+    problems: &mut Vec<ConfigProblem>,
+) -> Option<PersistClipboard> {
+    if !raw.persist_clipboard {
+        return None;
+    }
+
+    let mime_types: Vec<String> = raw
+        .persist_clipboard_types
+        .iter()
+        .map(|mime_type| mime_type.trim().to_string())
+        .filter(|mime_type| !mime_type.is_empty())
+        .collect();
+    if mime_types.is_empty() {
+        problems.push(ConfigProblem::new(
+            "persist_clipboard_types",
+            "No types given, nothing would be kept",
+        ));
+    }
+    let max_size = if raw.persist_clipboard_max_size > 0 {
+        raw.persist_clipboard_max_size
+    } else {
+        problems.push(ConfigProblem::new(
+            "persist_clipboard_max_size",
+            "Invalid value: 0. Falling back to 16",
+        ));
+        default_persist_clipboard_max_size()
+    };
+
+    Some(PersistClipboard { mime_types, max_size: max_size.saturating_mul(1024 * 1024) })
+}
+
+fn parse_map_to_output(
+    raw: BTreeMap<String, String>,
+    problems: &mut Vec<ConfigProblem>,
+) -> Vec<(String, String)> {
+    raw.into_iter()
+        .filter_map(|(device, output)| {
+            let output = output.trim();
+            if output.is_empty() {
+                problems.push(ConfigProblem::new(
+                    &format!("map_to_output.{device}"),
+                    "Empty output name. Ignoring it",
+                ));
+                return None;
+            }
+            Some((device, output.to_string()))
+        })
+        .collect()
+}
+
+/// `$RIPWM_LOG`, or else `log_file`, with the rotation settings of the config.
+fn parse_log_file(raw: &RawConfig, problems: &mut Vec<ConfigProblem>) -> Option<LogFile> {
+    let path = std::env::var("RIPWM_LOG")
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .unwrap_or_else(|| raw.log_file.clone());
+    let path = path.trim();
+    if path.is_empty() {
+        return None;
+    }
+
+    let max_size = if raw.log_file_size > 0 {
+        raw.log_file_size
+    } else {
+        problems.push(ConfigProblem::new("log_file_size", "Invalid value: 0. Falling back to 10"));
+        default_log_file_size()
+    };
+    let count = if raw.log_file_count > 0 {
+        raw.log_file_count
+    } else {
+        problems.push(ConfigProblem::new("log_file_count", "Invalid value: 0. Falling back to 3"));
+        default_log_file_count()
+    };
+
+    Some(LogFile { path: expand_home(path), max_size: max_size.saturating_mul(1024 * 1024), count })
+}
+
+/// Keeps the entries of `exec` or `exec_always` that name something to run.
+fn parse_exec(entries: Vec<String>, key: &str, problems: &mut Vec<ConfigProblem>) -> Vec<String> {
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                problems.push(ConfigProblem::new(key, "Empty entry. Ignoring it"));
+                return None;
+            }
+            Some(entry.to_string())
+        })
+        .collect()
+}
+
+/// Variables ripwm sets for the programs it starts itself.
+const MANAGED_ENV: [&str; 3] = ["WAYLAND_DISPLAY", "DISPLAY", "RIPWM_IPC_SOCKET"];
+
+/// Turns the `[env]` table into variables, with strings expanded and numbers and booleans
+/// written out.
+fn parse_env(
+    raw: BTreeMap<String, toml::Value>,
+    problems: &mut Vec<ConfigProblem>,
+) -> Vec<(String, String)> {
+    raw.into_iter()
+        .filter_map(|(name, value)| {
+            let mut problem = |message: &str| {
+                problems.push(ConfigProblem::new(&format!("env.{name}"), message));
+            };
+            if name.is_empty() || name.contains(['=', '\0']) {
+                problem("Invalid variable name. Ignoring it");
+                return None;
+            }
+            if MANAGED_ENV.contains(&name.as_str()) {
+                problem("Set by ripwm itself. Ignoring it");
+                return None;
+            }
+            let value = match value {
+                toml::Value::String(value) => expand_env(&value),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => {
+                    problem("Expected a string, number or boolean. Ignoring it");
+                    return None;
+                }
+            };
+            if value.contains('\0') {
+                problem("Value contains a NUL byte. Ignoring it");
+                return None;
+            }
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Replaces `$VAR` and `${VAR}` in `raw` with the variable from ripwm's environment,
+/// nothing if it isn't set. `$$` is a literal `$`.
+fn expand_env(raw: &str) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut expanded = String::new();
+    let mut rest = raw;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(rest.get(..start).unwrap_or_default());
+        let after = rest.get(start + 1..).unwrap_or_default();
+
+        let (name, remaining) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, remaining)) => (name, remaining),
+                None => ("", after),
+            }
+        } else if let Some(remaining) = after.strip_prefix('$') {
+            expanded.push('$');
+            rest = remaining;
+            continue;
+        } else {
+            let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+            (after.get(..end).unwrap_or_default(), after.get(end..).unwrap_or_default())
+        };
+
+        if name.is_empty() {
+            // Not a variable, the `$` stays.
+            expanded.push('$');
+        } else if let Some(value) = std::env::var_os(name) {
+            expanded.push_str(&value.to_string_lossy());
+        }
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+pub fn window_opacity(rules: &[WindowRule], app_id: Option<&str>) -> f32 {
+    let Some(app_id) = app_id else {
+        return 1.0;
+    };
+
+    rules.iter().filter(|rule| rule.app_id == app_id).find_map(|rule| rule.opacity).unwrap_or(1.0)
+}
+
+/// The `[[output]]` section for the connector `name`, or for the monitor `identifier`
+/// read from its EDID, if there is one.
+pub fn output_config<'a>(
+    outputs: &'a [OutputConfig],
+    name: &str,
+    identifier: Option<&str>,
+) -> Option<&'a OutputConfig> {
+    outputs.iter().find(|output| output.name == name || Some(output.name.as_str()) == identifier)
+}
+
+fn parse_output(
+    raw: RawOutput,
+    index: usize,
+    problems: &mut Vec<ConfigProblem>,
+) -> Option<OutputConfig> {
+    let mut problem = |key: &str, message: String| {
+        problems.push(ConfigProblem::in_table("output", index, key, message));
+    };
+
+    let name = raw.name.trim();
+    if name.is_empty() {
+        problem("name", "Empty name. Ignoring the section".into());
+        return None;
+    }
+
+    let mode = raw.mode.as_deref().map(str::trim).and_then(|mode| {
+        let parsed = parse_output_mode(mode);
+        if parsed.is_none() {
+            problem("mode", format!("Invalid value: {mode}. Using the preferred mode"));
+        }
+        parsed
+    });
+
+    let position = raw.position.as_deref().map(str::trim).and_then(|position| {
+        let parsed = position.split_once(',').and_then(|(x, y)| {
+            Some((x.trim().parse::<i32>().ok()?, y.trim().parse::<i32>().ok()?))
+        });
+        if parsed.is_none() {
+            problem("position", format!("Invalid value: {position}. Placing it automatically"));
+        }
+        parsed
+    });
+
+    let scale = raw.scale.filter(|scale| {
+        let valid = scale.is_finite() && (0.25..=10.0).contains(scale);
+        if !valid {
+            problem("scale", format!("Invalid value: {scale}. Falling back to 1"));
+        }
+        valid
+    });
+
+    let transform = raw.transform.as_deref().map(str::trim).and_then(|transform| {
+        let parsed = parse_transform(transform);
+        if parsed.is_none() {
+            problem("transform", format!("Invalid value: {transform}. Falling back to normal"));
+        }
+        parsed
+    });
+
+    Some(OutputConfig {
+        name: name.to_string(),
+        enabled: raw.enabled,
+        mode,
+        position,
+        scale,
+        transform,
+        force_enable: raw.force_enable,
+    })
+}
+
+/// Parses `WIDTHxHEIGHT` or `WIDTHxHEIGHT@REFRESH`.
+pub fn parse_output_mode(raw: &str) -> Option<OutputMode> {
+    let (size, refresh) = match raw.split_once('@') {
+        Some((size, refresh)) => {
+            let refresh = refresh.trim().trim_end_matches("Hz").parse::<f64>().ok()?;
+            if !refresh.is_finite() || refresh <= 0.0 {
+                return None;
+            }
+            (size, Some(refresh))
+        }
+        None => (raw, None),
+    };
+    let (width, height) = size.split_once('x')?;
+    Some(OutputMode {
+        width: width.trim().parse().ok().filter(|width| *width > 0)?,
+        height: height.trim().parse().ok().filter(|height| *height > 0)?,
+        refresh,
+    })
+}
+
+pub fn parse_transform(raw: &str) -> Option<Transform> {
+    let transform = match raw.to_ascii_lowercase().as_str() {
+        "normal" | "0" => Transform::Normal,
+        "90" => Transform::_90,
+        "180" => Transform::_180,
+        "270" => Transform::_270,
+        "flipped" => Transform::Flipped,
+        "flipped-90" => Transform::Flipped90,
+        "flipped-180" => Transform::Flipped180,
+        "flipped-270" => Transform::Flipped270,
+        _ => return None,
+    };
+    Some(transform)
+}
+
+/// The name `transform` has in `[[output]]` sections.
+pub fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+    }
+}
+
+/// `~/.config/ripwm/ripwm.toml`, used without --config or `$RIPWM_CONFIG`.
+pub(crate) fn default_config_path() -> PathBuf {
     match std::env::var_os("HOME") {
         Some(home) => PathBuf::from(home).join(".config/ripwm/ripwm.toml"),
         None => PathBuf::from(".config/ripwm/ripwm.toml"),
     }
 }
 
+/// Parses a `wallpaper` value: `off`, a `#RRGGBB` color or the path of an image.
+pub fn parse_wallpaper(raw: &str) -> Result<WallpaperSetting, String> {
+    let wallpaper = raw.trim();
+    if wallpaper.eq_ignore_ascii_case("off") {
+        Ok(WallpaperSetting::Off)
+    } else if wallpaper.starts_with('#') {
+        parse_hex_color(wallpaper)
+            .map(WallpaperSetting::Color)
+            .ok_or_else(|| format!("Invalid wallpaper color: {wallpaper}"))
+    } else {
+        Ok(WallpaperSetting::Path(expand_home(wallpaper)))
+    }
+}
+
+/// Writes `wallpaper = value` into the config file, replacing the top-level `wallpaper`
+/// line if there is one. The rest of the file is left as it is.
+pub fn save_wallpaper(config_path: &Path, value: &str) -> Result<(), String> {
+    let mut lines = read_config_lines(config_path)?;
+    let setting = format!("wallpaper = {}", toml::Value::String(value.trim().to_string()));
+    // Keys after the first table header belong to that table.
+    let top_level =
+        lines.iter().position(|line| line.trim_start().starts_with('[')).unwrap_or(lines.len());
+    match lines.iter().take(top_level).position(|line| line_key(line) == Some("wallpaper")) {
+        Some(index) => {
+            if let Some(line) = lines.get_mut(index) {
+                *line = setting;
+            }
+        }
+        None => lines.insert(0, setting),
+    }
+    write_config_lines(config_path, &lines)
+}
+
+/// Writes `settings` into the `[[output]]` section named `name`, adding the section if
+/// there is none. Other keys and sections are left as they are.
+pub fn save_output(
+    config_path: &Path,
+    name: &str,
+    settings: &[(&str, toml::Value)],
+) -> Result<(), String> {
+    let mut lines = read_config_lines(config_path)?;
+    let is_header = |line: &String| line.trim_start().starts_with('[');
+
+    let mut section = None;
+    let mut index = 0;
+    while let Some(line) = lines.get(index) {
+        index += 1;
+        if line.trim() != "[[output]]" {
+            continue;
+        }
+        let end = lines.iter().skip(index).position(is_header).map_or(lines.len(), |n| index + n);
+        let named = (index..end).find(|line| {
+            lines.get(*line).is_some_and(|line| {
+                toml::from_str::<toml::Table>(line).ok().is_some_and(|table| {
+                    table.get("name").and_then(toml::Value::as_str) == Some(name)
+                })
+            })
+        });
+        if let Some(named) = named {
+            section = Some((named, end));
+            break;
+        }
+    }
+
+    match section {
+        Some((named, mut end)) => {
+            for (key, value) in settings {
+                let setting = format!("{key} = {value}");
+                let existing = (named..end)
+                    .find(|line| lines.get(*line).and_then(|line| line_key(line)) == Some(*key));
+                match existing.and_then(|line| lines.get_mut(line)) {
+                    Some(line) => *line = setting,
+                    None => {
+                        lines.insert(named + 1, setting);
+                        end += 1;
+                    }
+                }
+            }
+        }
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[[output]]".into());
+            lines.push(format!("name = {}", toml::Value::String(name.to_string())));
+            lines.extend(settings.iter().map(|(key, value)| format!("{key} = {value}")));
+        }
+    }
+    write_config_lines(config_path, &lines)
+}
+
+/// The config file as lines, or the default config if there is no file yet.
+fn read_config_lines(config_path: &Path) -> Result<Vec<String>, String> {
+    let contents = match fs::read_to_string(config_path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => DEFAULT_CONFIG.to_string(),
+        Err(err) => {
+            return Err(format!("Failed to read config {}: {err}", config_path.display()));
+        }
+    };
+    Ok(contents.lines().map(str::to_string).collect())
+}
+
+fn write_config_lines(config_path: &Path, lines: &[String]) -> Result<(), String> {
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).map_err(|err| {
+            format!("Failed to create config directory {}: {err}", parent.display())
+        })?;
+    }
+    fs::write(config_path, contents)
+        .map_err(|err| format!("Failed to write config {}: {err}", config_path.display()))
+}
+
+/// The key a `key = value` line sets, `None` for comments and other lines.
+fn line_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    let key = key.trim().trim_matches('"');
+    (!key.is_empty() && !key.starts_with('#')).then_some(key)
+}
+
 fn normalize_wallpaper_values(contents: &str) -> String {
     contents
         .lines()
@@ -208,18 +1410,30 @@ fn expand_home(raw: &str) -> PathBuf {
     PathBuf::from(raw)
 }
 
-fn parse_color_or_default(raw: &str, default: [f32; 4], key: &str) -> [f32; 4] {
+fn parse_color_or_default(
+    raw: &str,
+    default: [f32; 4],
+    key: &str,
+    problems: &mut Vec<ConfigProblem>,
+) -> [f32; 4] {
     match parse_hex_color(raw) {
         Some(color) => color,
         None => {
-            tracing::warn!("Invalid color for {key}: {raw}. Falling back to default");
+            problems.push(ConfigProblem::new(
+                key,
+                format!("Invalid color: {raw}. Falling back to the default"),
+            ));
             default
         }
     }
 }
 
-fn parse_hex_color(raw: &str) -> Option<[f32; 4]> {
+pub fn parse_hex_color(raw: &str) -> Option<[f32; 4]> {
     let value = raw.strip_prefix('#').unwrap_or(raw);
+    // Byte lengths and slices below would split multibyte characters.
+    if !value.is_ascii() {
+        return None;
+    }
 
     match value.len() {
         6 => {
@@ -243,83 +1457,3 @@ fn parse_hex_color(raw: &str) -> Option<[f32; 4]> {
         _ => None,
     }
 }
-
-enum WallpaperSource {
-    Off,
-    Image(DynamicImage),
-}
-
-pub struct WallpaperState {
-    source: WallpaperSource,
-    cached_by_size: HashMap<(i32, i32), MemoryRenderBuffer>,
-}
-
-impl WallpaperState {
-    pub fn from_config(config: &RipwmConfig) -> Self {
-        let source = match &config.wallpaper {
-            WallpaperSetting::Off => WallpaperSource::Off,
-            WallpaperSetting::Path(path) => match image::open(path) {
-                Ok(image) => WallpaperSource::Image(image),
-                Err(err) => {
-                    tracing::warn!("Failed to load wallpaper {}: {err}", path.display());
-                    WallpaperSource::Off
-                }
-            },
-        };
-
-        Self { source, cached_by_size: HashMap::new() }
-    }
-
-    pub fn render_element<R>(
-        &mut self,
-        renderer: &mut R,
-        size: Size<i32, Physical>,
-    ) -> Option<MemoryRenderBufferRenderElement<R>>
-    where
-        R: Renderer + ImportMem,
-        R::TextureId: Send + Clone + 'static,
-    {
-        if size.w <= 0 || size.h <= 0 {
-            return None;
-        }
-
-        let key = (size.w, size.h);
-        if !self.cached_by_size.contains_key(&key) {
-            let buffer = self.create_buffer(size)?;
-            self.cached_by_size.insert(key, buffer);
-        }
-
-        let buffer = self.cached_by_size.get(&key)?;
-
-        MemoryRenderBufferRenderElement::from_buffer(
-            renderer,
-            (0.0, 0.0),
-            buffer,
-            None,
-            None,
-            None,
-            Kind::Unspecified,
-        )
-        .ok()
-    }
-
-    fn create_buffer(&self, size: Size<i32, Physical>) -> Option<MemoryRenderBuffer> {
-        let WallpaperSource::Image(image) = &self.source else {
-            return None;
-        };
-
-        let width = u32::try_from(size.w).ok()?;
-        let height = u32::try_from(size.h).ok()?;
-
-        let resized = image.resize_to_fill(width, height, FilterType::Lanczos3).to_rgba8();
-
-        Some(MemoryRenderBuffer::from_slice(
-            resized.as_raw(),
-            Fourcc::Abgr8888,
-            (size.w, size.h),
-            1,
-            Transform::Normal,
-            None,
-        ))
-    }
-}
@@ -0,0 +1,101 @@
+//! What happens when ripwm panics: the panic and a backtrace go to a crash file, and on a
+//! tty the input devices and the VT are given back first, so the console is usable again
+//! instead of black with no keyboard. The DRM master and the session are released by the
+//! kernel and the seat daemon once the process is gone.
+
+use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    io::Write,
+    panic::PanicHookInfo,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use smithay::{
+    backend::session::{Session, libseat::LibSeatSession},
+    reexports::input::Libinput,
+};
+
+/// Set by the first panic, a panic while handling it only gets the default message.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The tty session, set by the udev backend. It lives on the event loop's thread, which
+    /// is where panics that matter happen.
+    static SESSION: RefCell<Option<SessionCleanup>> = const { RefCell::new(None) };
+}
+
+struct SessionCleanup {
+    session: LibSeatSession,
+    libinput: Libinput,
+    /// The VT that was active when ripwm started.
+    vt: Option<i32>,
+}
+
+/// Installs the panic hook, before any backend starts.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !PANICKING.swap(true, Ordering::SeqCst) {
+            release_session();
+            write_crash_file(info);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Lets the panic hook give `session` and the devices of `libinput` back.
+pub fn set_session(session: LibSeatSession, libinput: Libinput) {
+    let vt = active_vt();
+    SESSION.with(|cleanup| {
+        *cleanup.borrow_mut() = Some(SessionCleanup { session, libinput, vt });
+    });
+}
+
+/// Releases the input devices and switches back to the VT ripwm started on, if another
+/// one is shown while ripwm still has the session.
+fn release_session() {
+    let cleanup = SESSION.try_with(|cleanup| cleanup.try_borrow_mut().ok()?.take());
+    let Ok(Some(SessionCleanup { mut session, libinput, vt })) = cleanup else {
+        return;
+    };
+
+    libinput.suspend();
+    if let Some(vt) = vt.filter(|vt| session.is_active() && active_vt() != Some(*vt))
+        && let Err(err) = session.change_vt(vt)
+    {
+        eprintln!("ripwm: failed to switch back to VT {vt}: {err}");
+    }
+}
+
+/// The number of the active VT, from `/sys/class/tty/tty0/active`.
+fn active_vt() -> Option<i32> {
+    let active = std::fs::read_to_string("/sys/class/tty/tty0/active").ok()?;
+    active.trim().strip_prefix("tty")?.parse().ok()
+}
+
+/// Writes the panic message and a backtrace to `ripwm-crash-<pid>.log` in the runtime
+/// directory.
+fn write_crash_file(info: &PanicHookInfo<'_>) {
+    let path = crash_file_path();
+    let thread = std::thread::current();
+    let report = format!(
+        "ripwm {} panicked on thread '{}': {info}\n\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        thread.name().unwrap_or("<unnamed>"),
+        Backtrace::force_capture(),
+    );
+
+    let written =
+        std::fs::File::create(&path).and_then(|mut file| file.write_all(report.as_bytes()));
+    match written {
+        Ok(()) => eprintln!("ripwm: crash report written to {}", path.display()),
+        Err(err) => eprintln!("ripwm: failed to write crash report {}: {err}", path.display()),
+    }
+}
+
+fn crash_file_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+    dir.join(format!("ripwm-crash-{}.log", std::process::id()))
+}
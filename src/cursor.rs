@@ -1,12 +1,27 @@
-use std::{io::Read, time::Duration};
+use std::{
+    collections::HashMap,
+    io::Read,
+    time::{Duration, Instant},
+};
 
+use smithay::{
+    input::pointer::{CursorIcon, CursorImageStatus},
+    reexports::calloop::{
+        RegistrationToken,
+        timer::{TimeoutAction, Timer},
+    },
+};
 use xcursor::{
     CursorTheme,
     parser::{Image, parse_xcursor},
 };
 
+use crate::Smallvil;
+
 pub struct Cursor {
-    icons: Vec<Image>,
+    theme: CursorTheme,
+    /// Parsed images by icon, loaded from the theme on first use.
+    icons: HashMap<CursorIcon, Vec<Image>>,
     size: u32,
 }
 
@@ -16,15 +31,120 @@ impl Cursor {
         let size = std::env::var("XCURSOR_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(24);
 
         let theme = CursorTheme::load(&name);
-        let icons = load_icon(&theme).unwrap_or_else(|| vec![fallback_cursor()]);
+        let default =
+            load_icon(&theme, CursorIcon::Default).unwrap_or_else(|| vec![fallback_cursor()]);
+        let icons = HashMap::from([(CursorIcon::Default, default)]);
 
-        Self { icons, size }
+        Self { theme, icons, size }
     }
 
-    pub fn get_image(&self, scale: u32, time: Duration) -> Image {
+    /// The frame of `icon` to show at `time`. Icons missing from the theme show the
+    /// default arrow instead.
+    pub fn get_image(&mut self, icon: CursorIcon, scale: u32, time: Duration) -> Image {
         let size = self.size * scale;
         let millis = u32::try_from(time.as_millis()).unwrap_or(u32::MAX);
-        frame(millis, size, &self.icons)
+
+        if !self.icons.contains_key(&icon) {
+            let images = load_icon(&self.theme, icon).unwrap_or_else(|| {
+                tracing::debug!("Cursor theme has no {} cursor, using the default", icon.name());
+                self.icons
+                    .get(&CursorIcon::Default)
+                    .cloned()
+                    .unwrap_or_else(|| vec![fallback_cursor()])
+            });
+            self.icons.insert(icon, images);
+        }
+
+        let images = self.icons.entry(icon).or_insert_with(|| vec![fallback_cursor()]);
+        frame(millis, size, images)
+    }
+}
+
+/// When the pointer gets out of the way: after `timeout` without pointer input and, with
+/// `hide_on_typing`, on every key press.
+pub struct CursorHiding {
+    pub timeout: Option<Duration>,
+    pub hide_on_typing: bool,
+    /// What to show again once the pointer is used, `Some` while the cursor is hidden.
+    hidden_status: Option<CursorImageStatus>,
+    last_activity: Option<Instant>,
+    timer: Option<RegistrationToken>,
+}
+
+impl CursorHiding {
+    pub fn new(timeout: Option<Duration>, hide_on_typing: bool) -> Self {
+        Self { timeout, hide_on_typing, hidden_status: None, last_activity: None, timer: None }
+    }
+}
+
+impl Smallvil {
+    /// Shows `status`, or keeps it for when the cursor shows again while it is hidden.
+    pub fn set_cursor_status(&mut self, status: CursorImageStatus) {
+        match &mut self.cursor_hiding.hidden_status {
+            Some(hidden) => *hidden = status,
+            None => {
+                self.seat.cursor_status = status;
+                // Backends drawing only on damage would keep showing the old cursor.
+                self.request_redraw_all();
+            }
+        }
+    }
+
+    /// Shows the cursor again after pointer input and restarts the inactivity timeout.
+    pub fn pointer_activity(&mut self) {
+        self.cursor_hiding.last_activity = Some(Instant::now());
+        if let Some(status) = self.cursor_hiding.hidden_status.take() {
+            self.seat.cursor_status = status;
+            self.request_redraw_all();
+        }
+
+        let Some(timeout) = self.cursor_hiding.timeout else {
+            return;
+        };
+        if self.cursor_hiding.timer.is_some() {
+            return;
+        }
+
+        // One timer per idle period: when it fires early because of later activity, it
+        // waits out the rest instead of being replaced on every motion event.
+        let timer =
+            self.loop_handle.insert_source(Timer::from_duration(timeout), |_, (), state| {
+                let idle =
+                    state.cursor_hiding.last_activity.map_or(Duration::MAX, |at| at.elapsed());
+                match state.cursor_hiding.timeout {
+                    Some(timeout) if idle < timeout => TimeoutAction::ToDuration(timeout - idle),
+                    Some(_) => {
+                        state.cursor_hiding.timer = None;
+                        state.hide_cursor();
+                        TimeoutAction::Drop
+                    }
+                    None => {
+                        state.cursor_hiding.timer = None;
+                        TimeoutAction::Drop
+                    }
+                }
+            });
+        match timer {
+            Ok(timer) => self.cursor_hiding.timer = Some(timer),
+            Err(err) => tracing::warn!("Failed to start cursor timeout: {err}"),
+        }
+    }
+
+    /// Hides the cursor on a key press, if configured to.
+    pub fn key_activity(&mut self) {
+        if self.cursor_hiding.hide_on_typing {
+            self.hide_cursor();
+        }
+    }
+
+    fn hide_cursor(&mut self) {
+        if self.cursor_hiding.hidden_status.is_some() {
+            return;
+        }
+
+        let status = std::mem::replace(&mut self.seat.cursor_status, CursorImageStatus::Hidden);
+        self.cursor_hiding.hidden_status = Some(status);
+        self.request_redraw_all();
     }
 }
 
@@ -63,8 +183,12 @@ fn fallback_cursor() -> Image {
     }
 }
 
-fn load_icon(theme: &CursorTheme) -> Option<Vec<Image>> {
-    let icon_path = theme.load_icon("default")?;
+/// Loads `icon` under its CSS name or, for older themes, one of its X11 aliases such as
+/// `size_fdiag` for `nwse-resize`.
+fn load_icon(theme: &CursorTheme, icon: CursorIcon) -> Option<Vec<Image>> {
+    let icon_path = std::iter::once(icon.name())
+        .chain(icon.alt_names().iter().copied())
+        .find_map(|name| theme.load_icon(name))?;
     let mut cursor_file = std::fs::File::open(icon_path).ok()?;
     let mut cursor_data = Vec::new();
     cursor_file.read_to_end(&mut cursor_data).ok()?;
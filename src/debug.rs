@@ -0,0 +1,255 @@
+//! On-screen debug overlay: per-output FPS, render time and element count, with a graph
+//! of recent frame times.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use image::{Rgba, RgbaImage};
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            ImportMem, Renderer,
+            element::{
+                Kind,
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+            },
+        },
+    },
+    output::Output,
+    utils::Transform,
+};
+
+/// How many frames each output remembers, one graph column per frame.
+const HISTORY: u32 = 120;
+
+const PADDING: u32 = 4;
+/// Glyphs are 3x5 pixels, drawn at this scale.
+const GLYPH_SCALE: u32 = 2;
+const LINE_HEIGHT: u32 = 7 * GLYPH_SCALE;
+const LINES: u32 = 3;
+const GRAPH_HEIGHT: u32 = 32;
+/// Render time filling the whole graph height.
+const GRAPH_MAX: Duration = Duration::from_millis(33);
+/// Render time marked by a line in the graph, one frame at 60Hz.
+const GRAPH_TARGET: Duration = Duration::from_micros(16_667);
+
+const WIDTH: u32 = HISTORY + PADDING * 2;
+const HEIGHT: u32 = PADDING * 3 + LINE_HEIGHT * LINES + GRAPH_HEIGHT;
+
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 192]);
+const TEXT: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BAR: Rgba<u8> = Rgba([80, 200, 120, 255]);
+const BAR_SLOW: Rgba<u8> = Rgba([230, 80, 60, 255]);
+const TARGET_LINE: Rgba<u8> = Rgba([255, 255, 255, 96]);
+
+struct FrameSample {
+    at: Instant,
+    render_time: Duration,
+    elements: usize,
+}
+
+#[derive(Default)]
+pub struct DebugOverlay {
+    enabled: bool,
+    /// Recent frames by output name.
+    frames: HashMap<String, VecDeque<FrameSample>>,
+}
+
+impl DebugOverlay {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.frames.clear();
+        }
+    }
+
+    /// Notes a frame rendered to `output`. Does nothing while the overlay is off.
+    pub fn record(&mut self, output: &Output, render_time: Duration, elements: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        let frames = self.frames.entry(output.name()).or_default();
+        if frames.len() >= HISTORY as usize {
+            frames.pop_front();
+        }
+        frames.push_back(FrameSample { at: Instant::now(), render_time, elements });
+    }
+
+    /// The overlay for `output`, to be drawn above everything else in its top left
+    /// corner. `None` while the overlay is off.
+    pub fn render_element<R>(
+        &self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Option<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        R::TextureId: Send + Clone + 'static,
+    {
+        if !self.enabled {
+            return None;
+        }
+
+        let image = draw(self.frames.get(&output.name()));
+        #[allow(clippy::cast_possible_wrap)]
+        let buffer = MemoryRenderBuffer::from_slice(
+            image.as_raw(),
+            Fourcc::Abgr8888,
+            (WIDTH as i32, HEIGHT as i32),
+            1,
+            Transform::Normal,
+            None,
+        );
+
+        match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            (0.0, 0.0),
+            &buffer,
+            None,
+            None,
+            None,
+            Kind::Unspecified,
+        ) {
+            Ok(element) => Some(element),
+            Err(err) => {
+                tracing::warn!("Failed to upload debug overlay: {err}");
+                None
+            }
+        }
+    }
+}
+
+fn draw(frames: Option<&VecDeque<FrameSample>>) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+    let empty = VecDeque::new();
+    let frames = frames.unwrap_or(&empty);
+
+    let (render_time, elements) =
+        frames.back().map_or((Duration::ZERO, 0), |frame| (frame.render_time, frame.elements));
+    let lines = [
+        format!("FPS {:.1}", fps(frames)),
+        format!("FRAME {:.2} MS", render_time.as_secs_f64() * 1000.0),
+        format!("ELEMENTS {elements}"),
+    ];
+    for (line, text) in (0..).zip(&lines) {
+        draw_text(&mut image, PADDING, PADDING + line * LINE_HEIGHT, text);
+    }
+
+    let graph_top = PADDING * 2 + LINE_HEIGHT * LINES;
+    let graph_bottom = graph_top + GRAPH_HEIGHT;
+    let offset = HISTORY.saturating_sub(u32::try_from(frames.len()).unwrap_or(HISTORY));
+    for (column, frame) in (0..).zip(frames) {
+        let height = bar_height(frame.render_time);
+        let color = if frame.render_time > GRAPH_TARGET { BAR_SLOW } else { BAR };
+        for y in graph_bottom - height..graph_bottom {
+            image.put_pixel(PADDING + offset + column, y, color);
+        }
+    }
+
+    let target_y = graph_bottom - bar_height(GRAPH_TARGET);
+    for x in PADDING..PADDING + HISTORY {
+        blend(&mut image, x, target_y, TARGET_LINE);
+    }
+
+    image
+}
+
+/// Frames per second over the last second of history.
+fn fps(frames: &VecDeque<FrameSample>) -> f64 {
+    let Some(last) = frames.back() else {
+        return 0.0;
+    };
+
+    let recent = |frame: &&FrameSample| last.at.duration_since(frame.at) <= Duration::from_secs(1);
+    let Some(first) = frames.iter().find(recent) else {
+        return 0.0;
+    };
+    let span = last.at.duration_since(first.at).as_secs_f64();
+    if span <= 0.0 {
+        return 0.0;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let intervals = (frames.iter().filter(recent).count() - 1) as f64;
+    intervals / span
+}
+
+fn bar_height(render_time: Duration) -> u32 {
+    let fraction = render_time.as_secs_f64() / GRAPH_MAX.as_secs_f64();
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let height = (fraction.min(1.0) * f64::from(GRAPH_HEIGHT)).ceil() as u32;
+    height.max(1)
+}
+
+fn blend(image: &mut RgbaImage, x: u32, y: u32, color: Rgba<u8>) {
+    let pixel = image.get_pixel_mut(x, y);
+    let alpha = u16::from(color[3]);
+    for channel in 0..3 {
+        let mixed =
+            (u16::from(color[channel]) * alpha + u16::from(pixel[channel]) * (255 - alpha)) / 255;
+        pixel[channel] = u8::try_from(mixed).unwrap_or(u8::MAX);
+    }
+}
+
+fn draw_text(image: &mut RgbaImage, x: u32, y: u32, text: &str) {
+    for (index, ch) in (0..).zip(text.chars()) {
+        let glyph_x = x + index * 4 * GLYPH_SCALE;
+        if glyph_x + 3 * GLYPH_SCALE > image.width() {
+            break;
+        }
+
+        for (row, bits) in (0..).zip(glyph(ch)) {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        image.put_pixel(
+                            glyph_x + column * GLYPH_SCALE + dx,
+                            y + row * GLYPH_SCALE + dy,
+                            TEXT,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 3x5 glyph, top to bottom, most significant bit leftmost. Only what the
+/// overlay prints is covered, anything else is blank.
+fn glyph(ch: char) -> [u8; 5] {
+    match ch.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        _ => [0; 5],
+    }
+}
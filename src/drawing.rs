@@ -1,18 +1,36 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use smithay::{
-    backend::renderer::{
-        ImportAll, ImportMem, Renderer, Texture,
-        element::{
-            AsRenderElements, Kind,
-            memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
-            solid::{SolidColorBuffer, SolidColorRenderElement},
-            surface::WaylandSurfaceRenderElement,
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            ImportAll, ImportMem, Renderer, Texture,
+            damage::OutputDamageTracker,
+            element::{
+                AsRenderElements, Kind, Wrap,
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+                surface::{WaylandSurfaceRenderElement, render_elements_from_surface_tree},
+            },
+            gles::{GlesRenderer, GlesTarget},
         },
     },
-    desktop::{Space, Window},
-    input::pointer::CursorImageStatus,
+    desktop::{Space, Window, space::SpaceRenderElements},
+    input::pointer::{CursorIcon, CursorImageAttributes, CursorImageStatus},
+    output::Output,
     reexports::wayland_server::protocol::wl_surface::WlSurface,
     render_elements,
-    utils::{Logical, Physical, Point, Rectangle, Scale},
+    utils::{IsAlive, Logical, Physical, Point, Rectangle, Scale, Transform},
+    wayland::compositor,
+};
+
+use crate::{
+    Smallvil,
+    rounded::{AsGlesRenderer, RoundedBorderElement, RoundedSurfaceElement, Rounding},
+    seat::SeatData,
 };
 
 pub struct PointerElement {
@@ -103,16 +121,270 @@ where
     }
 }
 
+/// A cursor drawn into the frame: the theme image for named cursors, or the client's
+/// cursor surface.
+pub struct SoftwareCursor {
+    theme: crate::cursor::Cursor,
+    /// Uploaded cursor frames, by icon, buffer scale and frame.
+    images: Vec<((CursorIcon, i32, xcursor::parser::Image), MemoryRenderBuffer)>,
+    element: PointerElement,
+}
+
+impl SoftwareCursor {
+    /// How many cursor frames have been uploaded, for `ripctl debug`.
+    pub fn cached_images(&self) -> usize {
+        self.images.len()
+    }
+}
+
+impl Default for SoftwareCursor {
+    fn default() -> Self {
+        Self {
+            theme: crate::cursor::Cursor::load(),
+            images: Vec::new(),
+            element: PointerElement::default(),
+        }
+    }
+}
+
+impl SoftwareCursor {
+    /// The elements of the pointer of `seat` on `output`, none if it is on another output.
+    /// A cursor surface that was destroyed falls back to the default named cursor.
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        seat: &mut SeatData,
+        output: &Output,
+        output_geometry: Rectangle<i32, Logical>,
+        time: Duration,
+    ) -> Vec<PointerRenderElement<R>>
+    where
+        R: Renderer + ImportAll + ImportMem,
+        R::TextureId: Clone + Send + 'static,
+    {
+        let icon = match &seat.cursor_status {
+            CursorImageStatus::Named(icon) => *icon,
+            _ => CursorIcon::Default,
+        };
+        // The theme image is picked for the output scale rounded up and drawn at
+        // XCURSOR_SIZE logical pixels, so it stays the same size across outputs.
+        let output_scale = Scale::from(output.current_scale().fractional_scale());
+        let cursor_scale = output.current_scale().integer_scale().max(1);
+        let frame = self.theme.get_image(icon, cursor_scale.unsigned_abs(), time);
+        let buffer = self
+            .images
+            .iter()
+            .find_map(|((image_icon, image_scale, image), buffer)| {
+                (*image_icon == icon && *image_scale == cursor_scale && image == &frame)
+                    .then(|| buffer.clone())
+            })
+            .unwrap_or_else(|| {
+                let buffer = MemoryRenderBuffer::from_slice(
+                    &frame.pixels_rgba,
+                    Fourcc::Argb8888,
+                    (
+                        i32::try_from(frame.width).unwrap_or(i32::MAX),
+                        i32::try_from(frame.height).unwrap_or(i32::MAX),
+                    ),
+                    cursor_scale,
+                    Transform::Normal,
+                    None,
+                );
+                self.images.push(((icon, cursor_scale, frame.clone()), buffer.clone()));
+                buffer
+            });
+
+        if let CursorImageStatus::Surface(ref cursor_surface) = seat.cursor_status
+            && !cursor_surface.alive()
+        {
+            seat.cursor_status = CursorImageStatus::default_named();
+        }
+
+        // Theme hotspots are in image pixels, client hotspots in surface coordinates.
+        let theme_hotspot = Point::<f64, Logical>::from((
+            f64::from(frame.xhot) / f64::from(cursor_scale),
+            f64::from(frame.yhot) / f64::from(cursor_scale),
+        ));
+        let hotspot = if let CursorImageStatus::Surface(ref cursor_surface) = seat.cursor_status {
+            compositor::with_states(cursor_surface, |states| {
+                states
+                    .data_map
+                    .get::<Mutex<CursorImageAttributes>>()
+                    .and_then(|attrs| attrs.lock().ok().map(|guard| guard.hotspot.to_f64()))
+                    .unwrap_or(theme_hotspot)
+            })
+        } else {
+            theme_hotspot
+        };
+
+        let Some(pointer) = seat.pointer() else {
+            return Vec::new();
+        };
+        let pointer_location = pointer.current_location();
+        if !output_geometry.to_f64().contains(pointer_location) {
+            return Vec::new();
+        }
+
+        let cursor_pos = pointer_location - output_geometry.loc.to_f64();
+        self.element.set_buffer(buffer);
+        self.element.set_status(seat.cursor_status.clone());
+        self.element.render_elements(
+            renderer,
+            (cursor_pos - hotspot).to_physical(output_scale).to_i32_round(),
+            output_scale,
+            1.0,
+        )
+    }
+}
+
+/// The elements of the drag-and-drop icon of `seat` on `output`: the icon surface at the
+/// pointer, moved by its offset. None while nothing is dragged or the pointer is on
+/// another output. The icon is never part of the space, so it can't be under the pointer
+/// itself.
+pub fn dnd_icon_elements<R>(
+    renderer: &mut R,
+    seat: &SeatData,
+    output: &Output,
+    output_geometry: Rectangle<i32, Logical>,
+) -> Vec<PointerRenderElement<R>>
+where
+    R: Renderer + ImportAll + ImportMem,
+    R::TextureId: Clone + Send + 'static,
+{
+    let Some(icon) = seat.dnd_icon.as_ref().filter(|icon| icon.surface.alive()) else {
+        return Vec::new();
+    };
+    let Some(pointer) = seat.pointer() else {
+        return Vec::new();
+    };
+    let pointer_location = pointer.current_location();
+    if !output_geometry.to_f64().contains(pointer_location) {
+        return Vec::new();
+    }
+
+    let output_scale = Scale::from(output.current_scale().fractional_scale());
+    let location = pointer_location - output_geometry.loc.to_f64() + icon.offset.to_f64();
+    render_elements_from_surface_tree(
+        renderer,
+        &icon.surface,
+        location.to_physical(output_scale).to_i32_round(),
+        output_scale,
+        1.0,
+        Kind::Unspecified,
+    )
+}
+
+/// Collects the render elements of every window visible on `output`, topmost first,
+/// applying the opacity configured by matching window rules. Windows other than the one
+/// owning `active_surface` are faded by `inactive_dim`; popups share their window's alpha.
+/// With `rounding`, every window but a fullscreen one gets its corners cut. Windows below
+/// a fullscreen one are left out, so its surface can be scanned out directly.
+pub fn space_render_elements<R>(
+    renderer: &mut R,
+    space: &Space<Window>,
+    output: &Output,
+    window_rules: &[crate::config::WindowRule],
+    active_surface: Option<&WlSurface>,
+    inactive_dim: f32,
+    rounding: Option<&Rounding>,
+) -> Vec<SpaceRenderElements<R, RoundedSurfaceElement<R>>>
+where
+    R: Renderer + ImportAll,
+    R::TextureId: Clone + Texture + 'static,
+{
+    let Some(output_geo) = space.output_geometry(output) else {
+        return Vec::new();
+    };
+    let output_scale = output.current_scale().fractional_scale();
+    let mut covered = false;
+
+    space
+        .elements()
+        .rev()
+        .filter(|window| space.element_bbox(window).is_some_and(|bbox| output_geo.overlaps(bbox)))
+        .take_while(|window| {
+            let visible = !covered;
+            covered |= crate::window::is_fullscreen(window);
+            visible
+        })
+        .flat_map(|window| {
+            let location = space.element_location(window).unwrap_or_default();
+            let render_location = location - window.geometry().loc - output_geo.loc;
+            let app_id = crate::window::app_id(window);
+            let mut opacity = crate::config::window_opacity(window_rules, app_id.as_deref());
+            let active = window
+                .toplevel()
+                .is_some_and(|toplevel| Some(toplevel.wl_surface()) == active_surface);
+            if !active {
+                opacity *= 1.0 - inactive_dim;
+            }
+            let clip = rounding.filter(|_| !crate::window::is_fullscreen(window)).map(|rounding| {
+                let geometry = Rectangle::new(location - output_geo.loc, window.geometry().size);
+                rounding.clip(geometry, output_scale)
+            });
+            window
+                .render_elements::<WaylandSurfaceRenderElement<R>>(
+                    renderer,
+                    render_location.to_physical_precise_round(output_scale),
+                    Scale::from(output_scale),
+                    opacity,
+                )
+                .into_iter()
+                .map(move |element| {
+                    let element = RoundedSurfaceElement::new(element, clip.clone());
+                    SpaceRenderElements::Element(Wrap::from(element))
+                })
+        })
+        .collect()
+}
+
+/// Like [`tiled_border_elements`], but with rounded corners.
+pub fn rounded_border_elements(
+    output_geo: Rectangle<i32, Logical>,
+    space: &Space<Window>,
+    border_color: impl Fn(&Window) -> [f32; 4],
+    border_width: i32,
+    border_outside: bool,
+    rounding: &Rounding,
+) -> Vec<RoundedBorderElement> {
+    // A width of 0 turns borders off.
+    if border_width <= 0 {
+        return Vec::new();
+    }
+
+    space
+        .elements()
+        .filter_map(|window| {
+            let location = space.element_location(window)?;
+            let geometry = window.geometry();
+            if geometry.size.w <= 0 || geometry.size.h <= 0 {
+                return None;
+            }
+
+            let window_rect = Rectangle::new(location, geometry.size);
+            let border_rect =
+                if border_outside { outset(window_rect, border_width) } else { window_rect };
+            if !border_rect.overlaps(output_geo) {
+                return None;
+            }
+
+            let relative = Rectangle::new(border_rect.loc - output_geo.loc, border_rect.size);
+            Some(rounding.border(relative, border_color(window), border_width, border_outside))
+        })
+        .collect()
+}
+
+/// Solid borders around the windows on the output at `output_geo`, drawn at the output
+/// `scale`. They cover the edge of each window, or with `border_outside` surround it.
 pub fn tiled_border_elements(
     output_geo: Rectangle<i32, Logical>,
     space: &Space<Window>,
-    focused_surface: Option<&WlSurface>,
-    active_color: [f32; 4],
-    inactive_color: [f32; 4],
+    border_color: impl Fn(&Window) -> [f32; 4],
     border_width: i32,
+    border_outside: bool,
+    scale: Scale<f64>,
 ) -> Vec<SolidColorRenderElement> {
     let mut elements = Vec::new();
-    let border = border_width.max(1);
 
     for window in space.elements() {
         let Some(location) = space.element_location(window) else {
@@ -125,27 +397,23 @@ pub fn tiled_border_elements(
         }
 
         let window_rect = Rectangle::new(location, geometry.size);
-        if !window_rect.overlaps(output_geo) {
+        let border_rect =
+            if border_outside { outset(window_rect, border_width) } else { window_rect };
+        if !border_rect.overlaps(output_geo) {
             continue;
         }
 
-        let relative_loc = window_rect.loc - output_geo.loc;
-        let width = window_rect.size.w;
-        let height = window_rect.size.h;
-        let border_thickness = border.min(width).min(height);
+        let relative_loc = border_rect.loc - output_geo.loc;
+        let width = border_rect.size.w;
+        let height = border_rect.size.h;
+        // Past half the window, the top and bottom segments cover all of it.
+        let border_thickness = border_width.min(width).min(height);
         if border_thickness <= 0 {
             continue;
         }
+        let side_height = height.saturating_sub(border_thickness.saturating_mul(2)).max(0);
 
-        let color = if let Some(toplevel) = window.toplevel() {
-            if focused_surface.is_some_and(|focused| focused == toplevel.wl_surface()) {
-                active_color
-            } else {
-                inactive_color
-            }
-        } else {
-            inactive_color
-        };
+        let color = border_color(window);
 
         let segments = [
             Rectangle::new(relative_loc, (width, border_thickness).into()),
@@ -155,12 +423,12 @@ pub fn tiled_border_elements(
             ),
             Rectangle::new(
                 (relative_loc.x, relative_loc.y + border_thickness).into(),
-                (border_thickness, height - (border_thickness * 2)).into(),
+                (border_thickness, side_height).into(),
             ),
             Rectangle::new(
                 (relative_loc.x + width - border_thickness, relative_loc.y + border_thickness)
                     .into(),
-                (border_thickness, height - (border_thickness * 2)).into(),
+                (border_thickness, side_height).into(),
             ),
         ];
 
@@ -172,8 +440,8 @@ pub fn tiled_border_elements(
             let buffer = SolidColorBuffer::new(segment.size, color);
             elements.push(SolidColorRenderElement::from_buffer(
                 &buffer,
-                (segment.loc.x, segment.loc.y),
-                Scale::from(1.0),
+                segment.loc.to_f64().to_physical(scale),
+                scale,
                 1.0,
                 Kind::Unspecified,
             ));
@@ -182,3 +450,157 @@ pub fn tiled_border_elements(
 
     elements
 }
+
+/// `rect` grown by `width` on every side.
+fn outset(rect: Rectangle<i32, Logical>, width: i32) -> Rectangle<i32, Logical> {
+    let width = width.max(0);
+    Rectangle::new(
+        (rect.loc.x.saturating_sub(width), rect.loc.y.saturating_sub(width)).into(),
+        (
+            rect.size.w.saturating_add(width.saturating_mul(2)),
+            rect.size.h.saturating_add(width.saturating_mul(2)),
+        )
+            .into(),
+    )
+}
+
+// Everything drawn on an output by the backends that render a whole output with GLES at
+// once: the nested window and headless outputs.
+render_elements! {
+    pub GlesOutputRenderElements<R, E> where R: ImportAll + ImportMem + AsGlesRenderer;
+    Space=SpaceRenderElements<R, E>,
+    Wallpaper=crate::wallpaper::WallpaperRenderElement<R>,
+    Border=SolidColorRenderElement,
+    RoundedBorder=RoundedBorderElement,
+    Pointer=PointerRenderElement<R>,
+    Debug=MemoryRenderBufferRenderElement<R>,
+}
+
+impl Smallvil {
+    /// Draws `output` into `framebuffer` and takes a pending screenshot of it, with the
+    /// pointer on top if `cursor` is given. `age` is the number of frames since
+    /// `framebuffer` was last drawn by `damage_tracker`, 0 if unknown. Returns the damage
+    /// of the frame, `None` if nothing changed or drawing failed.
+    pub fn render_gles_output(
+        &mut self,
+        renderer: &mut GlesRenderer,
+        framebuffer: &mut GlesTarget<'_>,
+        age: usize,
+        output: &Output,
+        damage_tracker: &mut OutputDamageTracker,
+        cursor: Option<&mut SoftwareCursor>,
+    ) -> Option<Vec<Rectangle<i32, Physical>>> {
+        let mut elements: Vec<
+            GlesOutputRenderElements<GlesRenderer, RoundedSurfaceElement<GlesRenderer>>,
+        > = Vec::new();
+
+        if let Some(overlay) = self.debug_overlay.render_element(renderer, output) {
+            elements.push(GlesOutputRenderElements::Debug(overlay));
+        }
+
+        if let Some(cursor) = cursor
+            && let Some(output_geo) = self.space.output_geometry(output)
+        {
+            let time = self.start_time.elapsed();
+            let pointer_elements =
+                cursor.render_elements(renderer, &mut self.seat, output, output_geo, time);
+            elements.extend(pointer_elements.into_iter().map(GlesOutputRenderElements::Pointer));
+        }
+
+        // Drawn with the host's cursor too, which can't show it.
+        if let Some(output_geo) = self.space.output_geometry(output) {
+            let icon_elements = dnd_icon_elements(renderer, &self.seat, output, output_geo);
+            elements.extend(icon_elements.into_iter().map(GlesOutputRenderElements::Pointer));
+        }
+
+        let rounding = Rounding::new(renderer, self.corner_radius);
+        let space_elements = space_render_elements(
+            renderer,
+            &self.space,
+            output,
+            &self.window_rules,
+            self.active_surface.as_ref(),
+            self.inactive_dim,
+            rounding.as_ref(),
+        );
+
+        // A fullscreen window hides the wallpaper and every border on its output.
+        let fullscreen = crate::window::has_fullscreen_window(&self.space, output);
+
+        if !fullscreen && let Some(output_geo) = self.space.output_geometry(output) {
+            if let Some(rounding) = &rounding {
+                let border_elements = rounded_border_elements(
+                    output_geo,
+                    &self.space,
+                    |window| self.border_color(window),
+                    self.border_width,
+                    self.border_outside,
+                    rounding,
+                );
+                elements.extend(
+                    border_elements.into_iter().map(GlesOutputRenderElements::RoundedBorder),
+                );
+            } else {
+                let border_elements = tiled_border_elements(
+                    output_geo,
+                    &self.space,
+                    |window| self.border_color(window),
+                    self.border_width,
+                    self.border_outside,
+                    Scale::from(output.current_scale().fractional_scale()),
+                );
+                elements.extend(border_elements.into_iter().map(GlesOutputRenderElements::Border));
+            }
+        }
+
+        elements.extend(space_elements.into_iter().map(GlesOutputRenderElements::Space));
+
+        if !fullscreen
+            && let Some(wallpaper_element) =
+                self.wallpaper.for_output(output).render_element(renderer, output)
+        {
+            elements.push(GlesOutputRenderElements::Wallpaper(wallpaper_element));
+        }
+
+        let render_start = Instant::now();
+        let damage = match damage_tracker.render_output(
+            renderer,
+            framebuffer,
+            age,
+            &elements,
+            [0.0, 0.0, 0.0, 1.0],
+        ) {
+            Ok(result) => {
+                self.update_primary_scanout_output(output, &result.states);
+                result.damage.cloned()
+            }
+            Err(err) => {
+                tracing::error!("Failed to render output {}: {err}", output.name());
+                return None;
+            }
+        };
+        self.debug_overlay.record(output, render_start.elapsed(), elements.len());
+
+        if let Some(request) = self.pending_screenshot.take_if(|request| request.output == *output)
+        {
+            let shot: Vec<_> = elements
+                .iter()
+                .filter(|element| {
+                    !matches!(
+                        element,
+                        GlesOutputRenderElements::Debug(_) | GlesOutputRenderElements::Pointer(_)
+                    )
+                })
+                .collect();
+            match crate::screenshot::capture(renderer, &request, &shot) {
+                Ok(image) => crate::screenshot::save(
+                    image,
+                    crate::screenshot::directory(self.screenshot_dir.as_deref()),
+                ),
+                Err(err) => tracing::warn!("Failed to take screenshot: {err}"),
+            }
+        }
+
+        damage
+    }
+}
@@ -0,0 +1,118 @@
+//! Monitor make, model and serial from the EDID blob of a connector.
+
+use smithay::reexports::drm::control::{Device as ControlDevice, connector};
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const BLOCK_LEN: usize = 128;
+/// Offsets of the four 18-byte display descriptors in the base block.
+const DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+const TAG_SERIAL: u8 = 0xff;
+const TAG_NAME: u8 = 0xfc;
+
+/// Names for the most common PNP manufacturer IDs, others show the three-letter ID.
+const VENDORS: &[(&str, &str)] = &[
+    ("ACR", "Acer"),
+    ("AOC", "AOC"),
+    ("AUO", "AU Optronics"),
+    ("BNQ", "BenQ"),
+    ("BOE", "BOE"),
+    ("CMN", "Chimei Innolux"),
+    ("DEL", "Dell"),
+    ("GSM", "LG Electronics"),
+    ("HWP", "HP"),
+    ("IVM", "Iiyama"),
+    ("LEN", "Lenovo"),
+    ("LGD", "LG Display"),
+    ("MSI", "MSI"),
+    ("NEC", "NEC"),
+    ("PHL", "Philips"),
+    ("SAM", "Samsung"),
+    ("SDC", "Samsung Display"),
+    ("SHP", "Sharp"),
+    ("SNY", "Sony"),
+    ("VSC", "ViewSonic"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdidInfo {
+    pub make: String,
+    pub model: String,
+    pub serial: Option<String>,
+}
+
+impl EdidInfo {
+    /// Parses the base block of an EDID blob. `None` if it is missing, truncated or fails
+    /// its checksum.
+    pub fn parse(edid: &[u8]) -> Option<Self> {
+        let block = edid.get(..BLOCK_LEN)?;
+        if block[..HEADER.len()] != HEADER {
+            return None;
+        }
+        if block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return None;
+        }
+
+        let id = u16::from_be_bytes([block[8], block[9]]);
+        let letters = [(id >> 10) & 0x1f, (id >> 5) & 0x1f, id & 0x1f];
+        let vendor: String = letters
+            .iter()
+            .map(|letter| match u8::try_from(*letter) {
+                Ok(letter @ 1..=26) => char::from(b'A' + letter - 1),
+                _ => '?',
+            })
+            .collect();
+        let make = VENDORS
+            .iter()
+            .find_map(|(id, name)| (*id == vendor).then(|| (*name).to_string()))
+            .unwrap_or(vendor);
+
+        let product = u16::from_le_bytes([block[10], block[11]]);
+        let serial_number = u32::from_le_bytes([block[12], block[13], block[14], block[15]]);
+
+        let model = descriptor_text(block, TAG_NAME).unwrap_or_else(|| format!("0x{product:04X}"));
+        let serial = descriptor_text(block, TAG_SERIAL)
+            .or_else(|| (serial_number != 0).then(|| format!("0x{serial_number:08X}")));
+
+        Some(Self { make, model, serial })
+    }
+
+    /// `make model serial`, which stays the same whichever connector the monitor is
+    /// plugged into. `[[output]]` sections can match on it instead of the connector name.
+    pub fn identifier(&self) -> String {
+        match &self.serial {
+            Some(serial) => format!("{} {} {serial}", self.make, self.model),
+            None => format!("{} {}", self.make, self.model),
+        }
+    }
+}
+
+/// Reads and parses the EDID of `connector`. Connectors without one, or with one that
+/// can't be read, give `None`.
+pub fn read(drm: &impl ControlDevice, connector: connector::Handle) -> Option<EdidInfo> {
+    let blob = crate::udev::connector_property(drm, connector, "EDID")?;
+    if blob == 0 {
+        return None;
+    }
+
+    let edid = drm.get_property_blob(blob).ok()?;
+    let info = EdidInfo::parse(&edid);
+    if info.is_none() {
+        tracing::warn!("Ignoring invalid EDID of connector {connector:?}");
+    }
+    info
+}
+
+/// The text of the first display descriptor tagged `tag`, trimmed of its padding.
+fn descriptor_text(block: &[u8], tag: u8) -> Option<String> {
+    DESCRIPTORS.iter().find_map(|offset| {
+        let descriptor = block.get(*offset..offset + 18)?;
+        if descriptor[..3] != [0, 0, 0] || descriptor[3] != tag {
+            return None;
+        }
+
+        let text = &descriptor[5..];
+        let end = text.iter().position(|byte| *byte == b'\n').unwrap_or(text.len());
+        let text = String::from_utf8_lossy(&text[..end]).trim().to_string();
+        (!text.is_empty()).then_some(text)
+    })
+}
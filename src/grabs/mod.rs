@@ -0,0 +1,3 @@
+mod resize_grab;
+
+pub use resize_grab::{ResizeAnchor, ResizeSurfaceGrab, resize_cursor};
@@ -0,0 +1,333 @@
+use smithay::{
+    desktop::Window,
+    input::pointer::{
+        AxisFrame, ButtonEvent, CursorIcon, CursorImageStatus, GestureHoldBeginEvent,
+        GestureHoldEndEvent, GesturePinchBeginEvent, GesturePinchEndEvent, GesturePinchUpdateEvent,
+        GestureSwipeBeginEvent, GestureSwipeEndEvent, GestureSwipeUpdateEvent,
+        GrabStartData as PointerGrabStartData, MotionEvent, PointerGrab, PointerInnerHandle,
+        RelativeMotionEvent,
+    },
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel::{self, ResizeEdge},
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Point, Rectangle, Size},
+    wayland::{
+        compositor::with_states,
+        shell::xdg::{SurfaceCachedState, ToplevelSurface},
+    },
+};
+
+use crate::{Smallvil, window::window_data};
+
+/// Where an interactively resized window started. Kept in the window's data until the
+/// client has committed its final size, so the edges opposite to the dragged ones stay
+/// put whatever size the client picks.
+#[derive(Debug, Clone, Copy)]
+pub struct ResizeAnchor {
+    edges: ResizeEdge,
+    initial_geometry: Rectangle<i32, Logical>,
+    /// The grab has ended and the final configure was sent.
+    released: bool,
+}
+
+impl ResizeAnchor {
+    /// Whether the grab resizing the window is still active.
+    pub fn is_active(&self) -> bool {
+        !self.released
+    }
+}
+
+fn has_left(edges: ResizeEdge) -> bool {
+    matches!(edges, ResizeEdge::Left | ResizeEdge::TopLeft | ResizeEdge::BottomLeft)
+}
+
+fn has_right(edges: ResizeEdge) -> bool {
+    matches!(edges, ResizeEdge::Right | ResizeEdge::TopRight | ResizeEdge::BottomRight)
+}
+
+fn has_top(edges: ResizeEdge) -> bool {
+    matches!(edges, ResizeEdge::Top | ResizeEdge::TopLeft | ResizeEdge::TopRight)
+}
+
+fn has_bottom(edges: ResizeEdge) -> bool {
+    matches!(edges, ResizeEdge::Bottom | ResizeEdge::BottomLeft | ResizeEdge::BottomRight)
+}
+
+/// The cursor shown while dragging `edges`.
+pub fn resize_cursor(edges: ResizeEdge) -> CursorIcon {
+    match edges {
+        ResizeEdge::Top => CursorIcon::NResize,
+        ResizeEdge::Bottom => CursorIcon::SResize,
+        ResizeEdge::Left => CursorIcon::WResize,
+        ResizeEdge::Right => CursorIcon::EResize,
+        ResizeEdge::TopLeft => CursorIcon::NwResize,
+        ResizeEdge::TopRight => CursorIcon::NeResize,
+        ResizeEdge::BottomLeft => CursorIcon::SwResize,
+        ResizeEdge::BottomRight => CursorIcon::SeResize,
+        _ => CursorIcon::Default,
+    }
+}
+
+/// Clamps one dimension to the client's limits, where 0 means unconstrained.
+fn clamp_dimension(value: i32, min: i32, max: i32) -> i32 {
+    let min = min.max(1);
+    let max = if max == 0 { i32::MAX } else { max.max(min) };
+    value.clamp(min, max)
+}
+
+/// Pointer grab for xdg_toplevel.resize on a floating window.
+pub struct ResizeSurfaceGrab {
+    start_data: PointerGrabStartData<Smallvil>,
+    window: Window,
+    edges: ResizeEdge,
+    initial_geometry: Rectangle<i32, Logical>,
+    last_size: Size<i32, Logical>,
+}
+
+impl ResizeSurfaceGrab {
+    pub fn start(
+        start_data: PointerGrabStartData<Smallvil>,
+        window: Window,
+        edges: ResizeEdge,
+        initial_geometry: Rectangle<i32, Logical>,
+    ) -> Self {
+        window_data(&window).resize =
+            Some(ResizeAnchor { edges, initial_geometry, released: false });
+
+        Self { start_data, window, edges, initial_geometry, last_size: initial_geometry.size }
+    }
+
+    /// The size the pointer at `location` asks for, clamped to the client's min and max size.
+    fn size_for(
+        &self,
+        toplevel: &ToplevelSurface,
+        location: Point<f64, Logical>,
+    ) -> Size<i32, Logical> {
+        let delta = (location - self.start_data.location).to_i32_round::<i32>();
+
+        let mut width = self.initial_geometry.size.w;
+        let mut height = self.initial_geometry.size.h;
+        if has_left(self.edges) {
+            width = width.saturating_sub(delta.x);
+        } else if has_right(self.edges) {
+            width = width.saturating_add(delta.x);
+        }
+        if has_top(self.edges) {
+            height = height.saturating_sub(delta.y);
+        } else if has_bottom(self.edges) {
+            height = height.saturating_add(delta.y);
+        }
+
+        let (min_size, max_size) = with_states(toplevel.wl_surface(), |states| {
+            let mut cached = states.cached_state.get::<SurfaceCachedState>();
+            let current = cached.current();
+            (current.min_size, current.max_size)
+        });
+
+        (
+            clamp_dimension(width, min_size.w, max_size.w),
+            clamp_dimension(height, min_size.h, max_size.h),
+        )
+            .into()
+    }
+}
+
+impl PointerGrab<Smallvil> for ResizeSurfaceGrab {
+    fn motion(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        _focus: Option<(WlSurface, Point<f64, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        // No client has pointer focus while resizing.
+        handle.motion(data, None, event);
+
+        let Some(toplevel) = self.window.toplevel().filter(|toplevel| toplevel.alive()).cloned()
+        else {
+            // The window went away mid-resize.
+            handle.unset_grab(self, data, event.serial, event.time, true);
+            return;
+        };
+
+        let size = self.size_for(&toplevel, event.location);
+        if size == self.last_size {
+            return;
+        }
+        self.last_size = size;
+
+        toplevel.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Resizing);
+            state.size = Some(size);
+        });
+        toplevel.send_pending_configure();
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        focus: Option<(WlSurface, Point<f64, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, focus, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+
+        if !handle.current_pressed().contains(&self.start_data.button) {
+            handle.unset_grab(self, data, event.serial, event.time, true);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details);
+    }
+
+    fn frame(&mut self, data: &mut Smallvil, handle: &mut PointerInnerHandle<'_, Smallvil>) {
+        handle.frame(data);
+    }
+
+    fn gesture_swipe_begin(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GestureSwipeBeginEvent,
+    ) {
+        handle.gesture_swipe_begin(data, event);
+    }
+
+    fn gesture_swipe_update(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GestureSwipeUpdateEvent,
+    ) {
+        handle.gesture_swipe_update(data, event);
+    }
+
+    fn gesture_swipe_end(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GestureSwipeEndEvent,
+    ) {
+        handle.gesture_swipe_end(data, event);
+    }
+
+    fn gesture_pinch_begin(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GesturePinchBeginEvent,
+    ) {
+        handle.gesture_pinch_begin(data, event);
+    }
+
+    fn gesture_pinch_update(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GesturePinchUpdateEvent,
+    ) {
+        handle.gesture_pinch_update(data, event);
+    }
+
+    fn gesture_pinch_end(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GesturePinchEndEvent,
+    ) {
+        handle.gesture_pinch_end(data, event);
+    }
+
+    fn gesture_hold_begin(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GestureHoldBeginEvent,
+    ) {
+        handle.gesture_hold_begin(data, event);
+    }
+
+    fn gesture_hold_end(
+        &mut self,
+        data: &mut Smallvil,
+        handle: &mut PointerInnerHandle<'_, Smallvil>,
+        event: &GestureHoldEndEvent,
+    ) {
+        handle.gesture_hold_end(data, event);
+    }
+
+    fn start_data(&self) -> &PointerGrabStartData<Smallvil> {
+        &self.start_data
+    }
+
+    /// Runs on release and whenever the grab is cancelled, so the Resizing state never
+    /// outlives the grab.
+    fn unset(&mut self, data: &mut Smallvil) {
+        data.set_cursor_status(CursorImageStatus::default_named());
+        if let Some(anchor) = window_data(&self.window).resize.as_mut() {
+            anchor.released = true;
+        }
+
+        let Some(toplevel) = self.window.toplevel() else {
+            return;
+        };
+        if !toplevel.alive() {
+            return;
+        }
+
+        let size = self.last_size;
+        toplevel.with_pending_state(|state| {
+            state.states.unset(xdg_toplevel::State::Resizing);
+            state.size = Some(size);
+        });
+        toplevel.send_pending_configure();
+        data.request_redraw_all();
+    }
+}
+
+impl Smallvil {
+    /// Keeps the edges opposite to the dragged ones in place as the client commits new
+    /// sizes, and forgets the anchor once the final size has been committed.
+    pub fn apply_resize_anchor(&mut self, window: &Window) {
+        let Some(anchor) = window_data(window).resize else {
+            return;
+        };
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+
+        let size = window.geometry().size;
+        let mut location = anchor.initial_geometry.loc;
+        if has_left(anchor.edges) {
+            location.x += anchor.initial_geometry.size.w - size.w;
+        }
+        if has_top(anchor.edges) {
+            location.y += anchor.initial_geometry.size.h - size.h;
+        }
+        if self.space.element_location(window) != Some(location) {
+            self.space.map_element(window.clone(), location, false);
+        }
+
+        if anchor.released
+            && !toplevel.current_state().states.contains(xdg_toplevel::State::Resizing)
+        {
+            window_data(window).resize = None;
+        }
+    }
+}
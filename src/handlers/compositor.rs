@@ -9,8 +9,8 @@ use smithay::{
     wayland::{
         buffer::BufferHandler,
         compositor::{
-            CompositorClientState, CompositorHandler, CompositorState, get_parent,
-            is_sync_subsurface,
+            CompositorClientState, CompositorHandler, CompositorState, SurfaceAttributes,
+            get_parent, is_sync_subsurface, with_states,
         },
         shm::{ShmHandler, ShmState},
     },
@@ -31,28 +31,53 @@ impl CompositorHandler for Smallvil {
         let before_count = self.space.elements().count();
 
         on_commit_buffer_handler::<Self>(surface);
+        let mut root = surface.clone();
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
         if !is_sync_subsurface(surface) {
-            let mut root = surface.clone();
-            while let Some(parent) = get_parent(&root) {
-                root = parent;
-            }
-            if let Some(window) =
-                self.space.elements().find(|w| w.toplevel().unwrap().wl_surface() == &root)
-            {
+            if let Some(window) = self.window_for_surface(&root) {
                 window.on_commit();
+                // Only metadata is updated here, a title ticking every second must not
+                // cause a retile.
+                crate::window::refresh_metadata(&window);
+                self.foreign_toplevels_changed();
+                self.apply_resize_anchor(&window);
+                if crate::window::is_dialog(&window) && !crate::window::window_data(&window).centred
+                {
+                    self.centre_over_parent(&window);
+                }
             }
         }
 
         xdg_shell::handle_commit(&mut self.popups, &self.space, surface);
 
+        // A drag icon attached with an offset moves by it, as a cursor's hotspot would.
+        if let Some(icon) = self.seat.dnd_icon.as_mut()
+            && icon.surface == *surface
+        {
+            icon.offset += with_states(surface, |states| {
+                states.cached_state.get::<SurfaceAttributes>().current().buffer_delta.take()
+            })
+            .unwrap_or_default();
+        }
+
         self.space.refresh();
         let after_count = self.space.elements().count();
         if before_count != after_count {
             self.arrange_windows_tiled();
         }
 
-        if self.udev.is_some() {
-            self.request_redraw_all();
+        // Only the outputs showing the window need a new frame. Surfaces without a window,
+        // popups and cursors among them, redraw everything.
+        match self.window_for_surface(&root) {
+            Some(window) => {
+                let outputs = self.space.outputs_for_element(&window);
+                for output in outputs {
+                    self.request_redraw(&output);
+                }
+            }
+            None => self.request_redraw_all(),
         }
     }
 }
@@ -0,0 +1,346 @@
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols::ext::workspace::v1::server::{
+            ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1, GroupCapabilities},
+            ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1, WorkspaceCapabilities},
+            ext_workspace_manager_v1::{self, ExtWorkspaceManagerV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            backend::{ClientId, GlobalId},
+            protocol::wl_output::WlOutput,
+        },
+    },
+};
+
+use crate::Smallvil;
+
+/// What a bar currently knows about one workspace.
+struct WorkspaceHandle {
+    id: u32,
+    handle: ExtWorkspaceHandleV1,
+    output: Output,
+    coordinate: u32,
+    state: ext_workspace_handle_v1::State,
+    /// The bar destroyed the handle, it gets no more events and isn't announced again.
+    destroyed: bool,
+}
+
+/// One bound `ext_workspace_manager_v1` and the handles it was sent.
+struct ManagerInstance {
+    manager: ExtWorkspaceManagerV1,
+    groups: Vec<(Output, ExtWorkspaceGroupHandleV1)>,
+    workspaces: Vec<WorkspaceHandle>,
+    pending_activation: Option<u32>,
+}
+
+pub struct ExtWorkspaceState {
+    _global: GlobalId,
+    managers: Vec<ManagerInstance>,
+}
+
+impl ExtWorkspaceState {
+    pub fn new(display: &DisplayHandle) -> Self {
+        let global = display.create_global::<Smallvil, ExtWorkspaceManagerV1, _>(1, ());
+        Self { _global: global, managers: Vec::new() }
+    }
+}
+
+impl Smallvil {
+    /// Brings every bound ext-workspace manager in sync with the internal workspaces.
+    /// Must be called whenever workspaces are created, removed, switched or change state.
+    pub fn workspaces_changed(&mut self) {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        let workspaces: Vec<(u32, Output, u32, ext_workspace_handle_v1::State)> = self
+            .workspaces
+            .iter()
+            .filter(|workspace| outputs.contains(&workspace.output))
+            .map(|workspace| {
+                let coordinate = self
+                    .workspaces
+                    .iter()
+                    .filter(|other| other.output == workspace.output && other.id < workspace.id)
+                    .count();
+                let mut state = ext_workspace_handle_v1::State::empty();
+                if workspace.active {
+                    state |= ext_workspace_handle_v1::State::Active;
+                }
+                if workspace.urgent {
+                    state |= ext_workspace_handle_v1::State::Urgent;
+                }
+                (
+                    workspace.id,
+                    workspace.output.clone(),
+                    u32::try_from(coordinate).unwrap_or(u32::MAX),
+                    state,
+                )
+            })
+            .collect();
+
+        let dh = self.display_handle.clone();
+        for instance in &mut self.ext_workspace_state.managers {
+            instance.sync(&dh, &outputs, &workspaces);
+        }
+    }
+
+    /// Sends `output_enter` for outputs a client binds after its workspace groups were announced.
+    pub fn ext_workspace_output_bound(&self, output: &Output, wl_output: &WlOutput) {
+        for instance in &self.ext_workspace_state.managers {
+            if instance.manager.client() != wl_output.client() {
+                continue;
+            }
+
+            let mut entered = false;
+            for (group_output, group) in &instance.groups {
+                if group_output == output {
+                    group.output_enter(wl_output);
+                    entered = true;
+                }
+            }
+            if entered {
+                instance.manager.done();
+            }
+        }
+    }
+}
+
+impl ManagerInstance {
+    fn sync(
+        &mut self,
+        dh: &DisplayHandle,
+        outputs: &[Output],
+        workspaces: &[(u32, Output, u32, ext_workspace_handle_v1::State)],
+    ) {
+        let Some(client) = self.manager.client() else {
+            return;
+        };
+        let mut changed = false;
+
+        for output in outputs {
+            if self.groups.iter().any(|(known, _)| known == output) {
+                continue;
+            }
+
+            let Ok(group) = client.create_resource::<ExtWorkspaceGroupHandleV1, _, Smallvil>(
+                dh,
+                self.manager.version(),
+                (),
+            ) else {
+                continue;
+            };
+            self.manager.workspace_group(&group);
+            group.capabilities(GroupCapabilities::empty());
+            for wl_output in output.client_outputs(&client) {
+                group.output_enter(&wl_output);
+            }
+            self.groups.push((output.clone(), group));
+            changed = true;
+        }
+
+        let groups = &self.groups;
+        let group_for = |output: &Output| {
+            groups.iter().find(|(known, _)| known == output).map(|(_, group)| group.clone())
+        };
+
+        self.workspaces.retain(|known| {
+            if workspaces.iter().any(|(id, _, _, _)| *id == known.id) {
+                return true;
+            }
+            if known.destroyed {
+                return false;
+            }
+
+            if let Some(group) = group_for(&known.output) {
+                group.workspace_leave(&known.handle);
+            }
+            known.handle.removed();
+            changed = true;
+            false
+        });
+
+        for (id, output, coordinate, state) in workspaces {
+            if let Some(known) = self.workspaces.iter_mut().find(|known| known.id == *id) {
+                if known.destroyed {
+                    continue;
+                }
+                if &known.output != output {
+                    if let Some(group) = group_for(&known.output) {
+                        group.workspace_leave(&known.handle);
+                    }
+                    if let Some(group) = group_for(output) {
+                        group.workspace_enter(&known.handle);
+                    }
+                    known.output = output.clone();
+                    changed = true;
+                }
+                if known.coordinate != *coordinate {
+                    known.handle.coordinates(coordinate.to_ne_bytes().to_vec());
+                    known.coordinate = *coordinate;
+                    changed = true;
+                }
+                if known.state != *state {
+                    known.handle.state(*state);
+                    known.state = *state;
+                    changed = true;
+                }
+                continue;
+            }
+
+            let Ok(handle) = client.create_resource::<ExtWorkspaceHandleV1, _, Smallvil>(
+                dh,
+                self.manager.version(),
+                (),
+            ) else {
+                continue;
+            };
+            self.manager.workspace(&handle);
+            handle.id(id.to_string());
+            handle.name(id.to_string());
+            handle.coordinates(coordinate.to_ne_bytes().to_vec());
+            handle.state(*state);
+            handle.capabilities(WorkspaceCapabilities::Activate);
+            if let Some(group) = group_for(output) {
+                group.workspace_enter(&handle);
+            }
+            self.workspaces.push(WorkspaceHandle {
+                id: *id,
+                handle,
+                output: output.clone(),
+                coordinate: *coordinate,
+                state: *state,
+                destroyed: false,
+            });
+            changed = true;
+        }
+
+        self.groups.retain(|(output, group)| {
+            if outputs.contains(output) {
+                return true;
+            }
+            group.removed();
+            changed = true;
+            false
+        });
+
+        if changed {
+            self.manager.done();
+        }
+    }
+}
+
+impl GlobalDispatch<ExtWorkspaceManagerV1, ()> for Smallvil {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtWorkspaceManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+        state.ext_workspace_state.managers.push(ManagerInstance {
+            manager,
+            groups: Vec::new(),
+            workspaces: Vec::new(),
+            pending_activation: None,
+        });
+
+        // A freshly bound manager knows nothing yet, so this sends the full current state.
+        state.workspaces_changed();
+    }
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtWorkspaceManagerV1,
+        request: ext_workspace_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_workspace_manager_v1::Request::Commit => {
+                let activation = state
+                    .ext_workspace_state
+                    .managers
+                    .iter_mut()
+                    .find(|instance| &instance.manager == resource)
+                    .and_then(|instance| instance.pending_activation.take());
+
+                if let Some(id) = activation {
+                    state.switch_workspace(id);
+                }
+            }
+            ext_workspace_manager_v1::Request::Stop => {
+                resource.finished();
+                state.ext_workspace_state.managers.retain(|instance| &instance.manager != resource);
+            }
+            _ => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        resource: &ExtWorkspaceManagerV1,
+        _data: &(),
+    ) {
+        state.ext_workspace_state.managers.retain(|instance| &instance.manager != resource);
+    }
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for Smallvil {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtWorkspaceGroupHandleV1,
+        _request: ext_workspace_group_handle_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Workspaces are created on demand by switching to them, so the create_workspace
+        // capability is never advertised and there is nothing to do for group requests.
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtWorkspaceHandleV1,
+        request: ext_workspace_handle_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_workspace_handle_v1::Request::Activate => {
+                for instance in &mut state.ext_workspace_state.managers {
+                    if let Some(known) = instance
+                        .workspaces
+                        .iter()
+                        .find(|known| &known.handle == resource && !known.destroyed)
+                    {
+                        instance.pending_activation = Some(known.id);
+                    }
+                }
+            }
+            ext_workspace_handle_v1::Request::Destroy => {
+                // Forgetting the handle would announce the workspace again on the next
+                // sync, so it is kept until the workspace itself goes away.
+                for instance in &mut state.ext_workspace_state.managers {
+                    for known in &mut instance.workspaces {
+                        if &known.handle == resource {
+                            known.destroyed = true;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
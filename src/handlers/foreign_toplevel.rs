@@ -0,0 +1,363 @@
+use smithay::{
+    desktop::Window,
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::foreign_toplevel::v1::server::{
+            zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+            zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            backend::{ClientId, GlobalId},
+            protocol::wl_output::WlOutput,
+        },
+    },
+};
+
+use crate::Smallvil;
+
+/// What a taskbar should currently know about one window.
+struct ToplevelInfo {
+    window: Window,
+    title: Option<String>,
+    app_id: Option<String>,
+    states: Vec<zwlr_foreign_toplevel_handle_v1::State>,
+    outputs: Vec<Output>,
+    parent: Option<Window>,
+}
+
+/// What a taskbar was last sent about one window.
+struct ToplevelHandle {
+    window: Window,
+    handle: ZwlrForeignToplevelHandleV1,
+    title: Option<String>,
+    app_id: Option<String>,
+    states: Vec<zwlr_foreign_toplevel_handle_v1::State>,
+    outputs: Vec<Output>,
+    parent: Option<Window>,
+}
+
+/// One bound `zwlr_foreign_toplevel_manager_v1` and the handles it was sent.
+struct ManagerInstance {
+    manager: ZwlrForeignToplevelManagerV1,
+    toplevels: Vec<ToplevelHandle>,
+}
+
+pub struct ForeignToplevelState {
+    _global: GlobalId,
+    managers: Vec<ManagerInstance>,
+}
+
+impl ForeignToplevelState {
+    pub fn new(display: &DisplayHandle) -> Self {
+        let global = display.create_global::<Smallvil, ZwlrForeignToplevelManagerV1, _>(3, ());
+        Self { _global: global, managers: Vec::new() }
+    }
+
+    fn window_for(&self, handle: &ZwlrForeignToplevelHandleV1) -> Option<Window> {
+        self.managers
+            .iter()
+            .flat_map(|instance| instance.toplevels.iter())
+            .find(|known| &known.handle == handle)
+            .map(|known| known.window.clone())
+    }
+}
+
+impl Smallvil {
+    /// Brings every bound foreign-toplevel manager in sync with the windows, minimized and
+    /// hidden ones included. Called after anything a taskbar shows may have changed.
+    pub fn foreign_toplevels_changed(&mut self) {
+        use zwlr_foreign_toplevel_handle_v1::State;
+
+        if self.foreign_toplevel_state.managers.is_empty() {
+            return;
+        }
+        let windows: Vec<ToplevelInfo> = self
+            .space
+            .elements()
+            .chain(self.workspaces.hidden_windows())
+            .chain(self.minimized.iter())
+            .map(|window| {
+                let mut states = Vec::new();
+                if crate::window::is_maximized(window) {
+                    states.push(State::Maximized);
+                }
+                if self.minimized.contains(window) {
+                    states.push(State::Minimized);
+                }
+                if window.toplevel().is_some_and(|toplevel| {
+                    self.active_surface.as_ref() == Some(toplevel.wl_surface())
+                }) {
+                    states.push(State::Activated);
+                }
+                if crate::window::is_fullscreen(window) {
+                    states.push(State::Fullscreen);
+                }
+                ToplevelInfo {
+                    window: window.clone(),
+                    title: crate::window::title(window),
+                    app_id: crate::window::app_id(window),
+                    states,
+                    outputs: self.space.outputs_for_element(window),
+                    parent: crate::window::parent_of(window)
+                        .and_then(|parent| self.window_for_surface(&parent)),
+                }
+            })
+            .collect();
+
+        let dh = self.display_handle.clone();
+        for instance in &mut self.foreign_toplevel_state.managers {
+            instance.sync(&dh, &windows);
+        }
+    }
+
+    /// Sends `output_enter` for outputs a client binds after its toplevels were announced.
+    pub fn foreign_toplevel_output_bound(&self, output: &Output, wl_output: &WlOutput) {
+        for instance in &self.foreign_toplevel_state.managers {
+            if instance.manager.client() != wl_output.client() {
+                continue;
+            }
+
+            for known in &instance.toplevels {
+                if known.outputs.contains(output) {
+                    known.handle.output_enter(wl_output);
+                    known.handle.done();
+                }
+            }
+        }
+    }
+}
+
+impl ManagerInstance {
+    fn sync(&mut self, dh: &DisplayHandle, windows: &[ToplevelInfo]) {
+        let Some(client) = self.manager.client() else {
+            return;
+        };
+        let version = self.manager.version();
+
+        self.toplevels.retain(|known| {
+            if windows.iter().any(|info| info.window == known.window) {
+                return true;
+            }
+            known.handle.closed();
+            false
+        });
+
+        let mut changed = Vec::new();
+        for info in windows {
+            let index = match self.toplevels.iter().position(|known| known.window == info.window) {
+                Some(index) => index,
+                None => {
+                    let Ok(handle) = client
+                        .create_resource::<ZwlrForeignToplevelHandleV1, _, Smallvil>(
+                            dh,
+                            version,
+                            (),
+                        )
+                    else {
+                        continue;
+                    };
+                    self.manager.toplevel(&handle);
+                    self.toplevels.push(ToplevelHandle {
+                        window: info.window.clone(),
+                        handle,
+                        title: None,
+                        app_id: None,
+                        states: Vec::new(),
+                        outputs: Vec::new(),
+                        parent: None,
+                    });
+                    // A new handle is announced with `done` even without a title yet.
+                    changed.push(self.toplevels.len() - 1);
+                    self.toplevels.len() - 1
+                }
+            };
+            let Some(known) = self.toplevels.get_mut(index) else {
+                continue;
+            };
+            let mut dirty = false;
+
+            if known.title != info.title {
+                known.handle.title(info.title.clone().unwrap_or_default());
+                known.title = info.title.clone();
+                dirty = true;
+            }
+            if known.app_id != info.app_id {
+                known.handle.app_id(info.app_id.clone().unwrap_or_default());
+                known.app_id = info.app_id.clone();
+                dirty = true;
+            }
+            if known.states != info.states {
+                // Fullscreen only exists from version 2 on.
+                let states: Vec<u8> = info
+                    .states
+                    .iter()
+                    .filter(|state| {
+                        version >= 2
+                            || **state != zwlr_foreign_toplevel_handle_v1::State::Fullscreen
+                    })
+                    .flat_map(|state| u32::from(*state).to_ne_bytes())
+                    .collect();
+                known.handle.state(states);
+                known.states = info.states.clone();
+                dirty = true;
+            }
+            if known.outputs != info.outputs {
+                for output in known.outputs.iter().filter(|output| !info.outputs.contains(output)) {
+                    for wl_output in output.client_outputs(&client) {
+                        known.handle.output_leave(&wl_output);
+                    }
+                }
+                for output in info.outputs.iter().filter(|output| !known.outputs.contains(output)) {
+                    for wl_output in output.client_outputs(&client) {
+                        known.handle.output_enter(&wl_output);
+                    }
+                }
+                known.outputs = info.outputs.clone();
+                dirty = true;
+            }
+            if dirty {
+                changed.push(index);
+            }
+        }
+
+        // Parents last, a dialog may be listed before the window it belongs to.
+        if version >= 3 {
+            for info in windows {
+                let Some(index) =
+                    self.toplevels.iter().position(|known| known.window == info.window)
+                else {
+                    continue;
+                };
+                let parent_handle = info
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| self.toplevels.iter().find(|known| &known.window == parent));
+                let Some(known) = self.toplevels.get(index) else {
+                    continue;
+                };
+                if known.parent.as_ref() == parent_handle.map(|parent| &parent.window) {
+                    continue;
+                }
+                known.handle.parent(parent_handle.map(|parent| &parent.handle));
+                let parent = parent_handle.map(|parent| parent.window.clone());
+                if let Some(known) = self.toplevels.get_mut(index) {
+                    known.parent = parent;
+                }
+                changed.push(index);
+            }
+        }
+
+        changed.sort_unstable();
+        changed.dedup();
+        for index in changed {
+            if let Some(known) = self.toplevels.get(index) {
+                known.handle.done();
+            }
+        }
+    }
+}
+
+impl GlobalDispatch<ZwlrForeignToplevelManagerV1, ()> for Smallvil {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrForeignToplevelManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+        state
+            .foreign_toplevel_state
+            .managers
+            .push(ManagerInstance { manager, toplevels: Vec::new() });
+
+        // A freshly bound manager knows nothing yet, so this announces every window.
+        state.foreign_toplevels_changed();
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrForeignToplevelManagerV1,
+        request: zwlr_foreign_toplevel_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Request::Stop = request {
+            resource.finished();
+            state.foreign_toplevel_state.managers.retain(|instance| &instance.manager != resource);
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        resource: &ZwlrForeignToplevelManagerV1,
+        _data: &(),
+    ) {
+        state.foreign_toplevel_state.managers.retain(|instance| &instance.manager != resource);
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrForeignToplevelHandleV1,
+        request: zwlr_foreign_toplevel_handle_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        use zwlr_foreign_toplevel_handle_v1::Request;
+
+        if let Request::Destroy = request {
+            for instance in &mut state.foreign_toplevel_state.managers {
+                instance.toplevels.retain(|known| &known.handle != resource);
+            }
+            return;
+        }
+        // Requests on the handle of a closed window are ignored.
+        let Some(window) = state.foreign_toplevel_state.window_for(resource) else {
+            return;
+        };
+
+        match request {
+            Request::SetMaximized => state.set_maximized(&window, true),
+            Request::UnsetMaximized => state.set_maximized(&window, false),
+            Request::SetMinimized => state.minimize_window(&window),
+            Request::UnsetMinimized => state.unminimize_window(&window),
+            Request::Activate { .. } => state.focus_window(&window),
+            Request::Close => state.close_window(&window),
+            Request::SetFullscreen { output } => {
+                let output = output
+                    .as_ref()
+                    .and_then(Output::from_resource)
+                    .or_else(|| state.space.outputs_for_element(&window).into_iter().next())
+                    .or_else(|| state.current_output());
+                if output.is_some() {
+                    state.set_fullscreen(&window, output);
+                }
+            }
+            Request::UnsetFullscreen => state.set_fullscreen(&window, None),
+            // Only used for minimize animations, which there are none of.
+            _ => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        resource: &ZwlrForeignToplevelHandleV1,
+        _data: &(),
+    ) {
+        for instance in &mut state.foreign_toplevel_state.managers {
+            instance.toplevels.retain(|known| &known.handle != resource);
+        }
+    }
+}
@@ -1,18 +1,35 @@
 mod compositor;
+pub mod ext_workspace;
+pub mod foreign_toplevel;
+pub mod toplevel_drag;
+pub mod virtual_pointer;
 mod xdg_shell;
 
-use crate::Smallvil;
+use std::os::unix::io::OwnedFd;
 
+use crate::{Smallvil, seat::DndIcon};
+
+use smithay::desktop::find_popup_root_surface;
 use smithay::input::{Seat, SeatHandler, SeatState};
+use smithay::output::Output;
 use smithay::reexports::wayland_server::Resource;
-use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::protocol::{
+    wl_data_source::WlDataSource, wl_output::WlOutput, wl_surface::WlSurface,
+};
+use smithay::utils::Point;
+use smithay::wayland::idle_inhibit::IdleInhibitHandler;
+use smithay::wayland::idle_notify::{IdleNotifierHandler, IdleNotifierState};
 use smithay::wayland::output::OutputHandler;
-use smithay::wayland::selection::SelectionHandler;
 use smithay::wayland::selection::data_device::{
     ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
     set_data_device_focus,
 };
-use smithay::{delegate_data_device, delegate_output, delegate_seat};
+use smithay::wayland::selection::{SelectionHandler, SelectionSource, SelectionTarget};
+use smithay::wayland::xdg_foreign::{XdgForeignHandler, XdgForeignState};
+use smithay::{
+    delegate_alpha_modifier, delegate_data_device, delegate_idle_inhibit, delegate_idle_notify,
+    delegate_output, delegate_seat, delegate_xdg_foreign,
+};
 
 impl SeatHandler for Smallvil {
     type KeyboardFocus = WlSurface;
@@ -28,15 +45,29 @@ impl SeatHandler for Smallvil {
         _seat: &Seat<Self>,
         image: smithay::input::pointer::CursorImageStatus,
     ) {
-        self.cursor_status = image;
+        self.set_cursor_status(image);
     }
 
     fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&WlSurface>) {
+        // Focus moves when a client exits, its clipboard may have to be kept.
+        self.schedule_clipboard_check();
+
         let dh = &self.display_handle;
         let client = focused.and_then(|s| dh.get_client(s.id()).ok());
         set_data_device_focus(dh, seat, client);
 
-        self.active_surface = focused.cloned();
+        // A grabbed popup keeps its toplevel active.
+        self.active_surface = focused.map(|surface| {
+            self.popups
+                .find_popup(surface)
+                .and_then(|popup| find_popup_root_surface(&popup).ok())
+                .unwrap_or_else(|| surface.clone())
+        });
+        if let Some(window) =
+            self.active_surface.as_ref().and_then(|surface| self.window_for_surface(surface))
+        {
+            crate::window::mark_focused(&window);
+        }
         self.arrange_windows_tiled();
         self.request_redraw_all();
     }
@@ -45,7 +76,29 @@ impl SeatHandler for Smallvil {
 delegate_seat!(Smallvil);
 
 impl SelectionHandler for Smallvil {
-    type SelectionUserData = ();
+    type SelectionUserData = crate::clipboard::ClipboardContents;
+
+    fn new_selection(
+        &mut self,
+        ty: SelectionTarget,
+        source: Option<SelectionSource>,
+        _seat: Seat<Self>,
+    ) {
+        if ty == SelectionTarget::Clipboard {
+            self.clipboard_selection_changed(source.as_ref());
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        _ty: SelectionTarget,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        user_data: &Self::SelectionUserData,
+    ) {
+        self.send_clipboard(&mime_type, fd, user_data);
+    }
 }
 
 impl DataDeviceHandler for Smallvil {
@@ -54,10 +107,62 @@ impl DataDeviceHandler for Smallvil {
     }
 }
 
-impl ClientDndGrabHandler for Smallvil {}
+impl ClientDndGrabHandler for Smallvil {
+    fn started(
+        &mut self,
+        source: Option<WlDataSource>,
+        icon: Option<WlSurface>,
+        _seat: Seat<Self>,
+    ) {
+        self.seat.dnd_icon = icon.map(|surface| DndIcon { surface, offset: Point::default() });
+        if let Some(source) = source {
+            self.start_toplevel_drag(&source);
+        }
+        self.request_redraw_all();
+    }
+
+    /// Also called when the drag is cancelled, `validated` is false then.
+    fn dropped(&mut self, _target: Option<WlSurface>, validated: bool, _seat: Seat<Self>) {
+        self.seat.dnd_icon = None;
+        self.end_toplevel_drag(validated);
+        self.request_redraw_all();
+    }
+}
 impl ServerDndGrabHandler for Smallvil {}
 
 delegate_data_device!(Smallvil);
 
-impl OutputHandler for Smallvil {}
+impl OutputHandler for Smallvil {
+    fn output_bound(&mut self, output: Output, wl_output: WlOutput) {
+        self.ext_workspace_output_bound(&output, &wl_output);
+        self.foreign_toplevel_output_bound(&output, &wl_output);
+    }
+}
 delegate_output!(Smallvil);
+
+delegate_alpha_modifier!(Smallvil);
+
+impl XdgForeignHandler for Smallvil {
+    fn xdg_foreign_state(&mut self) -> &mut XdgForeignState {
+        &mut self.xdg_foreign_state
+    }
+}
+delegate_xdg_foreign!(Smallvil);
+
+impl IdleNotifierHandler for Smallvil {
+    fn idle_notifier_state(&mut self) -> &mut IdleNotifierState<Self> {
+        &mut self.idle_notifier_state
+    }
+}
+delegate_idle_notify!(Smallvil);
+
+impl IdleInhibitHandler for Smallvil {
+    fn inhibit(&mut self, surface: WlSurface) {
+        self.add_idle_inhibitor(surface);
+    }
+
+    fn uninhibit(&mut self, surface: WlSurface) {
+        self.remove_idle_inhibitor(&surface);
+    }
+}
+delegate_idle_inhibit!(Smallvil);
@@ -0,0 +1,248 @@
+use smithay::{
+    reexports::{
+        wayland_protocols::xdg::toplevel_drag::v1::server::{
+            xdg_toplevel_drag_manager_v1::{self, XdgToplevelDragManagerV1},
+            xdg_toplevel_drag_v1::{self, XdgToplevelDragV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+            backend::{ClientId, GlobalId},
+            protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface},
+        },
+    },
+    utils::{Logical, Point},
+};
+
+use crate::{Smallvil, config::ToplevelDropMode};
+
+/// A toplevel attached to a drag via `xdg_toplevel_drag_v1.attach`.
+pub struct AttachedToplevel {
+    pub surface: WlSurface,
+    pub offset: Point<i32, Logical>,
+    /// Whether the window was floating before the drag picked it up, restored on cancel.
+    pub was_floating: bool,
+    /// Where the window was before the drag picked it up, restored on cancel.
+    pub was_location: Option<Point<i32, Logical>>,
+}
+
+pub struct ToplevelDrag {
+    pub resource: XdgToplevelDragV1,
+    pub source: WlDataSource,
+    pub attached: Option<AttachedToplevel>,
+    pub active: bool,
+}
+
+pub struct ToplevelDragState {
+    _global: GlobalId,
+    drags: Vec<ToplevelDrag>,
+    /// Set while a pointer button release is delivered. A drag that ends then was dropped
+    /// by the user, even if nothing accepted it, one that ends otherwise was cancelled.
+    pub button_released: bool,
+}
+
+impl ToplevelDragState {
+    pub fn new(display: &DisplayHandle) -> Self {
+        let global = display.create_global::<Smallvil, XdgToplevelDragManagerV1, _>(1, ());
+        Self { _global: global, drags: Vec::new(), button_released: false }
+    }
+
+    /// The toplevel moved by the drag that is currently in progress, if any.
+    pub fn dragged_surface(&self) -> Option<&WlSurface> {
+        self.active_drag().and_then(|drag| drag.attached.as_ref()).map(|attached| &attached.surface)
+    }
+
+    fn active_drag(&self) -> Option<&ToplevelDrag> {
+        self.drags.iter().find(|drag| drag.active)
+    }
+}
+
+impl GlobalDispatch<XdgToplevelDragManagerV1, ()> for Smallvil {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<XdgToplevelDragManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<XdgToplevelDragManagerV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &XdgToplevelDragManagerV1,
+        request: xdg_toplevel_drag_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_drag_manager_v1::Request::GetXdgToplevelDrag { id, data_source } => {
+                if state.toplevel_drag_state.drags.iter().any(|drag| drag.source == data_source) {
+                    resource.post_error(
+                        xdg_toplevel_drag_manager_v1::Error::InvalidSource,
+                        "data_source already used for toplevel drag",
+                    );
+                    return;
+                }
+
+                let drag = data_init.init(id, ());
+                state.toplevel_drag_state.drags.push(ToplevelDrag {
+                    resource: drag,
+                    source: data_source,
+                    attached: None,
+                    active: false,
+                });
+            }
+            xdg_toplevel_drag_manager_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<XdgToplevelDragV1, ()> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &XdgToplevelDragV1,
+        request: xdg_toplevel_drag_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel_drag_v1::Request::Attach { toplevel, x_offset, y_offset } => {
+                let Some(surface) = state
+                    .xdg_shell_state
+                    .toplevel_surfaces()
+                    .iter()
+                    .find(|surface| surface.xdg_toplevel() == &toplevel)
+                    .map(|surface| surface.wl_surface().clone())
+                else {
+                    return;
+                };
+
+                let window = state.window_for_surface(&surface);
+                let was_floating = window.as_ref().is_some_and(crate::window::is_floating);
+                let was_location =
+                    window.as_ref().and_then(|window| state.space.element_location(window));
+
+                let Some(drag) = state
+                    .toplevel_drag_state
+                    .drags
+                    .iter_mut()
+                    .find(|drag| &drag.resource == resource)
+                else {
+                    return;
+                };
+
+                if drag.attached.as_ref().is_some_and(|attached| attached.surface.is_alive()) {
+                    resource.post_error(
+                        xdg_toplevel_drag_v1::Error::ToplevelAttached,
+                        "valid toplevel already attached",
+                    );
+                    return;
+                }
+
+                drag.attached = Some(AttachedToplevel {
+                    surface,
+                    offset: (x_offset, y_offset).into(),
+                    was_floating,
+                    was_location,
+                });
+
+                if drag.active {
+                    state.arrange_windows_tiled();
+                    if let Some(pointer) = state.seat.pointer() {
+                        state.update_toplevel_drag(pointer.current_location());
+                    }
+                }
+            }
+            xdg_toplevel_drag_v1::Request::Destroy => {
+                if state
+                    .toplevel_drag_state
+                    .drags
+                    .iter()
+                    .any(|drag| &drag.resource == resource && drag.active)
+                {
+                    resource
+                        .post_error(xdg_toplevel_drag_v1::Error::OngoingDrag, "drag has not ended");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &XdgToplevelDragV1, _data: &()) {
+        state.toplevel_drag_state.drags.retain(|drag| &drag.resource != resource);
+    }
+}
+
+impl Smallvil {
+    /// Called when a client starts a drag-and-drop operation with `source`.
+    pub fn start_toplevel_drag(&mut self, source: &WlDataSource) {
+        let Some(drag) =
+            self.toplevel_drag_state.drags.iter_mut().find(|drag| &drag.source == source)
+        else {
+            return;
+        };
+        drag.active = true;
+
+        self.arrange_windows_tiled();
+        if let Some(pointer) = self.seat.pointer() {
+            self.update_toplevel_drag(pointer.current_location());
+        }
+    }
+
+    /// Moves the attached toplevel along with the pointer and keeps it above everything else.
+    pub fn update_toplevel_drag(&mut self, pointer_location: Point<f64, Logical>) {
+        let Some(attached) =
+            self.toplevel_drag_state.active_drag().and_then(|drag| drag.attached.as_ref())
+        else {
+            return;
+        };
+        let Some(window) = self.window_for_surface(&attached.surface) else {
+            return;
+        };
+
+        let location = pointer_location.to_i32_round() - attached.offset;
+        self.space.map_element(window, location, false);
+        self.request_redraw_all();
+    }
+
+    /// Finishes the active toplevel drag. A drop places the window according to
+    /// `toplevel_drag_drop`, whether a client accepted it or it landed on empty space. A
+    /// cancelled drag puts it back the way it was.
+    pub fn end_toplevel_drag(&mut self, validated: bool) {
+        let dropped = validated || self.toplevel_drag_state.button_released;
+        let Some(index) = self.toplevel_drag_state.drags.iter().position(|drag| drag.active) else {
+            return;
+        };
+
+        let drag = &mut self.toplevel_drag_state.drags[index];
+        drag.active = false;
+        let Some(attached) = drag.attached.take() else {
+            return;
+        };
+
+        if let Some(window) = self.window_for_surface(&attached.surface) {
+            if dropped {
+                crate::window::window_data(&window).floating =
+                    self.toplevel_drop_mode == ToplevelDropMode::Float;
+            } else {
+                crate::window::window_data(&window).floating = attached.was_floating;
+                if attached.was_floating
+                    && let Some(location) = attached.was_location
+                {
+                    self.space.map_element(window, location, false);
+                }
+            }
+        }
+
+        self.arrange_windows_tiled();
+        self.request_redraw_all();
+    }
+}
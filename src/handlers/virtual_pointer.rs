@@ -0,0 +1,188 @@
+use std::sync::{Mutex, PoisonError};
+
+use smithay::{
+    backend::input::{Axis, AxisSource, ButtonState},
+    input::pointer::AxisFrame,
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::virtual_pointer::v1::server::{
+            zwlr_virtual_pointer_manager_v1::{self, ZwlrVirtualPointerManagerV1},
+            zwlr_virtual_pointer_v1::{self, ZwlrVirtualPointerV1},
+        },
+        wayland_server::{
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+            backend::GlobalId,
+            protocol::wl_pointer::{self, AxisSource as WlAxisSource},
+        },
+    },
+    utils::{Logical, Point},
+};
+
+use crate::Smallvil;
+
+pub struct VirtualPointerManagerState {
+    _global: GlobalId,
+}
+
+impl VirtualPointerManagerState {
+    pub fn new(display: &DisplayHandle) -> Self {
+        let global = display.create_global::<Smallvil, ZwlrVirtualPointerManagerV1, _>(2, ());
+        Self { _global: global }
+    }
+}
+
+/// Per virtual pointer state. Axis events are collected until the client sends `frame`.
+pub struct VirtualPointerData {
+    output: Option<Output>,
+    pending_axis: Mutex<Option<AxisFrame>>,
+}
+
+impl GlobalDispatch<ZwlrVirtualPointerManagerV1, ()> for Smallvil {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrVirtualPointerManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrVirtualPointerManagerV1, ()> for Smallvil {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrVirtualPointerManagerV1,
+        request: zwlr_virtual_pointer_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        // Only one seat exists, so the seat argument is ignored.
+        let (id, output) = match request {
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. } => (id, None),
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                id,
+                output,
+                ..
+            } => (id, output.as_ref().and_then(Output::from_resource)),
+            _ => return,
+        };
+
+        data_init.init(id, VirtualPointerData { output, pending_axis: Mutex::new(None) });
+    }
+}
+
+impl Dispatch<ZwlrVirtualPointerV1, VirtualPointerData> for Smallvil {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrVirtualPointerV1,
+        request: zwlr_virtual_pointer_v1::Request,
+        data: &VirtualPointerData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_virtual_pointer_v1::Request::Motion { time, dx, dy } => {
+                state.pointer_motion_relative((dx, dy).into(), time);
+            }
+            zwlr_virtual_pointer_v1::Request::MotionAbsolute { time, x, y, x_extent, y_extent } => {
+                if x_extent == 0 || y_extent == 0 {
+                    return;
+                }
+
+                let output = data.output.clone().or_else(|| state.space.outputs().next().cloned());
+                let Some(output_geo) =
+                    output.and_then(|output| state.space.output_geometry(&output))
+                else {
+                    return;
+                };
+
+                let relative: Point<f64, Logical> = (
+                    f64::from(x) / f64::from(x_extent) * f64::from(output_geo.size.w),
+                    f64::from(y) / f64::from(y_extent) * f64::from(output_geo.size.h),
+                )
+                    .into();
+                state.pointer_motion_to(output_geo.loc.to_f64() + relative, time);
+            }
+            zwlr_virtual_pointer_v1::Request::Button { time, button, state: button_state } => {
+                let button_state = match button_state {
+                    WEnum::Value(wl_pointer::ButtonState::Pressed) => ButtonState::Pressed,
+                    WEnum::Value(wl_pointer::ButtonState::Released) => ButtonState::Released,
+                    _ => return,
+                };
+                state.pointer_button(button, button_state, time);
+            }
+            zwlr_virtual_pointer_v1::Request::Axis { time, axis, value } => {
+                let Some(axis) = axis_from_wl(resource, axis) else {
+                    return;
+                };
+                update_pending_axis(data, time, |frame| frame.value(axis, value));
+            }
+            zwlr_virtual_pointer_v1::Request::AxisSource { axis_source } => {
+                let source = match axis_source {
+                    WEnum::Value(WlAxisSource::Wheel) => AxisSource::Wheel,
+                    WEnum::Value(WlAxisSource::Finger) => AxisSource::Finger,
+                    WEnum::Value(WlAxisSource::Continuous) => AxisSource::Continuous,
+                    WEnum::Value(WlAxisSource::WheelTilt) => AxisSource::WheelTilt,
+                    _ => {
+                        resource.post_error(
+                            zwlr_virtual_pointer_v1::Error::InvalidAxisSource,
+                            "invalid axis source",
+                        );
+                        return;
+                    }
+                };
+                update_pending_axis(data, 0, |frame| frame.source(source));
+            }
+            zwlr_virtual_pointer_v1::Request::AxisStop { time, axis } => {
+                let Some(axis) = axis_from_wl(resource, axis) else {
+                    return;
+                };
+                update_pending_axis(data, time, |frame| frame.stop(axis));
+            }
+            zwlr_virtual_pointer_v1::Request::AxisDiscrete { time, axis, value, discrete } => {
+                let Some(axis) = axis_from_wl(resource, axis) else {
+                    return;
+                };
+                update_pending_axis(data, time, |frame| {
+                    frame.value(axis, value).v120(axis, discrete.saturating_mul(120))
+                });
+            }
+            zwlr_virtual_pointer_v1::Request::Frame => {
+                state.idle_activity();
+                let pending =
+                    data.pending_axis.lock().unwrap_or_else(PoisonError::into_inner).take();
+                if let Some(frame) = pending {
+                    state.pointer_axis(frame);
+                }
+                state.pointer_frame();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn axis_from_wl(resource: &ZwlrVirtualPointerV1, axis: WEnum<wl_pointer::Axis>) -> Option<Axis> {
+    match axis {
+        WEnum::Value(wl_pointer::Axis::VerticalScroll) => Some(Axis::Vertical),
+        WEnum::Value(wl_pointer::Axis::HorizontalScroll) => Some(Axis::Horizontal),
+        _ => {
+            resource.post_error(zwlr_virtual_pointer_v1::Error::InvalidAxis, "invalid axis");
+            None
+        }
+    }
+}
+
+fn update_pending_axis(
+    data: &VirtualPointerData,
+    time: u32,
+    update: impl FnOnce(AxisFrame) -> AxisFrame,
+) {
+    let mut pending = data.pending_axis.lock().unwrap_or_else(PoisonError::into_inner);
+    let frame = pending.take().unwrap_or_else(|| AxisFrame::new(time));
+    *pending = Some(update(frame));
+}
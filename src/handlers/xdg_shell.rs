@@ -1,33 +1,52 @@
 use smithay::{
     delegate_xdg_decoration, delegate_xdg_shell,
     desktop::{
-        PopupKind, PopupManager, Space, Window, find_popup_root_surface, get_popup_toplevel_coords,
+        PopupKeyboardGrab, PopupKind, PopupManager, PopupPointerGrab, PopupUngrabStrategy, Space,
+        Window, find_popup_root_surface, get_popup_toplevel_coords,
     },
+    input::{
+        Seat,
+        keyboard::KeyboardHandle,
+        pointer::{CursorImageStatus, Focus, PointerHandle},
+    },
+    output::Output,
     reexports::{
         wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode,
         wayland_protocols::xdg::shell::server::xdg_toplevel,
-        wayland_server::protocol::{wl_seat, wl_surface::WlSurface},
+        wayland_server::{
+            Resource,
+            protocol::{wl_output::WlOutput, wl_seat, wl_surface::WlSurface},
+        },
     },
-    utils::Serial,
+    utils::{Rectangle, SERIAL_COUNTER, Serial},
     wayland::{
         compositor::with_states,
         shell::xdg::{
-            PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
-            XdgToplevelSurfaceData, decoration::XdgDecorationHandler,
+            PopupSurface, PositionerState, ShellClient, ToplevelSurface, XdgShellHandler,
+            XdgShellState, XdgToplevelSurfaceData, decoration::XdgDecorationHandler,
         },
     },
 };
 
-use crate::Smallvil;
+use crate::{Smallvil, grabs::ResizeSurfaceGrab};
 
 impl XdgShellHandler for Smallvil {
     fn xdg_shell_state(&mut self) -> &mut XdgShellState {
         &mut self.xdg_shell_state
     }
 
+    fn client_pong(&mut self, client: ShellClient) {
+        self.clear_ping(&client);
+    }
+
+    fn client_destroyed(&mut self, client: ShellClient) {
+        self.clear_ping(&client);
+    }
+
     fn new_toplevel(&mut self, surface: ToplevelSurface) {
         let wl_surface = surface.wl_surface().clone();
         let window = Window::new_wayland_window(surface);
+        crate::window::window_data(&window).workspace = self.current_workspace().unwrap_or(1);
         self.space.map_element(window, (0, 0), false);
         self.active_surface = Some(wl_surface);
         self.arrange_windows_tiled();
@@ -65,16 +84,211 @@ impl XdgShellHandler for Smallvil {
         serial: Serial,
         edges: xdg_toplevel::ResizeEdge,
     ) {
-        let _ = (surface, seat, serial, edges);
+        let Some(seat) = Seat::<Self>::from_resource(&seat) else {
+            return;
+        };
+        let Some(pointer) = seat.get_pointer() else {
+            return;
+        };
+        let wl_surface = surface.wl_surface();
+
+        // The serial has to belong to the button press that is still holding the
+        // pointer, on a surface of the requesting client.
+        if !pointer.has_grab(serial) {
+            return;
+        }
+        let Some(start_data) = pointer.grab_start_data() else {
+            return;
+        };
+        if !start_data
+            .focus
+            .as_ref()
+            .is_some_and(|(focus, _)| focus.id().same_client_as(&wl_surface.id()))
+        {
+            return;
+        }
+
+        let Some(window) = self.window_for_surface(wl_surface) else {
+            return;
+        };
+        // Tiled, maximized and fullscreen windows are sized by the compositor.
+        if !crate::window::is_floating(&window)
+            || crate::window::is_fullscreen(&window)
+            || crate::window::is_maximized(&window)
+        {
+            tracing::debug!("Ignoring resize request for a window the layout sizes");
+            return;
+        }
+        let Some(location) = self.space.element_location(&window) else {
+            return;
+        };
+
+        let initial_geometry = Rectangle::new(location, window.geometry().size);
+        let grab = ResizeSurfaceGrab::start(start_data, window, edges, initial_geometry);
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+        // The client lost pointer focus to the grab, so the arrows are ours to show.
+        self.set_cursor_status(CursorImageStatus::Named(crate::grabs::resize_cursor(edges)));
+        self.request_redraw_all();
     }
 
-    fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: Serial) {}
+    fn grab(&mut self, surface: PopupSurface, seat: wl_seat::WlSeat, serial: Serial) {
+        let Some(seat) = Seat::<Self>::from_resource(&seat) else {
+            return;
+        };
+        let popup = PopupKind::Xdg(surface);
+        let Ok(root) = find_popup_root_surface(&popup) else {
+            return;
+        };
+
+        // Only the client that currently has input focus may grab, otherwise any
+        // client could steal the keyboard by opening a popup.
+        let focused_client = |focus: Option<WlSurface>| {
+            focus
+                .and_then(|focus| focus.client())
+                .is_some_and(|client| root.client() == Some(client))
+        };
+        let keyboard = seat.get_keyboard();
+        let pointer = seat.get_pointer();
+        if !focused_client(keyboard.as_ref().and_then(KeyboardHandle::current_focus))
+            && !focused_client(pointer.as_ref().and_then(PointerHandle::current_focus))
+        {
+            let _ = PopupManager::dismiss_popup(&root, &popup);
+            return;
+        }
+
+        let mut grab = match self.popups.grab_popup(root, popup, &seat, serial) {
+            Ok(grab) => grab,
+            Err(err) => {
+                tracing::debug!("Rejected popup grab: {err}");
+                return;
+            }
+        };
+
+        // A stale serial must not replace an unrelated grab. Nested popups carry the
+        // serial of the parent popup's grab, which is what previous_serial refers to.
+        if let Some(keyboard) = keyboard {
+            if keyboard.is_grabbed()
+                && !(keyboard.has_grab(serial)
+                    || keyboard.has_grab(grab.previous_serial().unwrap_or(serial)))
+            {
+                grab.ungrab(PopupUngrabStrategy::All);
+                return;
+            }
+            keyboard.set_focus(self, grab.current_grab(), serial);
+            keyboard.set_grab(self, PopupKeyboardGrab::new(&grab), serial);
+        }
+
+        if let Some(pointer) = pointer {
+            if pointer.is_grabbed()
+                && !(pointer.has_grab(serial)
+                    || pointer.has_grab(grab.previous_serial().unwrap_or_else(|| grab.serial())))
+            {
+                grab.ungrab(PopupUngrabStrategy::All);
+                return;
+            }
+            pointer.set_grab(self, PopupPointerGrab::new(&grab), serial, Focus::Keep);
+        }
+    }
+
+    fn fullscreen_request(&mut self, surface: ToplevelSurface, output: Option<WlOutput>) {
+        let Some(window) = self.window_for_surface(surface.wl_surface()) else {
+            surface.send_configure();
+            return;
+        };
+
+        let output = output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.space.outputs_for_element(&window).into_iter().next())
+            .or_else(|| self.current_output());
+        let Some(output) = output else {
+            surface.send_configure();
+            return;
+        };
+
+        self.set_fullscreen(&window, Some(output));
+    }
+
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_maximized(&window, true),
+            None => surface.send_configure(),
+        }
+    }
+
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_maximized(&window, false),
+            None => surface.send_configure(),
+        }
+    }
+
+    fn minimize_request(&mut self, surface: ToplevelSurface) {
+        if let Some(window) = self.window_for_surface(surface.wl_surface()) {
+            self.minimize_window(&window);
+        }
+    }
+
+    fn unfullscreen_request(&mut self, surface: ToplevelSurface) {
+        if let Some(window) = self.window_for_surface(surface.wl_surface()) {
+            self.set_fullscreen(&window, None);
+        }
+    }
+
+    fn parent_changed(&mut self, surface: ToplevelSurface) {
+        let Some(window) = self.window_for_surface(surface.wl_surface()) else {
+            return;
+        };
+
+        let parent = surface.parent();
+        let has_parent = parent.is_some();
+        {
+            let mut data = crate::window::window_data(&window);
+            data.parent = parent;
+            data.floating = has_parent;
+        }
+
+        if has_parent {
+            self.float_over_parent(&window);
+        }
+        self.arrange_windows_tiled();
+
+        self.request_redraw_all();
+    }
 
     fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        self.schedule_clipboard_check();
+
+        // Don't leave the pointer grabbed by a resize of a window that is gone.
+        let resizing = self.window_for_surface(surface.wl_surface()).is_some_and(|window| {
+            crate::window::window_data(&window).resize.is_some_and(|anchor| anchor.is_active())
+        });
+        if resizing && let Some(pointer) = self.seat.pointer() {
+            let time = u32::try_from(self.start_time.elapsed().as_millis()).unwrap_or(u32::MAX);
+            pointer.unset_grab(self, SERIAL_COUNTER.next_serial(), time);
+        }
+
         if self.active_surface.as_ref().is_some_and(|active| active == surface.wl_surface()) {
-            self.active_surface = None;
+            // Closing a dialog hands focus back to the window it belongs to.
+            let parent = self
+                .window_for_surface(surface.wl_surface())
+                .and_then(|window| crate::window::parent_of(&window))
+                .filter(|parent| {
+                    self.space.elements().any(|window| {
+                        window.toplevel().is_some_and(|toplevel| toplevel.wl_surface() == parent)
+                    })
+                });
+            match parent {
+                Some(parent) => self.set_keyboard_focus(Some(parent)),
+                None => self.active_surface = None,
+            }
         }
+        self.minimized.retain(|window| {
+            window.toplevel().is_some_and(|toplevel| toplevel.wl_surface() != surface.wl_surface())
+        });
+        self.remove_empty_workspaces();
         self.arrange_windows_tiled();
+        self.workspaces_changed();
         self.request_redraw_all();
     }
 }
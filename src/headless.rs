@@ -0,0 +1,206 @@
+//! A backend without a display or input devices, for CI and integration tests. It creates
+//! virtual outputs side by side and runs the usual Wayland socket, tiling and IPC. With
+//! `--headless-render` frames are drawn into offscreen buffers by a surfaceless GLES
+//! renderer, so screenshots show what a real output would.
+
+use std::time::Duration;
+
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        egl::{EGLContext, EGLDisplay, native::EGLSurfacelessDisplay},
+        renderer::{
+            Bind, Offscreen,
+            damage::OutputDamageTracker,
+            gles::{GlesRenderer, GlesTexture},
+        },
+    },
+    output::{Mode, Output, PhysicalProperties, Subpixel},
+    reexports::{
+        calloop::{
+            EventLoop,
+            timer::{TimeoutAction, Timer},
+        },
+        wayland_server::Display,
+    },
+    utils::Transform,
+};
+
+use crate::{Smallvil, cli::Options, state::frame_interval};
+
+const DEFAULT_SIZE: (i32, i32) = (1920, 1080);
+const REFRESH_MHZ: i32 = 60_000;
+
+pub struct HeadlessData {
+    renderer: Option<GlesRenderer>,
+    outputs: Vec<HeadlessOutput>,
+    /// A frame timer is armed, it draws the dirty outputs when it fires.
+    frame_scheduled: bool,
+}
+
+struct HeadlessOutput {
+    output: Output,
+    damage_tracker: OutputDamageTracker,
+    /// The offscreen buffer frames are drawn into, created with the first one.
+    buffer: Option<GlesTexture>,
+    dirty: bool,
+}
+
+pub fn run_headless(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut event_loop: EventLoop<Smallvil> = EventLoop::try_new()?;
+    let display: Display<Smallvil> = Display::new()?;
+
+    let mut state =
+        Smallvil::new(&mut event_loop, display, crate::seat::HEADLESS_SEAT, options.config_path());
+
+    let renderer = if options.headless_render { Some(surfaceless_renderer()?) } else { None };
+
+    let (width, height) = options.headless_size.unwrap_or(DEFAULT_SIZE);
+    let mode = Mode { size: (width, height).into(), refresh: REFRESH_MHZ };
+    let mut outputs = Vec::new();
+    for index in 0..options.headless_outputs.unwrap_or(1) {
+        let output = Output::new(
+            format!("HEADLESS-{}", index + 1),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "ripwm".into(),
+                model: "Headless".into(),
+            },
+        );
+        let _global = output.create_global::<Smallvil>(&state.display_handle);
+        let x = i32::try_from(index).unwrap_or(i32::MAX).saturating_mul(width);
+        output.change_current_state(Some(mode), Some(Transform::Normal), None, Some((x, 0).into()));
+        output.set_preferred(mode);
+
+        state.space.map_output(&output, (x, 0));
+        state.ensure_output_workspace(&output);
+        tracing::info!("Created headless output {} at {x},0", output.name());
+
+        let damage_tracker = OutputDamageTracker::from_output(&output);
+        outputs.push(HeadlessOutput { output, damage_tracker, buffer: None, dirty: true });
+    }
+
+    state.headless = Some(HeadlessData { renderer, outputs, frame_scheduled: false });
+    state.output_modes_changed();
+    state.request_redraw_all();
+
+    state.run_autostart();
+    // Unlike the other backends there is no terminal to fall back to, tests start their
+    // own clients.
+    if let Some(command) = options.command.as_deref() {
+        state.spawn_startup_client(Some(command));
+    }
+
+    event_loop.run(None, &mut state, |_| {})?;
+
+    Ok(())
+}
+
+fn surfaceless_renderer() -> Result<GlesRenderer, Box<dyn std::error::Error>> {
+    let display = unsafe { EGLDisplay::new(EGLSurfacelessDisplay)? };
+    let context = EGLContext::new(&display)?;
+    Ok(unsafe { GlesRenderer::new(context)? })
+}
+
+impl Smallvil {
+    /// Marks `output`, or every output without one, dirty on the headless backend. With no
+    /// vblank to wait for, a timer stands in for the refresh rate.
+    pub(crate) fn request_headless_redraw(&mut self, output: Option<&Output>) {
+        let Some(headless) = self.headless.as_mut() else {
+            return;
+        };
+
+        for target in &mut headless.outputs {
+            if output.is_none_or(|output| *output == target.output) {
+                target.dirty = true;
+            }
+        }
+        if headless.frame_scheduled {
+            return;
+        }
+
+        let interval = headless
+            .outputs
+            .first()
+            .map_or(Duration::from_micros(16_667), |target| frame_interval(&target.output));
+        let timer =
+            self.loop_handle.insert_source(Timer::from_duration(interval), |_, (), state| {
+                if let Some(headless) = state.headless.as_mut() {
+                    headless.frame_scheduled = false;
+                }
+                state.render_headless();
+                TimeoutAction::Drop
+            });
+        match timer {
+            Ok(_) => headless.frame_scheduled = true,
+            Err(err) => tracing::warn!("Failed to schedule headless frame: {err}"),
+        }
+    }
+
+    /// Draws the dirty outputs, if rendering is on, and sends their frame callbacks.
+    fn render_headless(&mut self) {
+        // Taken out while drawing, so the rest of the state stays borrowable.
+        let Some(mut headless) = self.headless.take() else {
+            return;
+        };
+
+        let mut drawn = Vec::new();
+        for target in &mut headless.outputs {
+            if !std::mem::take(&mut target.dirty) {
+                continue;
+            }
+            if let Some(renderer) = headless.renderer.as_mut() {
+                self.render_headless_output(renderer, target);
+            }
+            drawn.push(target.output.clone());
+        }
+        self.headless = Some(headless);
+
+        let time = self.start_time.elapsed();
+        for output in drawn {
+            self.send_frame_callbacks(&output, time);
+        }
+
+        self.space.refresh();
+        self.popups.cleanup();
+    }
+
+    fn render_headless_output(&mut self, renderer: &mut GlesRenderer, target: &mut HeadlessOutput) {
+        let Some(mode) = target.output.current_mode() else {
+            return;
+        };
+
+        // The same buffer is drawn every frame, it holds the previous one.
+        let age = usize::from(target.buffer.is_some());
+        if target.buffer.is_none() {
+            let size = (mode.size.w, mode.size.h).into();
+            match Offscreen::<GlesTexture>::create_buffer(renderer, Fourcc::Abgr8888, size) {
+                Ok(buffer) => target.buffer = Some(buffer),
+                Err(err) => {
+                    tracing::error!("Failed to create headless output buffer: {err}");
+                    return;
+                }
+            }
+        }
+        let Some(buffer) = target.buffer.as_mut() else {
+            return;
+        };
+        let mut framebuffer = match renderer.bind(buffer) {
+            Ok(framebuffer) => framebuffer,
+            Err(err) => {
+                tracing::error!("Failed to bind headless output buffer: {err}");
+                return;
+            }
+        };
+
+        self.render_gles_output(
+            renderer,
+            &mut framebuffer,
+            age,
+            &target.output,
+            &mut target.damage_tracker,
+            None,
+        );
+    }
+}
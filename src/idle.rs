@@ -0,0 +1,108 @@
+//! Idleness: input resets the ext-idle-notify timers and, after `dpms_timeout` without
+//! any, the displays are turned off until the next input event. Idle inhibitors held by
+//! clients (video players, presentations) postpone both.
+
+use std::time::{Duration, Instant};
+
+use smithay::{
+    reexports::{
+        calloop::{
+            RegistrationToken,
+            timer::{TimeoutAction, Timer},
+        },
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::IsAlive,
+};
+
+use crate::Smallvil;
+
+pub struct IdleState {
+    pub dpms_timeout: Option<Duration>,
+    /// Whether the displays were turned off for being idle.
+    pub displays_off: bool,
+    last_activity: Instant,
+    /// Surfaces holding an idle inhibitor.
+    inhibitors: Vec<WlSurface>,
+    timer: Option<RegistrationToken>,
+}
+
+impl IdleState {
+    pub fn new(dpms_timeout: Option<Duration>) -> Self {
+        Self {
+            dpms_timeout,
+            displays_off: false,
+            last_activity: Instant::now(),
+            inhibitors: Vec::new(),
+            timer: None,
+        }
+    }
+
+    pub fn is_inhibited(&self) -> bool {
+        self.inhibitors.iter().any(IsAlive::alive)
+    }
+}
+
+impl Smallvil {
+    /// Notes user input: idle timeouts start over and the displays come back on.
+    pub fn idle_activity(&mut self) {
+        self.idle_notifier_state.notify_activity(&self.seat.seat);
+        self.idle.last_activity = Instant::now();
+        if self.idle.displays_off {
+            self.set_displays_powered(true);
+        }
+        self.arm_dpms_timer();
+    }
+
+    pub fn add_idle_inhibitor(&mut self, surface: WlSurface) {
+        self.idle.inhibitors.push(surface);
+        self.update_idle_inhibited();
+    }
+
+    pub fn remove_idle_inhibitor(&mut self, surface: &WlSurface) {
+        self.idle.inhibitors.retain(|inhibitor| inhibitor != surface);
+        self.update_idle_inhibited();
+    }
+
+    fn update_idle_inhibited(&mut self) {
+        self.idle.inhibitors.retain(IsAlive::alive);
+        let inhibited = self.idle.is_inhibited();
+        self.idle_notifier_state.set_is_inhibited(inhibited);
+    }
+
+    /// Starts the DPMS timeout, unless it is already running or turned off. Like the
+    /// cursor timeout, one timer covers a whole idle period and waits out whatever is left
+    /// when it fires early.
+    pub fn arm_dpms_timer(&mut self) {
+        let Some(timeout) = self.idle.dpms_timeout else {
+            return;
+        };
+        if self.idle.timer.is_some() {
+            return;
+        }
+
+        let timer =
+            self.loop_handle.insert_source(Timer::from_duration(timeout), |_, (), state| {
+                let Some(timeout) = state.idle.dpms_timeout else {
+                    state.idle.timer = None;
+                    return TimeoutAction::Drop;
+                };
+                if state.idle.is_inhibited() {
+                    return TimeoutAction::ToDuration(timeout);
+                }
+
+                let idle = state.idle.last_activity.elapsed();
+                if idle < timeout {
+                    return TimeoutAction::ToDuration(timeout - idle);
+                }
+
+                state.idle.timer = None;
+                state.set_displays_powered(false);
+                TimeoutAction::Drop
+            });
+        match timer {
+            Ok(timer) => self.idle.timer = Some(timer),
+            Err(err) => tracing::warn!("Failed to start DPMS timeout: {err}"),
+        }
+    }
+}
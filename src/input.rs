@@ -1,25 +1,40 @@
+use crate::{screenshot::ScreenshotTarget, state::Smallvil};
 use smithay::{
     backend::input::{
-        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Event, InputBackend, InputEvent,
-        KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
+        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Device, DeviceCapability, Event,
+        InputBackend, InputEvent, KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent,
+        PointerMotionEvent,
     },
     backend::session::Session,
     input::{
         keyboard::{FilterResult, Keysym, keysyms as xkb},
         pointer::{AxisFrame, ButtonEvent, MotionEvent},
     },
+    output::Output,
     reexports::wayland_server::protocol::wl_surface::WlSurface,
-    utils::{Rectangle, SERIAL_COUNTER},
+    utils::{Logical, Point, Rectangle, SERIAL_COUNTER, Transform},
 };
-use std::process::Command;
-
-use crate::state::Smallvil;
 
 enum KeyAction {
     Forward,
     Quit,
     VtSwitch(i32),
-    RunFoot,
+    RunTerminal,
+    Workspace(u32),
+    MoveToWorkspace(u32),
+    Unminimize,
+    CloseWindow,
+    ToggleDebugOverlay,
+    Screenshot(ScreenshotTarget),
+}
+
+/// Maps the number row to workspaces 1-10, with 0 standing in for 10.
+fn workspace_for_keysym(keysym: Keysym) -> Option<u32> {
+    match keysym.raw() {
+        xkb::KEY_0 => Some(10),
+        raw @ xkb::KEY_1..=xkb::KEY_9 => Some(raw - xkb::KEY_1 + 1),
+        _ => None,
+    }
 }
 
 #[allow(clippy::cast_possible_truncation)]
@@ -38,15 +53,22 @@ fn f64_to_i32_saturating(value: f64) -> i32 {
 impl Smallvil {
     #[allow(clippy::too_many_lines)]
     pub fn process_input_event<I: InputBackend>(&mut self, event: InputEvent<I>) {
+        if !matches!(event, InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. }) {
+            self.idle_activity();
+        }
+
         match event {
             InputEvent::Keyboard { event, .. } => {
                 let serial = SERIAL_COUNTER.next_serial();
                 let time = Event::time_msec(&event);
 
-                let Some(keyboard) = self.seat.get_keyboard() else {
+                let Some(keyboard) = self.seat.keyboard() else {
                     tracing::warn!("Keyboard event received without keyboard in seat");
                     return;
                 };
+                if event.state() == KeyState::Pressed {
+                    self.key_activity();
+                }
 
                 let action = keyboard
                     .input::<KeyAction, _>(
@@ -74,8 +96,51 @@ impl Smallvil {
                                     return FilterResult::Intercept(KeyAction::VtSwitch(vt));
                                 }
 
+                                if keysym == Keysym::Print {
+                                    let target = if modifiers.shift {
+                                        ScreenshotTarget::Window
+                                    } else {
+                                        ScreenshotTarget::Output
+                                    };
+                                    return FilterResult::Intercept(KeyAction::Screenshot(target));
+                                }
+
                                 if modifiers.logo && keysym == Keysym::Return {
-                                    return FilterResult::Intercept(KeyAction::RunFoot);
+                                    return FilterResult::Intercept(KeyAction::RunTerminal);
+                                }
+
+                                if modifiers.logo
+                                    && !modifiers.shift
+                                    && handle.raw_latin_sym_or_raw_current_sym() == Some(Keysym::q)
+                                {
+                                    return FilterResult::Intercept(KeyAction::CloseWindow);
+                                }
+
+                                if modifiers.logo
+                                    && modifiers.shift
+                                    && handle.raw_latin_sym_or_raw_current_sym() == Some(Keysym::n)
+                                {
+                                    return FilterResult::Intercept(KeyAction::Unminimize);
+                                }
+
+                                if modifiers.logo
+                                    && modifiers.shift
+                                    && handle.raw_latin_sym_or_raw_current_sym() == Some(Keysym::d)
+                                {
+                                    return FilterResult::Intercept(KeyAction::ToggleDebugOverlay);
+                                }
+
+                                if modifiers.logo
+                                    && let Some(workspace) = handle
+                                        .raw_latin_sym_or_raw_current_sym()
+                                        .and_then(workspace_for_keysym)
+                                {
+                                    let action = if modifiers.shift {
+                                        KeyAction::MoveToWorkspace(workspace)
+                                    } else {
+                                        KeyAction::Workspace(workspace)
+                                    };
+                                    return FilterResult::Intercept(action);
                                 }
                             }
 
@@ -93,118 +158,70 @@ impl Smallvil {
                             tracing::error!("Error switching VT to {vt}: {err}");
                         }
                     }
-                    KeyAction::RunFoot => {
-                        if let Err(err) = Command::new("foot").spawn() {
-                            tracing::error!("Failed to start foot: {err}");
+                    KeyAction::RunTerminal => self.spawn_command_line("terminal", &self.terminal),
+                    KeyAction::Workspace(workspace) => self.select_workspace(workspace),
+                    KeyAction::MoveToWorkspace(workspace) => {
+                        if let Some(window) =
+                            self.active_surface.clone().and_then(|s| self.window_for_surface(&s))
+                        {
+                            self.move_window_to_workspace(&window, workspace);
                         }
                     }
+                    KeyAction::CloseWindow => {
+                        if let Some(window) =
+                            self.active_surface.clone().and_then(|s| self.window_for_surface(&s))
+                        {
+                            self.close_window(&window);
+                        }
+                    }
+                    KeyAction::Unminimize => {
+                        if let Some(window) = self.minimized.last().cloned() {
+                            self.unminimize_window(&window);
+                        }
+                    }
+                    KeyAction::ToggleDebugOverlay => {
+                        self.set_debug_overlay(!self.debug_overlay.enabled());
+                    }
+                    KeyAction::Screenshot(target) => self.screenshot(target),
                     KeyAction::Forward => {}
                 }
             }
             InputEvent::PointerMotion { event, .. } => {
-                let Some(pointer) = self.seat.get_pointer() else {
-                    tracing::warn!("Pointer motion received without pointer in seat");
-                    return;
-                };
-
-                let mut pos = pointer.current_location() + event.delta();
-
-                if let Some(output) = self.space.outputs().next()
-                    && let Some(output_geo) = self.space.output_geometry(output)
+                self.pointer_activity();
+                self.pointer_motion_relative(event.delta(), event.time_msec());
+                self.pointer_frame();
+            }
+            InputEvent::DeviceAdded { device } => {
+                if device.has_capability(DeviceCapability::Touch)
+                    || device.has_capability(DeviceCapability::TabletTool)
                 {
-                    pos = pos.constrain(Rectangle::new(output_geo.loc, output_geo.size).to_f64());
+                    tracing::info!("Absolute input device added: {}", device.name());
                 }
-
-                let serial = SERIAL_COUNTER.next_serial();
-                let under = self.surface_under(pos);
-
-                pointer.motion(
-                    self,
-                    under,
-                    &MotionEvent { location: pos, serial, time: event.time_msec() },
-                );
-                pointer.frame(self);
             }
             InputEvent::PointerMotionAbsolute { event, .. } => {
-                let Some(output) = self.space.outputs().next() else {
-                    return;
-                };
-
-                let Some(output_geo) = self.space.output_geometry(output) else {
-                    return;
-                };
-
-                let pos = event.position_transformed(output_geo.size) + output_geo.loc.to_f64();
-
-                let serial = SERIAL_COUNTER.next_serial();
-
-                let Some(pointer) = self.seat.get_pointer() else {
-                    tracing::warn!("Pointer absolute motion received without pointer in seat");
+                self.pointer_activity();
+                let device = event.device();
+                let Some((area, transform)) =
+                    self.absolute_motion_area(&device.id(), &device.name())
+                else {
                     return;
                 };
 
-                let under = self.surface_under(pos);
-
-                pointer.motion(
-                    self,
-                    under,
-                    &MotionEvent { location: pos, serial, time: event.time_msec() },
-                );
-                pointer.frame(self);
+                // Devices report positions across the panel as it is mounted, a rotated
+                // output shows them rotated.
+                let panel_size = transform.transform_size(area.size);
+                let on_panel = event.position_transformed(panel_size);
+                let pos = transform.invert().transform_point_in(on_panel, &panel_size.to_f64());
+                self.pointer_motion_to(pos + area.loc.to_f64(), event.time_msec());
+                self.pointer_frame();
             }
             InputEvent::PointerButton { event, .. } => {
-                let Some(pointer) = self.seat.get_pointer() else {
-                    tracing::warn!("Pointer button received without pointer in seat");
-                    return;
-                };
-                let Some(keyboard) = self.seat.get_keyboard() else {
-                    tracing::warn!("Pointer button received without keyboard in seat");
-                    return;
-                };
-
-                let serial = SERIAL_COUNTER.next_serial();
-
-                let button = event.button_code();
-
-                let button_state = event.state();
-
-                if ButtonState::Pressed == button_state && !pointer.is_grabbed() {
-                    if let Some((window, _loc)) = self
-                        .space
-                        .element_under(pointer.current_location())
-                        .map(|(w, l)| (w.clone(), l))
-                    {
-                        let Some(toplevel) = window.toplevel() else {
-                            tracing::warn!("Window without toplevel cannot receive focus");
-                            pointer.button(
-                                self,
-                                &ButtonEvent {
-                                    button,
-                                    state: button_state,
-                                    serial,
-                                    time: event.time_msec(),
-                                },
-                            );
-                            pointer.frame(self);
-                            return;
-                        };
-                        self.active_surface = Some(toplevel.wl_surface().clone());
-                        keyboard.set_focus(self, Some(toplevel.wl_surface().clone()), serial);
-                        self.arrange_windows_tiled();
-                    } else {
-                        self.active_surface = None;
-                        keyboard.set_focus(self, Option::<WlSurface>::None, serial);
-                        self.arrange_windows_tiled();
-                    }
-                }
-
-                pointer.button(
-                    self,
-                    &ButtonEvent { button, state: button_state, serial, time: event.time_msec() },
-                );
-                pointer.frame(self);
+                self.pointer_activity();
+                self.pointer_button(event.button_code(), event.state(), event.time_msec());
+                self.pointer_frame();
             }
             InputEvent::PointerAxis { event, .. } => {
+                self.pointer_activity();
                 let source = event.source();
 
                 let horizontal_amount = event.amount(Axis::Horizontal).unwrap_or_else(|| {
@@ -239,18 +256,160 @@ impl Smallvil {
                     }
                 }
 
-                let Some(pointer) = self.seat.get_pointer() else {
-                    tracing::warn!("Pointer axis received without pointer in seat");
-                    return;
-                };
-                pointer.axis(self, frame);
-                pointer.frame(self);
+                self.pointer_axis(frame);
+                self.pointer_frame();
             }
             _ => {}
         }
+    }
+
+    /// Where absolute pointer positions of the device `id` named `name` land, with the
+    /// transform of the output they are on: the whole nested window, which may be split
+    /// into several outputs, or else the output `[map_to_output]` names, then the one
+    /// libinput associates the device with, then all outputs.
+    fn absolute_motion_area(
+        &self,
+        id: &str,
+        name: &str,
+    ) -> Option<(Rectangle<i32, Logical>, Transform)> {
+        let bounding_box = |outputs: Vec<&Output>| {
+            outputs
+                .into_iter()
+                .filter_map(|output| self.space.output_geometry(output))
+                .reduce(|area, geometry| area.merge(geometry))
+                .map(|area| (area, Transform::Normal))
+        };
+        if let Some(winit) = &self.winit {
+            return bounding_box(winit.outputs().collect());
+        }
+
+        let mapped = |mapping: &[(String, String)], key: &str| {
+            let (_, output_name) = mapping.iter().find(|(device, _)| device == key)?;
+            let output = self.space.outputs().find(|output| output.name() == *output_name)?;
+            Some((self.space.output_geometry(output)?, output.current_transform()))
+        };
+        mapped(&self.map_to_output, name)
+            .or_else(|| mapped(&self.device_outputs, id))
+            .or_else(|| bounding_box(self.space.outputs().collect()))
+    }
+
+    /// Remembers the output libinput associates the device `id` with, a touchscreen's
+    /// comes from udev. `None` forgets it, when the device goes away.
+    pub fn set_device_output(&mut self, id: &str, output: Option<&str>) {
+        self.device_outputs.retain(|(device, _)| device != id);
+        if let Some(output) = output {
+            tracing::info!("Input device {id} belongs to output {output}");
+            self.device_outputs.push((id.to_string(), output.to_string()));
+        }
+    }
+
+    /// Moves the pointer by `delta`, constrained to the outputs.
+    pub fn pointer_motion_relative(&mut self, delta: Point<f64, Logical>, time: u32) {
+        let Some(pointer) = self.seat.pointer() else {
+            tracing::warn!("Pointer motion received without pointer in seat");
+            return;
+        };
+
+        let pos = self.constrain_to_outputs(pointer.current_location() + delta);
+        self.pointer_motion_to(pos, time);
+    }
+
+    /// The point nearest to `pos` on any output, `pos` itself without outputs. Outputs of
+    /// different sizes leave corners outside all of them, a pointer pushed into one
+    /// slides along the nearest output edge.
+    pub fn constrain_to_outputs(&self, pos: Point<f64, Logical>) -> Point<f64, Logical> {
+        let distance = |point: Point<f64, Logical>| {
+            let offset = point - pos;
+            offset.x * offset.x + offset.y * offset.y
+        };
+        self.space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .map(|geometry| {
+                // The far edges belong to the next output, or to none.
+                let size = (geometry.size.w - 1, geometry.size.h - 1);
+                pos.constrain(Rectangle::new(geometry.loc, size.into()).to_f64())
+            })
+            .min_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+            .unwrap_or(pos)
+    }
+
+    /// Moves the pointer to `pos` in global coordinates. The motion goes through the
+    /// active pointer grab, if any.
+    pub fn pointer_motion_to(&mut self, pos: Point<f64, Logical>, time: u32) {
+        let Some(pointer) = self.seat.pointer() else {
+            tracing::warn!("Pointer motion received without pointer in seat");
+            return;
+        };
+
+        let serial = SERIAL_COUNTER.next_serial();
+        let under = self.surface_under(pos);
+        let previous = pointer.current_location();
+
+        pointer.motion(self, under, &MotionEvent { location: pos, serial, time });
+        self.update_toplevel_drag(pos);
+
+        // The cursor leaves one spot and shows up at another, possibly on another output.
+        let outputs: Vec<_> = [previous, pos]
+            .into_iter()
+            .filter_map(|location| self.space.output_under(location).next().cloned())
+            .collect();
+        for output in outputs {
+            self.request_redraw(&output);
+        }
+    }
+
+    /// Sends a button event, focusing the window under the pointer on press unless a
+    /// grab is active.
+    pub fn pointer_button(&mut self, button: u32, button_state: ButtonState, time: u32) {
+        let Some(pointer) = self.seat.pointer() else {
+            tracing::warn!("Pointer button received without pointer in seat");
+            return;
+        };
+        let Some(keyboard) = self.seat.keyboard() else {
+            tracing::warn!("Pointer button received without keyboard in seat");
+            return;
+        };
+
+        let serial = SERIAL_COUNTER.next_serial();
+
+        if ButtonState::Pressed == button_state && !pointer.is_grabbed() {
+            if let Some((window, _loc)) =
+                self.space.element_under(pointer.current_location()).map(|(w, l)| (w.clone(), l))
+            {
+                if let Some(toplevel) = window.toplevel() {
+                    self.active_surface = Some(toplevel.wl_surface().clone());
+                    keyboard.set_focus(self, Some(toplevel.wl_surface().clone()), serial);
+                    self.arrange_windows_tiled();
+                    self.ping_window_client(&window);
+                } else {
+                    tracing::warn!("Window without toplevel cannot receive focus");
+                }
+            } else {
+                self.active_surface = None;
+                keyboard.set_focus(self, Option::<WlSurface>::None, serial);
+                self.arrange_windows_tiled();
+            }
+        }
+
+        // Releasing the button ends a drag-and-drop as a drop.
+        self.toplevel_drag_state.button_released = button_state == ButtonState::Released;
+        pointer.button(self, &ButtonEvent { button, state: button_state, serial, time });
+        self.toplevel_drag_state.button_released = false;
+    }
+
+    pub fn pointer_axis(&mut self, frame: AxisFrame) {
+        let Some(pointer) = self.seat.pointer() else {
+            tracing::warn!("Pointer axis received without pointer in seat");
+            return;
+        };
+        pointer.axis(self, frame);
+    }
 
-        if self.udev.is_some() {
-            self.request_redraw_all();
+    /// Ends a group of pointer events.
+    pub fn pointer_frame(&mut self) {
+        if let Some(pointer) = self.seat.pointer() {
+            pointer.frame(self);
         }
     }
 }
@@ -0,0 +1,1095 @@
+//! The control socket `ripctl` talks to. Each connection sends one JSON request, such as
+//! `{"cmd": "reload"}`, and gets one JSON response back: `{"ok": true}` with any
+//! command-specific fields next to `ok`, or `{"ok": false, "error": "..."}`.
+//!
+//! The bare text commands of older `ripctl` versions, `reload` or `keyboard us intl`,
+//! are still understood and answered the old way, in plain text.
+//!
+//! `{"cmd": "subscribe"}` is the exception: after `{"ok": true}` the connection stays
+//! open and gets one line of JSON per event, such as
+//! `{"event": "window-moved", "id": 3, "app_id": "foot", "workspace": 2}`.
+//!
+//! A request ends with a newline, with a complete JSON object or when the client shuts
+//! down its side of the connection. Connections never block the event loop, they are
+//! polled like any other source and closed after [`IPC_TIMEOUT`].
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use smithay::{
+    desktop::Window,
+    output::{Mode, Scale as OutputScale},
+    reexports::calloop::{
+        Interest, Mode as PollMode, PostAction,
+        generic::Generic,
+        timer::{TimeoutAction, Timer},
+    },
+    utils::{Logical, Point, Rectangle},
+};
+
+use crate::{Smallvil, config::OutputConfig};
+
+/// How long a client has to send its request and read the response.
+const IPC_TIMEOUT: Duration = Duration::from_secs(10);
+/// Requests are a line of JSON, anything longer is not one.
+const MAX_REQUEST_LEN: usize = 1 << 20;
+
+/// A connection waiting for the rest of its request, or for its response to be read.
+#[derive(Default)]
+struct IpcConnection {
+    request: Vec<u8>,
+    response: Option<Vec<u8>>,
+    /// How much of the response has been written.
+    written: usize,
+    /// Kept open for events once the response is written.
+    subscribed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Reload,
+    Keyboard {
+        layout: String,
+        #[serde(default)]
+        variant: String,
+    },
+    Minimized,
+    Unminimize {
+        app_id: String,
+    },
+    Windows,
+    Outputs,
+    Workspaces,
+    /// Keeps the connection open and sends it every event from then on.
+    Subscribe,
+    /// Switches to workspace `id`, or to the next or previous one.
+    Workspace {
+        id: Option<u32>,
+        direction: Option<Direction>,
+    },
+    Exec {
+        argv: Vec<String>,
+    },
+    DebugOverlay {
+        state: Toggle,
+    },
+    /// Dumps the compositor state for bug reports.
+    Debug {
+        #[serde(default)]
+        redact_titles: bool,
+    },
+    /// Changes the border colours and width. Missing fields keep their current value,
+    /// a width of 0 turns borders off.
+    Border {
+        active: Option<String>,
+        inactive: Option<String>,
+        width: Option<u32>,
+    },
+    /// Focuses the most recently focused window matching every given field. `title`
+    /// matches a substring, or a regex with `regex`. With `warp` the pointer moves to
+    /// the centre of the window.
+    Focus {
+        id: Option<u64>,
+        app_id: Option<String>,
+        title: Option<String>,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        warp: bool,
+    },
+    /// Moves the window with `app_id`, or the focused one, to workspace `workspace` or to
+    /// the workspace shown on `output`.
+    Move {
+        app_id: Option<String>,
+        workspace: Option<u32>,
+        output: Option<String>,
+    },
+    /// Changes the mode, scale, transform or position of the output `name`, or turns it on
+    /// or off, until the next reload. With `save` the changes go into its `[[output]]`
+    /// section too.
+    Output {
+        name: String,
+        #[serde(flatten)]
+        changes: OutputChanges,
+        #[serde(default)]
+        save: bool,
+    },
+    /// Shows `value`, an image path, `off` or `#RRGGBB`, on `output` or on every output.
+    /// With `save` it is written to the config file too.
+    Wallpaper {
+        value: String,
+        output: Option<String>,
+        #[serde(default)]
+        save: bool,
+    },
+}
+
+/// What an `output` request changes, fields that are missing stay as they are.
+#[derive(Debug, Default, Deserialize)]
+pub struct OutputChanges {
+    /// `WIDTHxHEIGHT`, optionally `@REFRESH` in Hz.
+    pub mode: Option<String>,
+    pub scale: Option<f64>,
+    pub transform: Option<String>,
+    /// Top left corner in the space, in logical pixels.
+    pub position: Option<(i32, i32)>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Toggle {
+    On,
+    Off,
+    Toggle,
+}
+
+/// What a successful request answers with.
+pub enum Reply {
+    Done,
+    Minimized(Vec<String>),
+    Windows(Vec<WindowInfo>),
+    Outputs(Vec<OutputInfo>),
+    Workspaces(Vec<WorkspaceInfo>),
+    Spawned(u32),
+    Debug(Box<DebugInfo>),
+    Subscribed,
+}
+
+/// A mapped window, as listed by the `windows` command.
+#[derive(Debug, Serialize)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub app_id: String,
+    pub title: String,
+    pub geometry: Geometry,
+    /// The output the window is on, `None` while it is on none.
+    pub output: Option<String>,
+    pub workspace: u32,
+    pub focused: bool,
+    pub floating: bool,
+    pub fullscreen: bool,
+    pub urgent: bool,
+}
+
+/// An output, as listed by the `outputs` command. Outputs the config turns off are listed
+/// too, with nothing but their name.
+#[derive(Debug, Default, Serialize)]
+pub struct OutputInfo {
+    pub name: String,
+    pub make: String,
+    pub model: String,
+    pub serial: Option<String>,
+    pub enabled: bool,
+    /// Whether the display is on, off while the session is idle.
+    pub powered: bool,
+    /// Whether the pointer is on this output.
+    pub pointer: bool,
+    pub current_mode: Option<ModeInfo>,
+    pub modes: Vec<ModeInfo>,
+    /// Position and size in the space, in logical pixels.
+    pub geometry: Option<Geometry>,
+    pub scale: f64,
+    pub transform: String,
+    /// DRM fourcc of the framebuffers, on the udev backend.
+    pub format: Option<String>,
+}
+
+/// Everything `ripctl debug` prints.
+#[derive(Debug, Serialize)]
+pub struct DebugInfo {
+    pub version: &'static str,
+    pub git_hash: Option<&'static str>,
+    pub backend: &'static str,
+    pub gpus: Vec<GpuInfo>,
+    pub outputs: Vec<OutputInfo>,
+    pub seat: SeatInfo,
+    pub windows: Vec<DebugWindowInfo>,
+    pub minimized: usize,
+    pub workspaces: Vec<WorkspaceInfo>,
+    pub config: Value,
+    pub caches: CacheInfo,
+}
+
+/// A DRM device on the udev backend.
+#[derive(Debug, Serialize)]
+pub struct GpuInfo {
+    pub node: String,
+    pub render_node: Option<String>,
+    /// Whether this device does the compositing for the others.
+    pub primary: bool,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeatInfo {
+    pub name: String,
+    pub keyboard: bool,
+    pub pointer: bool,
+    /// Names of the layouts in the keymap.
+    pub layouts: Vec<String>,
+    pub active_layout: Option<String>,
+}
+
+/// A window with the state of its configure sequence.
+#[derive(Debug, Serialize)]
+pub struct DebugWindowInfo {
+    #[serde(flatten)]
+    pub window: WindowInfo,
+    /// The size the next configure asks for, `None` to let the client decide.
+    pub pending_size: Option<(i32, i32)>,
+    /// Whether the pending state differs from what the client last acknowledged.
+    pub configure_pending: bool,
+    pub initial_configure_sent: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheInfo {
+    /// Scaled wallpaper buffers, across outputs.
+    pub wallpaper_buffers: usize,
+    /// Uploaded frames of the software cursor.
+    pub cursor_images: usize,
+}
+
+/// A workspace, as listed by the `workspaces` command.
+#[derive(Debug, Serialize)]
+pub struct WorkspaceInfo {
+    pub id: u32,
+    /// Workspaces are only numbered, the name is the number.
+    pub name: String,
+    pub output: String,
+    /// Windows on the workspace, minimized ones aside.
+    pub windows: usize,
+    /// Whether the workspace is shown on its output.
+    pub active: bool,
+    /// Whether the workspace has the focus, it is on the current output.
+    pub focused: bool,
+    pub urgent: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ModeInfo {
+    pub width: i32,
+    pub height: i32,
+    /// In Hz.
+    pub refresh: f64,
+}
+
+impl From<Mode> for ModeInfo {
+    fn from(mode: Mode) -> Self {
+        Self { width: mode.size.w, height: mode.size.h, refresh: f64::from(mode.refresh) / 1000.0 }
+    }
+}
+
+/// A rectangle in global logical coordinates.
+#[derive(Debug, Serialize)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<Rectangle<i32, Logical>> for Geometry {
+    fn from(rect: Rectangle<i32, Logical>) -> Self {
+        Self { x: rect.loc.x, y: rect.loc.y, width: rect.size.w, height: rect.size.h }
+    }
+}
+
+impl Reply {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Done | Self::Subscribed => json!({ "ok": true }),
+            Self::Minimized(app_ids) => json!({ "ok": true, "app_ids": app_ids }),
+            Self::Windows(windows) => json!({ "ok": true, "windows": windows }),
+            Self::Outputs(outputs) => json!({ "ok": true, "outputs": outputs }),
+            Self::Workspaces(workspaces) => json!({ "ok": true, "workspaces": workspaces }),
+            Self::Spawned(pid) => json!({ "ok": true, "pid": pid }),
+            Self::Debug(info) => json!({ "ok": true, "debug": info }),
+        }
+    }
+
+    /// The answer to a bare text command: one line per item, nothing for `Done`.
+    fn to_text(&self) -> String {
+        match self {
+            Self::Done | Self::Subscribed => String::new(),
+            Self::Minimized(app_ids) => {
+                app_ids.iter().map(|app_id| format!("{app_id}\n")).collect()
+            }
+            Self::Windows(windows) => windows
+                .iter()
+                .map(|window| {
+                    let Geometry { x, y, width, height } = window.geometry;
+                    format!("{x} {y} {width} {height} {}\n", window.app_id)
+                })
+                .collect(),
+            Self::Outputs(outputs) => {
+                outputs.iter().map(|output| format!("{}\n", output.name)).collect()
+            }
+            Self::Workspaces(workspaces) => {
+                workspaces.iter().map(|workspace| format!("{}\n", workspace.id)).collect()
+            }
+            Self::Spawned(pid) => format!("{pid}\n"),
+            Self::Debug(info) => {
+                serde_json::to_string_pretty(info).map(|json| json + "\n").unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Parses a bare text command from older `ripctl` versions.
+fn parse_text_command(command: &str) -> Result<Request, String> {
+    if let Some(app_id) = command.strip_prefix("unminimize ") {
+        return Ok(Request::Unminimize { app_id: app_id.trim().to_string() });
+    }
+    if let Some(setting) = command.strip_prefix("debug overlay ") {
+        let state = match setting.trim() {
+            "on" => Toggle::On,
+            "off" => Toggle::Off,
+            "toggle" => Toggle::Toggle,
+            other => return Err(format!("expected on, off or toggle, got {other}")),
+        };
+        return Ok(Request::DebugOverlay { state });
+    }
+    if let Some(layout_args) = command.strip_prefix("keyboard ") {
+        let mut parts = layout_args.splitn(2, ' ');
+        let layout = parts.next().map(str::trim).unwrap_or_default().to_string();
+        let variant = parts.next().map(str::trim).unwrap_or_default().to_string();
+        return Ok(Request::Keyboard { layout, variant });
+    }
+
+    match command {
+        "reload" => Ok(Request::Reload),
+        "minimized" => Ok(Request::Minimized),
+        "windows" => Ok(Request::Windows),
+        "outputs" => Ok(Request::Outputs),
+        "workspaces" => Ok(Request::Workspaces),
+        _ => Err(format!("unknown command: {command}")),
+    }
+}
+
+impl Smallvil {
+    /// Registers an accepted connection. It is read and answered as data comes in, and
+    /// dropped after [`IPC_TIMEOUT`], so a client that never finishes its request can't
+    /// hold up the event loop.
+    pub(crate) fn accept_ipc_client(&mut self, stream: UnixStream) {
+        if let Err(err) = stream.set_nonblocking(true) {
+            tracing::warn!("Failed to configure IPC stream: {err}");
+            return;
+        }
+
+        let mut connection = IpcConnection::default();
+        let inserted = self.loop_handle.insert_source(
+            Generic::new(stream, Interest::BOTH, PollMode::Edge),
+            move |_, stream, state| {
+                let stream = unsafe { stream.get_mut() };
+                Ok(state.poll_ipc_connection(stream, &mut connection))
+            },
+        );
+        let token = match inserted {
+            Ok(token) => token,
+            Err(err) => {
+                tracing::warn!("Failed to register IPC connection: {err}");
+                return;
+            }
+        };
+
+        let deadline = self.loop_handle.insert_source(
+            Timer::from_duration(IPC_TIMEOUT),
+            move |_, (), state| {
+                // Does nothing if the connection was answered and removed in time.
+                state.loop_handle.remove(token);
+                TimeoutAction::Drop
+            },
+        );
+        if let Err(err) = deadline {
+            tracing::warn!("Failed to set IPC connection deadline: {err}");
+        }
+    }
+
+    /// Reads what `stream` has to offer, answers once the request is complete and writes
+    /// as much of the response as the socket takes.
+    fn poll_ipc_connection(
+        &mut self,
+        stream: &mut UnixStream,
+        connection: &mut IpcConnection,
+    ) -> PostAction {
+        if connection.response.is_none() {
+            let mut eof = false;
+            let mut chunk = [0; 4096];
+            loop {
+                match stream.read(&mut chunk) {
+                    Ok(0) => {
+                        eof = true;
+                        break;
+                    }
+                    Ok(read) => {
+                        connection.request.extend(chunk.iter().take(read));
+                        if connection.request.len() > MAX_REQUEST_LEN {
+                            tracing::warn!("Dropping IPC connection with an oversized request");
+                            return PostAction::Remove;
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        tracing::warn!("Failed to read IPC command: {err}");
+                        return PostAction::Remove;
+                    }
+                }
+            }
+
+            // Connecting and hanging up is how ripctl and starting instances tell a live
+            // socket from a stale one.
+            if eof && connection.request.is_empty() {
+                return PostAction::Remove;
+            }
+            let line = connection.request.split(|byte| *byte == b'\n').next().unwrap_or_default();
+            let complete = eof
+                || line.len() < connection.request.len()
+                || (line.starts_with(b"{") && serde_json::from_slice::<Value>(line).is_ok());
+            if !complete {
+                return PostAction::Continue;
+            }
+            let command = String::from_utf8_lossy(line).into_owned();
+            let (response, subscribed) = self.answer_ipc_command(command.trim());
+            connection.response = Some(response.into_bytes());
+            connection.subscribed = subscribed;
+        }
+
+        let Some(response) = connection.response.as_deref() else {
+            return PostAction::Remove;
+        };
+        while let Some(rest) = response.get(connection.written..).filter(|rest| !rest.is_empty()) {
+            match stream.write(rest) {
+                Ok(written) => connection.written += written,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return PostAction::Continue,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    tracing::warn!("Failed to write IPC response: {err}");
+                    return PostAction::Remove;
+                }
+            }
+        }
+
+        // The source goes away with the request answered, a second handle on the socket
+        // stays for the events.
+        if connection.subscribed {
+            match stream.try_clone() {
+                Ok(subscriber) => self.ipc_subscribers.push(subscriber),
+                Err(err) => tracing::warn!("Failed to keep IPC subscriber: {err}"),
+            }
+        }
+        PostAction::Remove
+    }
+
+    /// Sends `event` as a line of JSON to every subscriber. One that hung up or doesn't
+    /// keep up with reading is dropped instead of buffering for it.
+    pub(crate) fn emit_ipc_event(&mut self, event: &Value) {
+        if self.ipc_subscribers.is_empty() {
+            return;
+        }
+        let line = format!("{event}\n");
+        self.ipc_subscribers.retain_mut(|subscriber| subscriber.write_all(line.as_bytes()).is_ok());
+    }
+
+    /// Runs a request and returns the response to send back: JSON for JSON requests,
+    /// plain text for bare text commands. Also whether the connection subscribed to events.
+    fn answer_ipc_command(&mut self, command: &str) -> (String, bool) {
+        if command.starts_with('{') {
+            let reply = serde_json::from_str::<Request>(command)
+                .map_err(|err| format!("invalid request: {err}"))
+                .and_then(|request| self.handle_ipc_request(request));
+            let subscribed = matches!(reply, Ok(Reply::Subscribed));
+            let response = match reply {
+                Ok(reply) => reply.to_json(),
+                Err(error) => {
+                    tracing::warn!("IPC request failed: {error}");
+                    json!({ "ok": false, "error": error })
+                }
+            };
+            (format!("{response}\n"), subscribed)
+        } else {
+            let response = match parse_text_command(command)
+                .and_then(|request| self.handle_ipc_request(request))
+            {
+                Ok(reply) => reply.to_text(),
+                Err(error) => {
+                    tracing::warn!("IPC command failed: {error}");
+                    format!("error: {error}\n")
+                }
+            };
+            (response, false)
+        }
+    }
+
+    /// The windows mapped in the space, bottom to top.
+    fn window_list(&self) -> Vec<WindowInfo> {
+        self.space.elements().filter_map(|window| self.window_info(window)).collect()
+    }
+
+    /// `window` as listed by the `windows` command, `None` if it isn't mapped.
+    fn window_info(&self, window: &Window) -> Option<WindowInfo> {
+        let geometry = self.space.element_geometry(window)?;
+        let focused = window
+            .toplevel()
+            .is_some_and(|toplevel| self.active_surface.as_ref() == Some(toplevel.wl_surface()));
+        Some(WindowInfo {
+            id: crate::window::id(window),
+            app_id: crate::window::app_id(window).unwrap_or_default(),
+            title: crate::window::title(window).unwrap_or_default(),
+            geometry: geometry.into(),
+            output: self
+                .space
+                .outputs_for_element(window)
+                .first()
+                .map(smithay::output::Output::name),
+            workspace: crate::window::workspace_of(window),
+            focused,
+            floating: crate::window::is_floating(window),
+            fullscreen: crate::window::is_fullscreen(window),
+            // Nothing asks for attention yet, there is no xdg-activation support.
+            urgent: false,
+        })
+    }
+
+    /// The compositor state for `ripctl debug`. Only reads what is at hand, nothing in here
+    /// waits on a client or the GPU.
+    fn debug_info(&mut self, redact_titles: bool) -> DebugInfo {
+        let backend = if self.udev.is_some() {
+            "udev"
+        } else if self.winit.is_some() {
+            "winit"
+        } else {
+            "headless"
+        };
+
+        let keyboard = self.seat.keyboard();
+        let (layouts, active_layout) = keyboard
+            .as_ref()
+            .and_then(|keyboard| {
+                keyboard.with_xkb_state(self, |context| {
+                    let xkb = context.xkb().lock().ok()?;
+                    let layouts: Vec<String> =
+                        xkb.layouts().map(|layout| xkb.layout_name(layout).to_string()).collect();
+                    let active = xkb.layout_name(xkb.active_layout()).to_string();
+                    Some((layouts, Some(active)))
+                })
+            })
+            .unwrap_or_default();
+        let seat = SeatInfo {
+            name: self.seat.seat.name().to_string(),
+            keyboard: keyboard.is_some(),
+            pointer: self.seat.pointer().is_some(),
+            layouts,
+            active_layout,
+        };
+
+        let windows = self
+            .space
+            .elements()
+            .filter_map(|window| {
+                let mut info = self.window_info(window)?;
+                if redact_titles && !info.title.is_empty() {
+                    info.title = "[redacted]".into();
+                }
+                let toplevel = window.toplevel();
+                Some(DebugWindowInfo {
+                    window: info,
+                    pending_size: toplevel
+                        .and_then(|toplevel| toplevel.with_pending_state(|state| state.size))
+                        .map(|size| (size.w, size.h)),
+                    configure_pending: toplevel
+                        .is_some_and(|toplevel| toplevel.has_pending_changes()),
+                    initial_configure_sent: toplevel
+                        .is_some_and(|toplevel| toplevel.is_initial_configure_sent()),
+                })
+            })
+            .collect();
+
+        let config = json!({
+            "path": self.config_path,
+            "active_border_color": self.active_border_color,
+            "inactive_border_color": self.inactive_border_color,
+            "unresponsive_border_color": self.unresponsive_border_color,
+            "border_width": self.border_width,
+            "border_outside": self.border_outside,
+            "corner_radius": self.corner_radius,
+            "inactive_dim": self.inactive_dim,
+            "color_depth": format!("{:?}", self.color_depth),
+            "software_cursor": self.software_cursor,
+            "auto_reload": self.config_watch.is_some(),
+            "toplevel_drag_drop": format!("{:?}", self.toplevel_drop_mode),
+            "maximize": format!("{:?}", self.maximize_mode),
+            "workspace_back_and_forth": self.workspaces.back_and_forth,
+            "screenshot_dir": self.screenshot_dir,
+            "window_rules": format!("{:?}", self.window_rules),
+            "outputs": format!("{:?}", self.output_configs),
+        });
+
+        let cursor_images = self
+            .udev
+            .as_ref()
+            .map(|udev| udev.cursor.cached_images())
+            .or_else(|| self.winit.as_ref().map(|winit| winit.cursor().cached_images()))
+            .unwrap_or_default();
+
+        DebugInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("RIPWM_GIT_HASH"),
+            backend,
+            gpus: self.udev_gpus(),
+            outputs: self.output_list(),
+            seat,
+            windows,
+            minimized: self.minimized.len(),
+            workspaces: self.workspace_list(),
+            config,
+            caches: CacheInfo { wallpaper_buffers: self.wallpaper.cached_buffers(), cursor_images },
+        }
+    }
+
+    /// The workspaces by id.
+    fn workspace_list(&self) -> Vec<WorkspaceInfo> {
+        let current = self.current_workspace();
+        self.workspaces
+            .iter()
+            .map(|workspace| {
+                let mapped = self
+                    .space
+                    .elements()
+                    .filter(|window| crate::window::workspace_of(window) == workspace.id)
+                    .count();
+                WorkspaceInfo {
+                    id: workspace.id,
+                    name: workspace.id.to_string(),
+                    output: workspace.output.name(),
+                    windows: mapped + workspace.hidden_windows.len(),
+                    active: workspace.active,
+                    focused: current == Some(workspace.id),
+                    urgent: workspace.urgent,
+                }
+            })
+            .collect()
+    }
+
+    /// The outputs in the space, then those the config turns off.
+    fn output_list(&self) -> Vec<OutputInfo> {
+        let pointer = self.seat.pointer().map(|pointer| pointer.current_location());
+        let mut outputs: Vec<OutputInfo> = self
+            .space
+            .outputs()
+            .map(|output| {
+                let geometry = self.space.output_geometry(output);
+                let properties = output.physical_properties();
+                let (format, serial) = self.udev_output_details(output).unzip();
+                OutputInfo {
+                    name: output.name(),
+                    make: properties.make,
+                    model: properties.model,
+                    serial: serial.flatten(),
+                    enabled: true,
+                    powered: !self.idle.displays_off,
+                    pointer: geometry
+                        .zip(pointer)
+                        .is_some_and(|(geometry, pointer)| geometry.to_f64().contains(pointer)),
+                    current_mode: output.current_mode().map(ModeInfo::from),
+                    modes: output.modes().into_iter().map(ModeInfo::from).collect(),
+                    geometry: geometry.map(Geometry::from),
+                    scale: output.current_scale().fractional_scale(),
+                    transform: crate::config::transform_name(output.current_transform()).into(),
+                    format,
+                }
+            })
+            .collect();
+
+        outputs.extend(self.udev_disabled_outputs().into_iter().map(|name| OutputInfo {
+            name,
+            scale: 1.0,
+            transform: "normal".into(),
+            ..OutputInfo::default()
+        }));
+        outputs
+    }
+
+    /// The window a `focus` request is about, see [`Request::Focus`].
+    fn find_window(
+        &self,
+        id: Option<u64>,
+        app_id: Option<&str>,
+        title: Option<&str>,
+        regex: bool,
+    ) -> Result<Window, String> {
+        if id.is_none() && app_id.is_none() && title.is_none() {
+            return Err("missing window id, app_id or title".into());
+        }
+        let title_regex = match title {
+            Some(title) if regex => {
+                Some(Regex::new(title).map_err(|err| format!("invalid title regex: {err}"))?)
+            }
+            _ => None,
+        };
+        let title_matches = |window: &Window| {
+            let Some(title) = title else {
+                return true;
+            };
+            let window_title = crate::window::title(window).unwrap_or_default();
+            match &title_regex {
+                Some(regex) => regex.is_match(&window_title),
+                None => window_title.contains(title),
+            }
+        };
+
+        self.space
+            .elements()
+            .chain(self.workspaces.hidden_windows())
+            .chain(self.minimized.iter())
+            .filter(|window| id.is_none_or(|id| crate::window::id(window) == id))
+            .filter(|window| {
+                app_id.is_none_or(|app_id| crate::window::app_id(window).as_deref() == Some(app_id))
+            })
+            .filter(|window| title_matches(window))
+            .max_by_key(|window| crate::window::focused_at(window))
+            .cloned()
+            .ok_or_else(|| "no matching window".to_string())
+    }
+
+    /// Moves the pointer to the centre of `window`.
+    fn warp_pointer_to(&mut self, window: &Window) {
+        let Some(geometry) = self.space.element_geometry(window) else {
+            return;
+        };
+        let time = u32::try_from(self.start_time.elapsed().as_millis()).unwrap_or(u32::MAX);
+        let centre = geometry.loc.to_f64() + geometry.size.to_f64().downscale(2.0).to_point();
+        self.pointer_motion_to(centre, time);
+        self.pointer_frame();
+    }
+
+    /// Applies an `output` request, see [`Request::Output`]. The udev backend goes through
+    /// the same `[[output]]` handling as a config reload, the others can only move, scale
+    /// and rotate their outputs.
+    fn configure_output(
+        &mut self,
+        name: &str,
+        changes: &OutputChanges,
+        save: bool,
+    ) -> Result<(), String> {
+        let mode = changes
+            .mode
+            .as_deref()
+            .map(|mode| {
+                crate::config::parse_output_mode(mode.trim())
+                    .ok_or_else(|| format!("invalid mode: {mode}"))
+            })
+            .transpose()?;
+        if let Some(scale) = changes.scale
+            && !(scale.is_finite() && (0.25..=10.0).contains(&scale))
+        {
+            return Err(format!("invalid scale: {scale}"));
+        }
+        let transform = changes
+            .transform
+            .as_deref()
+            .map(|transform| {
+                crate::config::parse_transform(transform.trim())
+                    .ok_or_else(|| format!("invalid transform: {transform}"))
+            })
+            .transpose()?;
+
+        let output = self.space.outputs().find(|output| output.name() == name).cloned();
+        if output.is_none() && !self.udev_disabled_outputs().iter().any(|off| off == name) {
+            return Err(format!("no output named {name}"));
+        }
+
+        if self.udev.is_none() {
+            let Some(output) = output else {
+                return Err(format!("no output named {name}"));
+            };
+            if mode.is_some() || changes.enabled.is_some() {
+                return Err("modes can only be changed and outputs turned off on a TTY".into());
+            }
+            output.change_current_state(
+                None,
+                transform,
+                changes.scale.map(OutputScale::Fractional),
+                changes.position.map(Point::from),
+            );
+            if let Some(position) = changes.position {
+                self.space.map_output(&output, position);
+            }
+            self.output_modes_changed();
+        } else {
+            // Checked before the config changes, turned off outputs included, so a
+            // rejected request leaves everything as it was.
+            if let Some(mode) = mode
+                && !self.udev_supports_mode(name, mode)
+            {
+                return Err(format!("output {name} has no {}x{} mode", mode.width, mode.height));
+            }
+            if changes.enabled == Some(false) && self.space.outputs().count() <= 1 {
+                return Err("the last output can't be turned off".into());
+            }
+
+            let previous_configs = self.output_configs.clone();
+            let index = match self.output_configs.iter().position(|config| config.name == name) {
+                Some(index) => index,
+                None => {
+                    // A section for the monitor's identifier keeps applying, apart from
+                    // what is changed here.
+                    let identifier = self.udev_output_identifier(name);
+                    let inherited = crate::config::output_config(
+                        &self.output_configs,
+                        name,
+                        identifier.as_deref(),
+                    )
+                    .cloned();
+                    let config = inherited.unwrap_or(OutputConfig {
+                        name: String::new(),
+                        enabled: true,
+                        mode: None,
+                        position: None,
+                        scale: None,
+                        transform: None,
+                        force_enable: false,
+                    });
+                    self.output_configs
+                        .insert(0, OutputConfig { name: name.to_string(), ..config });
+                    0
+                }
+            };
+            if let Some(config) = self.output_configs.get_mut(index) {
+                config.mode = mode.or(config.mode);
+                config.scale = changes.scale.or(config.scale);
+                config.transform = transform.or(config.transform);
+                config.position = changes.position.or(config.position);
+                config.enabled = changes.enabled.unwrap_or(config.enabled);
+            }
+            self.apply_output_configs();
+
+            if let (Some(mode), Some(output)) = (mode, &output)
+                && output.current_mode().is_none_or(|current| {
+                    (current.size.w, current.size.h)
+                        != (i32::from(mode.width), i32::from(mode.height))
+                })
+            {
+                // The modeset failed, the output goes back to how it was configured.
+                self.output_configs = previous_configs;
+                self.apply_output_configs();
+                return Err(format!("failed to set the mode of output {name}"));
+            }
+        }
+
+        tracing::info!("Reconfigured output {name} via IPC: {changes:?}");
+        self.arrange_windows_tiled();
+        self.request_redraw_all();
+
+        if save {
+            let mut settings = Vec::new();
+            if let Some(mode) = &changes.mode {
+                settings.push(("mode", toml::Value::String(mode.trim().to_string())));
+            }
+            if let Some(scale) = changes.scale {
+                settings.push(("scale", toml::Value::Float(scale)));
+            }
+            if let Some(transform) = transform {
+                let name = crate::config::transform_name(transform).to_string();
+                settings.push(("transform", toml::Value::String(name)));
+            }
+            if let Some((x, y)) = changes.position {
+                settings.push(("position", toml::Value::String(format!("{x},{y}"))));
+            }
+            if let Some(enabled) = changes.enabled {
+                settings.push(("enabled", toml::Value::Boolean(enabled)));
+            }
+            crate::config::save_output(&self.config_path, name, &settings)?;
+        }
+        Ok(())
+    }
+
+    fn handle_ipc_request(&mut self, request: Request) -> Result<Reply, String> {
+        match request {
+            Request::Reload => {
+                self.reload_config()?;
+                Ok(Reply::Done)
+            }
+            Request::Keyboard { layout, variant } => {
+                let layout = layout.trim();
+                let variant = variant.trim();
+                if layout.is_empty() {
+                    return Err("missing keyboard layout".into());
+                }
+
+                let xkb_config =
+                    smithay::input::keyboard::XkbConfig { layout, variant, ..Default::default() };
+                self.seat.set_keymap(xkb_config).map_err(|err| {
+                    tracing::error!("Failed to update keyboard layout via IPC: {err}");
+                    err
+                })?;
+                tracing::info!(
+                    "Updated keyboard layout via IPC: layout={layout}, variant={variant}"
+                );
+                Ok(Reply::Done)
+            }
+            Request::Minimized => Ok(Reply::Minimized(
+                self.minimized
+                    .iter()
+                    .map(|window| crate::window::app_id(window).unwrap_or_default())
+                    .collect(),
+            )),
+            Request::Unminimize { app_id } => {
+                let app_id = app_id.trim();
+                let window = self
+                    .minimized
+                    .iter()
+                    .rev()
+                    .find(|window| crate::window::app_id(window).as_deref() == Some(app_id))
+                    .cloned()
+                    .ok_or_else(|| format!("no minimized window with app_id {app_id}"))?;
+                self.unminimize_window(&window);
+                Ok(Reply::Done)
+            }
+            Request::Windows => Ok(Reply::Windows(self.window_list())),
+            Request::Outputs => Ok(Reply::Outputs(self.output_list())),
+            Request::Workspaces => Ok(Reply::Workspaces(self.workspace_list())),
+            Request::Subscribe => Ok(Reply::Subscribed),
+            Request::Workspace { id, direction } => {
+                match (id, direction) {
+                    (Some(0), None) => return Err("workspaces are numbered from 1".into()),
+                    (Some(id), None) => self.select_workspace(id),
+                    (None, Some(direction)) => {
+                        let offset = match direction {
+                            Direction::Next => 1,
+                            Direction::Prev => -1,
+                        };
+                        let id = self.relative_workspace(offset).ok_or("no current workspace")?;
+                        self.switch_workspace(id);
+                    }
+                    _ => return Err("expected either a workspace id or a direction".into()),
+                }
+                Ok(Reply::Done)
+            }
+            Request::Exec { argv } => {
+                let pid = self.spawn(&argv).map_err(|err| {
+                    format!("failed to run {}: {err}", argv.first().map_or("", String::as_str))
+                })?;
+                tracing::info!("Started {argv:?} via IPC, pid {pid}");
+                Ok(Reply::Spawned(pid))
+            }
+            Request::Border { active, inactive, width } => {
+                let parse = |color: Option<String>| {
+                    color
+                        .map(|color| {
+                            crate::config::parse_hex_color(color.trim())
+                                .ok_or_else(|| format!("invalid colour: {color}"))
+                        })
+                        .transpose()
+                };
+                let active = parse(active)?;
+                let inactive = parse(inactive)?;
+                let width = width
+                    .map(|width| {
+                        i32::try_from(width).map_err(|_| format!("invalid border width: {width}"))
+                    })
+                    .transpose()?;
+
+                if let Some(active) = active {
+                    self.active_border_color = active;
+                }
+                if let Some(inactive) = inactive {
+                    self.inactive_border_color = inactive;
+                }
+                if let Some(width) = width {
+                    self.border_width = width;
+                    // Tiles leave room for borders drawn around them.
+                    if self.border_outside {
+                        self.arrange_windows_tiled();
+                    }
+                }
+                self.request_redraw_all();
+                Ok(Reply::Done)
+            }
+            Request::Focus { id, app_id, title, regex, warp } => {
+                let window = self.find_window(id, app_id.as_deref(), title.as_deref(), regex)?;
+                self.focus_window(&window);
+                if warp {
+                    self.warp_pointer_to(&window);
+                }
+                Ok(Reply::Done)
+            }
+            Request::Move { app_id, workspace, output } => {
+                let window = match app_id.as_deref() {
+                    Some(app_id) => self.find_window(None, Some(app_id), None, false)?,
+                    None => self
+                        .active_surface
+                        .as_ref()
+                        .and_then(|surface| self.window_for_surface(surface))
+                        .ok_or("no focused window")?,
+                };
+                let workspace = match (workspace, output) {
+                    (Some(0), None) => return Err("workspaces are numbered from 1".into()),
+                    (Some(workspace), None) => workspace,
+                    (None, Some(name)) => {
+                        let output = self
+                            .space
+                            .outputs()
+                            .find(|output| output.name() == name)
+                            .ok_or_else(|| format!("no output named {name}"))?;
+                        self.workspaces
+                            .active_on(output)
+                            .ok_or_else(|| format!("output {name} shows no workspace"))?
+                    }
+                    _ => return Err("expected either a workspace or an output".into()),
+                };
+                // Both workspaces are retiled, and focus moves on if the window leaves
+                // the screen.
+                let from = crate::window::workspace_of(&window);
+                self.move_window_to_workspace(&window, workspace);
+                let id = crate::window::id(&window);
+                tracing::info!("Moved window {id} to workspace {workspace} via IPC");
+                if from != workspace && crate::window::workspace_of(&window) == workspace {
+                    self.emit_ipc_event(&json!({
+                        "event": "window-moved",
+                        "id": id,
+                        "app_id": crate::window::app_id(&window).unwrap_or_default(),
+                        "workspace": workspace,
+                    }));
+                }
+                Ok(Reply::Done)
+            }
+            Request::Output { name, changes, save } => {
+                self.configure_output(&name, &changes, save)?;
+                Ok(Reply::Done)
+            }
+            Request::Wallpaper { value, output, save } => {
+                self.set_wallpaper(&value, output.as_deref(), save)?;
+                Ok(Reply::Done)
+            }
+            Request::Debug { redact_titles } => {
+                Ok(Reply::Debug(Box::new(self.debug_info(redact_titles))))
+            }
+            Request::DebugOverlay { state } => {
+                let enabled = match state {
+                    Toggle::On => true,
+                    Toggle::Off => false,
+                    Toggle::Toggle => !self.debug_overlay.enabled(),
+                };
+                self.set_debug_overlay(enabled);
+                Ok(Reply::Done)
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Logging to stderr and, with `log_file` or `$RIPWM_LOG`, to a file that is rotated by
+//! size so a warning repeated in a loop can't fill the disk.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::LogFile;
+
+/// Sets up logging. `$RUST_LOG` filters what goes to stderr, `$RIPWM_LOG_FILTER` what
+/// goes to the file and defaults to `$RUST_LOG` too.
+pub fn init(log_file: Option<LogFile>) {
+    let stderr = tracing_subscriber::fmt::layer().with_filter(env_filter(&["RUST_LOG"]));
+
+    let mut open_error = None;
+    let file = log_file.map(|log_file| {
+        let path = log_file.path.clone();
+        let mut writer = RotatingFile::new(log_file);
+        if let Err(err) = writer.open() {
+            open_error = Some(format!("Failed to open log file {}: {err}", path.display()));
+        }
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(Mutex::new(writer))
+            .with_filter(env_filter(&["RIPWM_LOG_FILTER", "RUST_LOG"]))
+    });
+
+    tracing_subscriber::registry().with(stderr).with(file).init();
+    if let Some(err) = open_error {
+        tracing::warn!("{err}");
+    }
+}
+
+/// The filter in the first of `vars` that is set and valid, `info` without one.
+fn env_filter(vars: &[&str]) -> EnvFilter {
+    vars.iter()
+        .find_map(|var| EnvFilter::try_from_env(var).ok())
+        .unwrap_or_else(|| EnvFilter::new("info"))
+}
+
+/// The log file, moved to `<path>.1` once it reaches its size, which moves to `<path>.2`
+/// and so on, up to the number of files kept. A file that can't be written is reopened
+/// on the next line, the lines in between are lost.
+struct RotatingFile {
+    config: LogFile,
+    file: Option<File>,
+    /// Bytes in the current file.
+    size: u64,
+}
+
+impl RotatingFile {
+    fn new(config: LogFile) -> Self {
+        Self { config, file: None, size: 0 }
+    }
+
+    fn open(&mut self) -> io::Result<&mut File> {
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.config.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new().create(true).append(true).open(&self.config.path)?;
+                self.size = file.metadata()?.len();
+                file
+            }
+        };
+        Ok(self.file.insert(file))
+    }
+
+    /// Moves the current file out of the way, dropping the oldest one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        self.size = 0;
+        if self.config.count <= 1 {
+            return fs::remove_file(&self.config.path);
+        }
+
+        for index in (1..self.config.count - 1).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.config.path, self.rotated_path(1))
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.config.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size.saturating_add(buf.len() as u64) > self.config.max_size {
+            // Better an oversized file than no log at all.
+            if let Err(err) = self.rotate() {
+                eprintln!("ripwm: failed to rotate {}: {err}", self.config.path.display());
+            }
+        }
+
+        let written = self.open().and_then(|file| file.write(buf));
+        match written {
+            Ok(written) => {
+                self.size += written as u64;
+                Ok(written)
+            }
+            Err(err) => {
+                self.file = None;
+                Err(err)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().map_or(Ok(()), File::flush)
+    }
+}
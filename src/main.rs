@@ -2,85 +2,85 @@
 
 mod handlers;
 
+mod autoreload;
+mod cli;
+mod clipboard;
 mod config;
+mod crash;
 mod cursor;
+mod debug;
 mod drawing;
+mod edid;
+mod grabs;
+mod headless;
+mod idle;
 mod input;
+mod ipc;
+mod logging;
+mod ping;
+mod rounded;
+mod screenshot;
+mod seat;
+mod spawn;
 mod state;
 mod udev;
+mod wallpaper;
+mod window;
 mod winit;
+mod workspace;
 
+use cli::{Backend, Options};
 use smithay::reexports::{calloop::EventLoop, wayland_server::Display};
 pub use state::Smallvil;
 use std::io::IsTerminal;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum Backend {
-    Winit,
-    TtyUdev,
-}
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_logging();
+    let options = Options::parse(std::env::args().skip(1))?;
+    // The log file is open before a backend starts, so its errors end up there too.
+    let starting = !options.help && !options.check_config;
+    logging::init(starting.then(|| config::log_file(&options.config_path())).flatten());
+    crash::install_panic_hook();
 
-    if wants_help() {
+    if options.help {
         print_help();
         return Ok(());
     }
+    if options.check_config {
+        check_config(&options.config_path());
+    }
 
-    let backend = select_backend()?;
+    let backend = options.backend.unwrap_or_else(detect_backend);
     tracing::info!("Selected backend: {:?}", backend);
 
     match backend {
         Backend::TtyUdev => {
-            crate::udev::run_udev()?;
+            crate::udev::run_udev(&options)?;
             Ok(())
         }
-        Backend::Winit => run_winit(),
+        Backend::Winit => run_winit(&options),
+        Backend::Headless => crate::headless::run_headless(&options),
     }
 }
 
-fn wants_help() -> bool {
-    std::env::args().skip(1).any(|arg| arg == "-h" || arg == "--help")
-}
-
 fn print_help() {
     println!(
-        "ripwm\n\nUsage:\n  ripwm [OPTIONS]\n\nOptions:\n  --tty-udev            Force DRM/udev backend\n  --winit               Force nested winit backend\n  -c, --command <CMD>   Spawn command inside compositor\n  -h, --help            Print help\n\nBackend selection:\n  If no backend flag is provided, ripwm auto-detects:\n  - Uses winit when running under Wayland/X11\n  - Uses tty-udev when started from a real Linux tty"
+        "ripwm\n\nUsage:\n  ripwm [OPTIONS]\n\nOptions:\n  --tty-udev              Force DRM/udev backend\n  --winit                 Force nested winit backend\n  --headless              Run without display or input devices, for tests\n  --headless-outputs <N>  Number of virtual outputs with --headless (default: 1)\n  --headless-size <WxH>   Size of each virtual output (default: 1920x1080)\n  --headless-render       Draw virtual outputs offscreen, so screenshots work\n  --winit-outputs <N>     Split the winit window into N outputs, closing it removes\n                          the one under the pointer (default: 1)\n  --software-cursor       Draw the pointer inside the winit window, as on a tty\n  --drm-device <PATH>     GPU to use with tty-udev, e.g. /dev/dri/card1\n                          (default: $RIPWM_DRM_DEVICE, or the primary GPU)\n  --config <PATH>         Config file to use (default: $RIPWM_CONFIG, or\n                          ~/.config/ripwm/ripwm.toml)\n  --check-config [PATH]   Check the config file, or PATH, print its problems and exit\n  -c, --command <CMD> [ARGS...]\n                          Spawn CMD inside compositor instead of the configured\n                          startup_command. Everything after -c belongs to it, a\n                          single quoted argument is split like a shell would\n  --no-spawn              Start no first client, not even the configured\n                          startup_command\n  -h, --help              Print help\n\nBackend selection:\n  If no backend flag is provided, ripwm auto-detects:\n  - Uses winit when running under Wayland/X11\n  - Uses tty-udev when started from a real Linux tty"
     );
 }
 
-fn select_backend() -> Result<Backend, Box<dyn std::error::Error>> {
-    if let Some(cli_backend) = parse_backend_override()? {
-        return Ok(cli_backend);
+/// Prints the problems of the config at `path` and exits, unsuccessfully if there are
+/// any.
+fn check_config(path: &std::path::Path) -> ! {
+    let problems = config::check_config(path);
+    for problem in &problems {
+        eprintln!("{}: {problem}", path.display());
     }
-
-    Ok(detect_backend())
-}
-
-fn parse_backend_override() -> Result<Option<Backend>, Box<dyn std::error::Error>> {
-    let mut selected_backend = None;
-
-    for arg in std::env::args().skip(1) {
-        let backend = match arg.as_str() {
-            "--tty-udev" => Some(Backend::TtyUdev),
-            "--winit" => Some(Backend::Winit),
-            _ => None,
-        };
-
-        if let Some(backend) = backend {
-            if let Some(existing) = selected_backend
-                && existing != backend
-            {
-                return Err(
-                    "Conflicting backend flags: use only one of --tty-udev or --winit".into()
-                );
-            }
-            selected_backend = Some(backend);
-        }
+    if problems.is_empty() {
+        println!("{}: no problems found", path.display());
+        std::process::exit(0);
     }
-
-    Ok(selected_backend)
+    eprintln!("{}: {} problem(s) found", path.display(), problems.len());
+    std::process::exit(1);
 }
 
 fn detect_backend() -> Backend {
@@ -102,58 +102,23 @@ fn detect_backend() -> Backend {
     Backend::Winit
 }
 
-fn run_winit() -> Result<(), Box<dyn std::error::Error>> {
+fn run_winit(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
     let mut event_loop: EventLoop<Smallvil> = EventLoop::try_new()?;
 
     let display: Display<Smallvil> = Display::new()?;
 
-    let mut state = Smallvil::new(&mut event_loop, display);
+    let mut state =
+        Smallvil::new(&mut event_loop, display, seat::WINIT_SEAT, options.config_path());
 
-    crate::winit::init_winit(&event_loop, &mut state)?;
+    crate::winit::init_winit(&event_loop, &mut state, options)?;
 
-    set_wayland_display(&state.socket_name);
+    state.run_autostart();
 
-    spawn_client();
+    if !options.no_spawn {
+        state.spawn_startup_client(options.command.as_deref());
+    }
 
     event_loop.run(None, &mut state, move |_| {})?;
 
     Ok(())
 }
-
-fn init_logging() {
-    if let Ok(env_filter) = tracing_subscriber::EnvFilter::try_from_default_env() {
-        tracing_subscriber::fmt().with_env_filter(env_filter).init();
-    } else {
-        tracing_subscriber::fmt().init();
-    }
-}
-
-pub(crate) fn spawn_client() {
-    let mut args = std::env::args().skip(1).peekable();
-
-    while matches!(args.peek().map(String::as_str), Some("--winit" | "--tty-udev")) {
-        let _ = args.next();
-    }
-
-    let flag = args.next();
-    let arg = args.next();
-
-    match (flag.as_deref(), arg) {
-        (Some("-c" | "--command"), Some(command)) => {
-            if let Err(err) = std::process::Command::new(command).spawn() {
-                tracing::error!("Failed to spawn command: {err}");
-            }
-        }
-        _ => {
-            if let Err(err) = std::process::Command::new("foot").spawn() {
-                tracing::error!("Failed to spawn foot: {err}");
-            }
-        }
-    }
-}
-
-pub(crate) fn set_wayland_display(socket_name: &std::ffi::OsStr) {
-    unsafe {
-        std::env::set_var("WAYLAND_DISPLAY", socket_name);
-    }
-}
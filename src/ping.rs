@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use smithay::{
+    desktop::Window,
+    reexports::calloop::{
+        RegistrationToken,
+        timer::{TimeoutAction, Timer},
+    },
+    utils::{SERIAL_COUNTER, Serial},
+    wayland::shell::xdg::{ShellClient, ToplevelSurface},
+};
+
+use crate::Smallvil;
+
+/// How long a client gets to answer a ping before its windows are marked unresponsive.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An xdg_wm_base ping that has not been answered yet.
+pub struct PendingPing {
+    /// The toplevel the ping was sent for. `ShellClient` can't be cloned, so the client is
+    /// looked up through it.
+    toplevel: ToplevelSurface,
+    serial: Serial,
+    timer: RegistrationToken,
+    /// The pong did not arrive within [`PING_TIMEOUT`].
+    unresponsive: bool,
+    /// The user asked one of the client's windows to close while the ping was pending.
+    close_requested: bool,
+}
+
+impl Smallvil {
+    /// Pings the client owning `window` unless a ping is already outstanding.
+    pub fn ping_window_client(&mut self, window: &Window) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+        let client = toplevel.client();
+        if self.pending_pings.iter().any(|ping| ping.toplevel.client() == client) {
+            return;
+        }
+
+        let serial = SERIAL_COUNTER.next_serial();
+        if let Err(err) = client.send_ping(serial) {
+            tracing::debug!("Failed to ping client: {err}");
+            return;
+        }
+
+        let timer = self.loop_handle.insert_source(
+            Timer::from_duration(PING_TIMEOUT),
+            move |_, (), state| {
+                if let Some(ping) =
+                    state.pending_pings.iter_mut().find(|ping| ping.serial == serial)
+                {
+                    ping.unresponsive = true;
+                    tracing::info!("Client did not answer ping within {PING_TIMEOUT:?}");
+                }
+                state.request_redraw_all();
+                TimeoutAction::Drop
+            },
+        );
+        let timer = match timer {
+            Ok(timer) => timer,
+            Err(err) => {
+                tracing::warn!("Failed to start ping timer: {err}");
+                return;
+            }
+        };
+
+        self.pending_pings.push(PendingPing {
+            toplevel: toplevel.clone(),
+            serial,
+            timer,
+            unresponsive: false,
+            close_requested: false,
+        });
+    }
+
+    /// Forgets the pending ping of `client`, after a pong or when it disconnects.
+    pub fn clear_ping(&mut self, client: &ShellClient) {
+        let Some(index) =
+            self.pending_pings.iter().position(|ping| &ping.toplevel.client() == client)
+        else {
+            return;
+        };
+
+        let ping = self.pending_pings.remove(index);
+        self.loop_handle.remove(ping.timer);
+        if ping.unresponsive {
+            self.request_redraw_all();
+        }
+    }
+
+    pub fn is_unresponsive(&self, window: &Window) -> bool {
+        window.toplevel().is_some_and(|toplevel| {
+            let client = toplevel.client();
+            self.pending_pings
+                .iter()
+                .any(|ping| ping.unresponsive && ping.toplevel.client() == client)
+        })
+    }
+
+    /// Asks `window` to close. Asking again while the client still hasn't answered the
+    /// ping sent with the first request kills the client.
+    pub fn close_window(&mut self, window: &Window) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+        let client = toplevel.client();
+
+        if let Some(ping) = self.pending_pings.iter().find(|ping| ping.toplevel.client() == client)
+            && (ping.close_requested || ping.unresponsive)
+        {
+            tracing::info!("Killing unresponsive client");
+            if let Err(err) = client.unresponsive() {
+                tracing::debug!("Failed to kill client: {err}");
+            }
+            return;
+        }
+
+        toplevel.send_close();
+        self.ping_window_client(window);
+        if let Some(ping) =
+            self.pending_pings.iter_mut().find(|ping| ping.toplevel.client() == client)
+        {
+            ping.close_requested = true;
+        }
+    }
+}
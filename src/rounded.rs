@@ -0,0 +1,446 @@
+//! Rounded corners: a pixel shader drawing borders as rounded outlines and a texture
+//! shader clipping window surfaces to the same radius.
+
+use smithay::{
+    backend::renderer::{
+        ImportAll, Renderer, Texture,
+        element::{
+            Element, Id, Kind, RenderElement, UnderlyingStorage,
+            surface::WaylandSurfaceRenderElement,
+        },
+        gles::{
+            GlesError, GlesFrame, GlesPixelProgram, GlesRenderer, GlesTexProgram, Uniform,
+            UniformName, UniformType, element::PixelShaderElement,
+        },
+        utils::{CommitCounter, DamageSet, OpaqueRegions},
+    },
+    utils::{Buffer, Logical, Physical, Point, Rectangle, Scale, Transform},
+};
+
+/// Renderers backed by a [`GlesRenderer`], which the rounding shaders need.
+pub trait AsGlesRenderer: Renderer {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer;
+
+    fn gles_frame<'a, 'frame, 'buffer>(
+        frame: &'a mut Self::Frame<'frame, 'buffer>,
+    ) -> &'a mut GlesFrame<'frame, 'buffer>
+    where
+        'buffer: 'frame,
+        Self: 'frame;
+
+    fn from_gles_error(err: GlesError) -> Self::Error;
+}
+
+impl AsGlesRenderer for GlesRenderer {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer {
+        self
+    }
+
+    fn gles_frame<'a, 'frame, 'buffer>(
+        frame: &'a mut Self::Frame<'frame, 'buffer>,
+    ) -> &'a mut GlesFrame<'frame, 'buffer>
+    where
+        'buffer: 'frame,
+        Self: 'frame,
+    {
+        frame
+    }
+
+    fn from_gles_error(err: GlesError) -> Self::Error {
+        err
+    }
+}
+
+const BORDER_SHADER: &str = r"
+precision mediump float;
+uniform float alpha;
+uniform vec2 size;
+varying vec2 v_coords;
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+uniform vec4 color;
+uniform float radius;
+uniform float border_width;
+
+float rounded_box(vec2 p, vec2 half_size, float r) {
+    vec2 q = abs(p) - half_size + vec2(r);
+    return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - r;
+}
+
+void main() {
+    vec2 p = v_coords * size - size / 2.0;
+    float outer = rounded_box(p, size / 2.0, radius);
+    float inner = rounded_box(p, size / 2.0 - vec2(border_width), max(radius - border_width, 0.0));
+    float coverage = clamp(0.5 - outer, 0.0, 1.0) * clamp(0.5 + inner, 0.0, 1.0);
+
+    vec4 result = color * coverage * alpha;
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        result = vec4(0.0, 0.2, 0.0, 0.2) + result * 0.8;
+#endif
+    gl_FragColor = result;
+}
+";
+
+const CLIP_SHADER: &str = r"
+//_DEFINES_
+
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+// All in physical pixels. The offset is where the element starts relative to the
+// window geometry.
+uniform vec2 geo_size;
+uniform vec2 element_offset;
+uniform vec2 element_size;
+uniform float radius;
+
+void main() {
+    vec4 color = texture2D(tex, v_coords);
+
+#if defined(NO_ALPHA)
+    color = vec4(color.rgb, 1.0) * alpha;
+#else
+    color = color * alpha;
+#endif
+
+    // Only the corners of the window geometry are cut, client side shadows and
+    // popups outside of it are left alone.
+    vec2 p = element_offset + v_coords * element_size;
+    if (p.x >= 0.0 && p.y >= 0.0 && p.x <= geo_size.x && p.y <= geo_size.y) {
+        vec2 q = abs(p - geo_size / 2.0) - geo_size / 2.0 + vec2(radius);
+        if (q.x > 0.0 && q.y > 0.0) {
+            color = color * clamp(0.5 - (length(q) - radius), 0.0, 1.0);
+        }
+    }
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.2, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+";
+
+#[derive(Clone)]
+pub struct RoundedShaders {
+    border: GlesPixelProgram,
+    clip: GlesTexProgram,
+}
+
+impl RoundedShaders {
+    /// The shaders of `renderer`, compiled on first use. `None` if they failed to
+    /// compile, callers then fall back to square corners.
+    pub fn get(renderer: &mut GlesRenderer) -> Option<Self> {
+        if let Some(shaders) = renderer.egl_context().user_data().get::<Option<Self>>() {
+            return shaders.clone();
+        }
+
+        let shaders = match Self::compile(renderer) {
+            Ok(shaders) => Some(shaders),
+            Err(err) => {
+                tracing::warn!("Failed to compile rounded corner shaders: {err}");
+                None
+            }
+        };
+        renderer.egl_context().user_data().insert_if_missing(|| shaders.clone());
+        shaders
+    }
+
+    fn compile(renderer: &mut GlesRenderer) -> Result<Self, GlesError> {
+        let border = renderer.compile_custom_pixel_shader(
+            BORDER_SHADER,
+            &[
+                UniformName::new("color", UniformType::_4f),
+                UniformName::new("radius", UniformType::_1f),
+                UniformName::new("border_width", UniformType::_1f),
+            ],
+        )?;
+        let clip = renderer.compile_custom_texture_shader(
+            CLIP_SHADER,
+            &[
+                UniformName::new("geo_size", UniformType::_2f),
+                UniformName::new("element_offset", UniformType::_2f),
+                UniformName::new("element_size", UniformType::_2f),
+                UniformName::new("radius", UniformType::_1f),
+            ],
+        )?;
+
+        Ok(Self { border, clip })
+    }
+}
+
+/// Rounding for one frame, `None` when `radius` is 0 or the shaders are unavailable.
+#[derive(Clone)]
+pub struct Rounding {
+    shaders: RoundedShaders,
+    radius: i32,
+}
+
+impl Rounding {
+    pub fn new<R: AsGlesRenderer>(renderer: &mut R, radius: i32) -> Option<Self> {
+        if radius <= 0 {
+            return None;
+        }
+        RoundedShaders::get(renderer.gles_renderer()).map(|shaders| Self { shaders, radius })
+    }
+
+    /// Clip for the surfaces of a window whose geometry is at `geometry`, relative to
+    /// the output.
+    pub fn clip(&self, geometry: Rectangle<i32, Logical>, scale: f64) -> CornerClip {
+        #[allow(clippy::cast_possible_truncation)]
+        let radius = (f64::from(self.radius) * scale) as f32;
+        CornerClip {
+            program: self.shaders.clip.clone(),
+            geometry: geometry.to_physical_precise_round(scale),
+            radius,
+        }
+    }
+
+    /// A rounded outline along the edge of `rect`, relative to the output. With `outside`
+    /// the window it belongs to sits `border_width` inside `rect`.
+    pub fn border(
+        &self,
+        rect: Rectangle<i32, Logical>,
+        color: [f32; 4],
+        border_width: i32,
+        outside: bool,
+    ) -> RoundedBorderElement {
+        // Around the window its inner edge follows the window's corners.
+        let radius = if outside { self.radius.saturating_add(border_width) } else { self.radius };
+        // Shaders work on premultiplied colours.
+        let [r, g, b, a] = color;
+        #[allow(clippy::cast_precision_loss)]
+        let uniforms = vec![
+            Uniform::new("color", [r * a, g * a, b * a, a]),
+            Uniform::new("radius", radius as f32),
+            Uniform::new("border_width", border_width as f32),
+        ];
+
+        RoundedBorderElement(PixelShaderElement::new(
+            self.shaders.border.clone(),
+            rect,
+            None,
+            1.0,
+            uniforms,
+            Kind::Unspecified,
+        ))
+    }
+}
+
+/// Where and how much to round the corners of one window.
+#[derive(Debug, Clone)]
+pub struct CornerClip {
+    program: GlesTexProgram,
+    geometry: Rectangle<i32, Physical>,
+    radius: f32,
+}
+
+impl CornerClip {
+    fn uniforms(&self, dst: Rectangle<i32, Physical>) -> Vec<Uniform<'static>> {
+        let offset = dst.loc - self.geometry.loc;
+        #[allow(clippy::cast_precision_loss)]
+        let vec2 = |x: i32, y: i32| [x as f32, y as f32];
+        vec![
+            Uniform::new("geo_size", vec2(self.geometry.size.w, self.geometry.size.h)),
+            Uniform::new("element_offset", vec2(offset.x, offset.y)),
+            Uniform::new("element_size", vec2(dst.size.w, dst.size.h)),
+            Uniform::new("radius", self.radius),
+        ]
+    }
+
+    /// The squares at the corners of the geometry, relative to `element_geometry`.
+    fn corners(&self, element_geometry: Rectangle<i32, Physical>) -> [Rectangle<i32, Physical>; 4] {
+        #[allow(clippy::cast_possible_truncation)]
+        let radius = self.radius.ceil() as i32;
+        let geo = self.geometry;
+        let loc = geo.loc - element_geometry.loc;
+        let size = (radius, radius);
+        [
+            Rectangle::new(loc, size.into()),
+            Rectangle::new((loc.x + geo.size.w - radius, loc.y).into(), size.into()),
+            Rectangle::new((loc.x, loc.y + geo.size.h - radius).into(), size.into()),
+            Rectangle::new(
+                (loc.x + geo.size.w - radius, loc.y + geo.size.h - radius).into(),
+                size.into(),
+            ),
+        ]
+    }
+}
+
+/// A window surface, drawn with its corners cut when a [`CornerClip`] is set.
+pub struct RoundedSurfaceElement<R: Renderer> {
+    inner: WaylandSurfaceRenderElement<R>,
+    clip: Option<CornerClip>,
+}
+
+impl<R: Renderer> RoundedSurfaceElement<R> {
+    pub fn new(inner: WaylandSurfaceRenderElement<R>, clip: Option<CornerClip>) -> Self {
+        Self { inner, clip }
+    }
+}
+
+impl<R: Renderer> std::fmt::Debug for RoundedSurfaceElement<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoundedSurfaceElement")
+            .field("inner", &self.inner)
+            .field("clip", &self.clip)
+            .finish()
+    }
+}
+
+impl<R: Renderer> Element for RoundedSurfaceElement<R> {
+    fn id(&self) -> &Id {
+        self.inner.id()
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        self.inner.current_commit()
+    }
+
+    fn location(&self, scale: Scale<f64>) -> Point<i32, Physical> {
+        self.inner.location(scale)
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        self.inner.src()
+    }
+
+    fn transform(&self) -> Transform {
+        self.inner.transform()
+    }
+
+    fn geometry(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        self.inner.geometry(scale)
+    }
+
+    fn damage_since(
+        &self,
+        scale: Scale<f64>,
+        commit: Option<CommitCounter>,
+    ) -> DamageSet<i32, Physical> {
+        self.inner.damage_since(scale, commit)
+    }
+
+    fn opaque_regions(&self, scale: Scale<f64>) -> OpaqueRegions<i32, Physical> {
+        let regions = self.inner.opaque_regions(scale);
+        let Some(clip) = &self.clip else {
+            return regions;
+        };
+
+        // Whatever is below the cut corners has to be drawn.
+        let corners = clip.corners(self.inner.geometry(scale));
+        Rectangle::subtract_rects_many(regions.iter().copied(), corners).into_iter().collect()
+    }
+
+    fn alpha(&self) -> f32 {
+        self.inner.alpha()
+    }
+
+    fn kind(&self) -> Kind {
+        self.inner.kind()
+    }
+}
+
+impl<R> RenderElement<R> for RoundedSurfaceElement<R>
+where
+    R: AsGlesRenderer + ImportAll,
+    R::TextureId: Texture + 'static,
+{
+    fn draw(
+        &self,
+        frame: &mut R::Frame<'_, '_>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        opaque_regions: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        let Some(clip) = &self.clip else {
+            return self.inner.draw(frame, src, dst, damage, opaque_regions);
+        };
+
+        R::gles_frame(frame).override_default_tex_program(clip.program.clone(), clip.uniforms(dst));
+        let result = self.inner.draw(frame, src, dst, damage, opaque_regions);
+        R::gles_frame(frame).clear_tex_program_override();
+        result
+    }
+
+    fn underlying_storage(&self, renderer: &mut R) -> Option<UnderlyingStorage<'_>> {
+        // A plane can't cut the corners.
+        if self.clip.is_some() { None } else { self.inner.underlying_storage(renderer) }
+    }
+}
+
+/// A rounded border outline. Wraps [`PixelShaderElement`] so it can be drawn by the
+/// udev multi-GPU renderer too.
+#[derive(Debug, Clone)]
+pub struct RoundedBorderElement(PixelShaderElement);
+
+impl Element for RoundedBorderElement {
+    fn id(&self) -> &Id {
+        self.0.id()
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        self.0.current_commit()
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        self.0.src()
+    }
+
+    fn geometry(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        self.0.geometry(scale)
+    }
+
+    fn opaque_regions(&self, scale: Scale<f64>) -> OpaqueRegions<i32, Physical> {
+        self.0.opaque_regions(scale)
+    }
+
+    fn alpha(&self) -> f32 {
+        self.0.alpha()
+    }
+
+    fn kind(&self) -> Kind {
+        self.0.kind()
+    }
+}
+
+impl<R: AsGlesRenderer> RenderElement<R> for RoundedBorderElement {
+    fn draw(
+        &self,
+        frame: &mut R::Frame<'_, '_>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        opaque_regions: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        RenderElement::<GlesRenderer>::draw(
+            &self.0,
+            R::gles_frame(frame),
+            src,
+            dst,
+            damage,
+            opaque_regions,
+        )
+        .map_err(R::from_gles_error)
+    }
+}
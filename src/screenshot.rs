@@ -0,0 +1,197 @@
+//! Screenshots: the next frame of an output is rendered a second time into an offscreen
+//! texture, read back and written as PNG by a worker thread.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::{ImageFormat, RgbaImage, imageops};
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            ExportMem, Offscreen, Renderer, Texture, TextureMapping, damage::OutputDamageTracker,
+            element::RenderElement, gles::GlesTexture,
+        },
+    },
+    output::Output,
+    utils::{Logical, Rectangle, Transform},
+};
+
+use crate::Smallvil;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotTarget {
+    /// The whole current output.
+    Output,
+    /// The focused window's geometry.
+    Window,
+}
+
+/// A screenshot waiting for the next frame of `output`.
+#[derive(Debug)]
+pub struct ScreenshotRequest {
+    pub output: Output,
+    /// Part of the output to keep, relative to it. `None` keeps everything.
+    pub crop: Option<Rectangle<i32, Logical>>,
+}
+
+impl Smallvil {
+    /// Takes a screenshot with the next frame drawn to the current output.
+    pub fn screenshot(&mut self, target: ScreenshotTarget) {
+        let Some(output) = self.current_output() else {
+            tracing::warn!("No output to take a screenshot of");
+            return;
+        };
+
+        let crop = match target {
+            ScreenshotTarget::Output => None,
+            ScreenshotTarget::Window => {
+                let Some(rect) = self.focused_window_rect(&output) else {
+                    tracing::warn!("No focused window to take a screenshot of");
+                    return;
+                };
+                Some(rect)
+            }
+        };
+
+        self.pending_screenshot = Some(ScreenshotRequest { output, crop });
+        self.request_redraw_all();
+    }
+
+    /// The geometry of the focused window relative to `output`.
+    fn focused_window_rect(&self, output: &Output) -> Option<Rectangle<i32, Logical>> {
+        let window =
+            self.active_surface.as_ref().and_then(|surface| self.window_for_surface(surface))?;
+        let location = self.space.element_location(&window)?;
+        let output_geo = self.space.output_geometry(output)?;
+        Some(Rectangle::new(location - output_geo.loc, window.geometry().size))
+    }
+}
+
+/// Renders `elements` the way they'd appear on the request's output and reads them back.
+/// Backends call this after drawing a frame of an output with a pending request.
+pub fn capture<R, E>(
+    renderer: &mut R,
+    request: &ScreenshotRequest,
+    elements: &[E],
+) -> Result<RgbaImage, Box<dyn Error>>
+where
+    R: Renderer + Offscreen<GlesTexture> + ExportMem,
+    R::TextureId: Texture,
+    R::Error: 'static,
+    E: RenderElement<R>,
+{
+    let output = &request.output;
+    let mode = output.current_mode().ok_or("output has no mode")?;
+    let scale = output.current_scale().fractional_scale();
+    // Elements are laid out for the transformed output, the screenshot shows it upright.
+    let size = output.current_transform().transform_size(mode.size);
+    let buffer_size = size.to_logical(1).to_buffer(1, Transform::Normal);
+
+    let mut texture = renderer.create_buffer(Fourcc::Abgr8888, buffer_size)?;
+    let mut framebuffer = renderer.bind(&mut texture)?;
+    let mut damage_tracker = OutputDamageTracker::new(size, scale, Transform::Normal);
+    damage_tracker.render_output(renderer, &mut framebuffer, 0, elements, [0.0, 0.0, 0.0, 1.0])?;
+
+    let mapping = renderer.copy_framebuffer(
+        &framebuffer,
+        Rectangle::from_size(buffer_size),
+        Fourcc::Abgr8888,
+    )?;
+    let flipped = mapping.flipped();
+    let pixels = renderer.map_texture(&mapping)?.to_vec();
+
+    let (width, height) = (u32::try_from(size.w)?, u32::try_from(size.h)?);
+    let mut image =
+        RgbaImage::from_raw(width, height, pixels).ok_or("read back fewer pixels than expected")?;
+    if !flipped {
+        imageops::flip_vertical_in_place(&mut image);
+    }
+
+    let Some(crop) = request.crop else {
+        return Ok(image);
+    };
+    let crop = crop.to_physical_precise_round(scale).intersection(Rectangle::from_size(size));
+    let Some(crop) = crop.filter(|crop| !crop.is_empty()) else {
+        return Err("window is outside of its output".into());
+    };
+    Ok(imageops::crop_imm(
+        &image,
+        u32::try_from(crop.loc.x)?,
+        u32::try_from(crop.loc.y)?,
+        u32::try_from(crop.size.w)?,
+        u32::try_from(crop.size.h)?,
+    )
+    .to_image())
+}
+
+/// Writes `image` into `dir` from a worker thread, so encoding never stalls a frame.
+pub fn save(image: RgbaImage, dir: PathBuf) {
+    let spawned = thread::Builder::new().name("screenshot".into()).spawn(move || {
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            tracing::warn!("Failed to create screenshot directory {}: {err}", dir.display());
+            return;
+        }
+
+        let path = dir.join(file_name());
+        match image.save_with_format(&path, ImageFormat::Png) {
+            Ok(()) => tracing::info!("Saved screenshot to {}", path.display()),
+            Err(err) => tracing::warn!("Failed to save screenshot {}: {err}", path.display()),
+        }
+    });
+
+    if let Err(err) = spawned {
+        tracing::warn!("Failed to start screenshot thread: {err}");
+    }
+}
+
+/// Where screenshots go: the configured directory, then `$XDG_PICTURES_DIR`, then
+/// `~/Pictures`.
+pub fn directory(configured: Option<&Path>) -> PathBuf {
+    if let Some(dir) = configured {
+        return dir.to_path_buf();
+    }
+
+    if let Some(dir) = std::env::var_os("XDG_PICTURES_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join("Pictures"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// `ripwm-YYYY-MM-DD-HHMMSS-mmm.png`, in UTC.
+fn file_name() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let time = secs % 86_400;
+    format!(
+        "ripwm-{year:04}-{month:02}-{day:02}-{:02}{:02}{:02}-{:03}.png",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's algorithm, shifted so years start in March.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,94 @@
+//! Seats: a keyboard and pointer shared by the input devices assigned to them, and the
+//! cursor they show. There is one seat for now, named after the libseat seat on the udev
+//! backend so it matches the seat libinput devices are assigned to.
+
+use smithay::{
+    input::{
+        Seat, SeatState,
+        keyboard::{KeyboardHandle, XkbConfig, xkb},
+        pointer::{CursorImageStatus, PointerHandle},
+    },
+    reexports::wayland_server::{DisplayHandle, protocol::wl_surface::WlSurface},
+    utils::{Logical, Point},
+};
+
+use crate::Smallvil;
+
+/// Name of the seat on the nested backend, which has no libseat session.
+pub const WINIT_SEAT: &str = "winit";
+/// Name of the seat on the headless backend, which has no input devices at all.
+pub const HEADLESS_SEAT: &str = "headless";
+
+pub struct SeatData {
+    pub seat: Seat<Smallvil>,
+    /// What the cursor of this seat looks like, as last set by a client or a grab.
+    pub cursor_status: CursorImageStatus,
+    /// The icon of the drag-and-drop in progress, if its client gave one.
+    pub dnd_icon: Option<DndIcon>,
+}
+
+/// The surface a client shows at the pointer while it drags something.
+pub struct DndIcon {
+    pub surface: WlSurface,
+    /// Where the icon is drawn relative to the pointer, moved by the offsets the client
+    /// attaches its buffers with.
+    pub offset: Point<i32, Logical>,
+}
+
+impl SeatData {
+    /// Creates the seat `name` with a keyboard using `xkb_config` and a pointer.
+    pub fn new(
+        seat_state: &mut SeatState<Smallvil>,
+        dh: &DisplayHandle,
+        name: &str,
+        xkb_config: XkbConfig<'_>,
+    ) -> Self {
+        let mut seat = seat_state.new_wl_seat(dh, name);
+
+        if let Err(err) = seat.add_keyboard(xkb_config, 200, 25) {
+            tracing::error!("Failed to add keyboard to seat {name}: {err}");
+        }
+        seat.add_pointer();
+
+        Self { seat, cursor_status: CursorImageStatus::default_named(), dnd_icon: None }
+    }
+
+    pub fn keyboard(&self) -> Option<KeyboardHandle<Smallvil>> {
+        self.seat.get_keyboard()
+    }
+
+    pub fn pointer(&self) -> Option<PointerHandle<Smallvil>> {
+        self.seat.get_pointer()
+    }
+
+    /// Replaces the keyboard with one using `xkb_config`. The keymap is compiled first, a
+    /// layout or variant xkb doesn't know leaves the current keyboard alone.
+    pub fn set_keymap(&mut self, xkb_config: XkbConfig<'_>) -> Result<(), String> {
+        validate_keymap(&xkb_config)?;
+        self.seat.add_keyboard(xkb_config, 200, 25).map(|_| ()).map_err(|err| err.to_string())
+    }
+}
+
+/// Checks that xkb can compile a keymap for `xkb_config`.
+pub fn validate_keymap(xkb_config: &XkbConfig<'_>) -> Result<(), String> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(
+        &context,
+        xkb_config.rules,
+        xkb_config.model,
+        xkb_config.layout,
+        xkb_config.variant,
+        xkb_config.options.clone(),
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    );
+    match keymap {
+        Some(_) => Ok(()),
+        None if xkb_config.variant.is_empty() => {
+            Err(format!("unknown layout '{}'", xkb_config.layout))
+        }
+        None => Err(format!(
+            "unknown layout '{}' with variant '{}'",
+            xkb_config.layout, xkb_config.variant
+        )),
+    }
+}
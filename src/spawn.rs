@@ -0,0 +1,241 @@
+//! Starting programs inside the session.
+
+use std::{
+    ffi::OsStr,
+    io,
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+use smithay::reexports::{
+    calloop::{Interest, Mode, PostAction, generic::Generic},
+    rustix::process::{Pid, PidfdFlags, pidfd_open},
+};
+
+use crate::Smallvil;
+
+impl Smallvil {
+    /// Starts `argv` as a client of this compositor and returns its PID. The child gets
+    /// its own process group, so it outlives whatever asked for it, a terminal closing
+    /// included. It is reaped once it exits.
+    pub fn spawn(&self, argv: &[String]) -> io::Result<u32> {
+        let Some(program) = argv.first() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+        };
+
+        let mut command = client_command(
+            argv,
+            &self.socket_name,
+            self.ipc_socket_path.as_deref(),
+            &self.spawn_env,
+        )?;
+        let child = command.stdin(Stdio::null()).process_group(0).spawn()?;
+        let pid = child.id();
+        self.reap_on_exit(program.clone(), child);
+        Ok(pid)
+    }
+
+    /// Waits for `child` to exit so it doesn't linger as a zombie. Only this child is
+    /// waited for, processes spawned and waited for elsewhere are left alone. The exit is
+    /// noticed through a pidfd in the event loop, or a thread on kernels without pidfds.
+    fn reap_on_exit(&self, program: String, mut child: Child) {
+        let pidfd = i32::try_from(child.id())
+            .ok()
+            .and_then(Pid::from_raw)
+            .ok_or(io::ErrorKind::InvalidInput.into())
+            .and_then(|pid| pidfd_open(pid, PidfdFlags::NONBLOCK).map_err(io::Error::from));
+        let pidfd = match pidfd {
+            Ok(pidfd) => pidfd,
+            Err(err) => {
+                tracing::debug!("No pidfd for {program}, waiting in a thread: {err}");
+                std::thread::spawn(move || log_exit(&program, &mut child));
+                return;
+            }
+        };
+
+        let source = Generic::new(pidfd, Interest::READ, Mode::Level);
+        let inserted = self.loop_handle.insert_source(source, move |_, _, _| {
+            log_exit(&program, &mut child);
+            Ok(PostAction::Remove)
+        });
+        if let Err(err) = inserted {
+            tracing::warn!("Failed to watch a spawned process, it won't be reaped: {err}");
+        }
+    }
+
+    /// Starts the `exec` and `exec_always` entries of the config, once the Wayland socket
+    /// is up.
+    pub fn run_autostart(&self) {
+        self.run_exec_entries("exec", &self.exec);
+        self.run_exec_entries("exec_always", &self.exec_always);
+    }
+
+    /// Starts each command line in `entries`, `key` names the config option they come
+    /// from in the log.
+    pub fn run_exec_entries(&self, key: &str, entries: &[String]) {
+        for entry in entries {
+            self.spawn_command_line(key, entry);
+        }
+    }
+
+    /// Starts the first client: `command` from `-c`, or else the configured
+    /// `startup_command`.
+    pub fn spawn_startup_client(&self, command: Option<&[String]>) {
+        match command {
+            Some(argv) => match self.spawn(argv) {
+                Ok(pid) => tracing::info!("Started --command `{}` with PID {pid}", argv.join(" ")),
+                Err(err) => {
+                    tracing::error!("Failed to start --command `{}`: {err}", argv.join(" "))
+                }
+            },
+            None => {
+                if let Some(command) = self.startup_command.as_deref() {
+                    self.spawn_command_line("startup_command", command);
+                }
+            }
+        }
+    }
+
+    /// Splits `line` into arguments and starts it. Failures are logged, `key` names the
+    /// option the command came from so a typo is easy to find.
+    pub fn spawn_command_line(&self, key: &str, line: &str) {
+        match split_command(line).and_then(|argv| self.spawn(&argv)) {
+            Ok(pid) => tracing::info!("Started {key} `{line}` with PID {pid}"),
+            Err(err) => tracing::error!("Failed to start {key} `{line}`: {err}"),
+        }
+    }
+}
+
+/// Splits a command line into arguments the way a shell would, minus expansions:
+/// whitespace separates them, single quotes keep everything, double quotes and
+/// backslashes keep whitespace.
+pub fn split_command(line: &str) -> io::Result<Vec<String>> {
+    let mut argv = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                argv.extend(current.take());
+            }
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated("'")),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(unterminated("\"")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated("\"")),
+                    }
+                }
+            }
+            '\\' => {
+                let arg = current.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    arg.push(c);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    argv.extend(current);
+
+    if argv.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+    }
+    Ok(argv)
+}
+
+/// The command running `argv` as a client: its environment is ripwm's with `env` from
+/// the config on top, and the sockets of this instance. Only the child's environment
+/// is changed, ripwm's own `WAYLAND_DISPLAY` stays that of the host, if any.
+fn client_command(
+    argv: &[String],
+    socket_name: &OsStr,
+    ipc_socket: Option<&Path>,
+    env: &[(String, String)],
+) -> io::Result<Command> {
+    let Some((program, args)) = argv.split_first() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command"));
+    };
+
+    // DISPLAY too, once there is XWayland.
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .env("WAYLAND_DISPLAY", socket_name);
+    if let Some(path) = ipc_socket {
+        command.env("RIPWM_IPC_SOCKET", path);
+    }
+    Ok(command)
+}
+
+/// Reaps `child`, which has exited or is about to, and logs how it ended.
+fn log_exit(program: &str, child: &mut Child) {
+    let pid = child.id();
+    match child.wait() {
+        Ok(status) => tracing::debug!("{program} (PID {pid}) exited: {status}"),
+        Err(err) => tracing::debug!("Failed to reap {program} (PID {pid}): {err}"),
+    }
+}
+
+fn unterminated(quote: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("unterminated {quote} quote"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_clients_get_the_compositor_socket() {
+        let before = std::env::var_os("WAYLAND_DISPLAY");
+        let argv = [
+            "sh",
+            "-c",
+            "printf '%s %s %s' \"$WAYLAND_DISPLAY\" \"$RIPWM_IPC_SOCKET\" \"$GREETING\"",
+        ]
+        .map(String::from);
+        let env = [(String::from("GREETING"), String::from("hello"))];
+
+        let output = client_command(
+            &argv,
+            OsStr::new("wayland-test"),
+            Some(Path::new("/run/ripwm-wayland-test.sock")),
+            &env,
+        )
+        .and_then(|mut command| command.output())
+        .expect("failed to run sh");
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "wayland-test /run/ripwm-wayland-test.sock hello"
+        );
+        assert_eq!(std::env::var_os("WAYLAND_DISPLAY"), before);
+    }
+
+    #[test]
+    fn empty_commands_are_errors() {
+        assert!(client_command(&[], OsStr::new("wayland-test"), None, &[]).is_err());
+    }
+}
@@ -1,34 +1,66 @@
-use std::{ffi::OsString, io::Read, os::unix::net::UnixListener, path::PathBuf, sync::Arc};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use smithay::{
-    desktop::{PopupManager, Space, Window, WindowSurfaceType},
+    backend::renderer::element::{RenderElementStates, default_primary_scanout_output_compare},
+    desktop::{
+        PopupManager, Space, Window, WindowSurfaceType,
+        utils::{
+            send_frames_surface_tree, surface_primary_scanout_output,
+            update_surface_primary_scanout_output, with_surfaces_surface_tree,
+        },
+    },
+    input::SeatState,
     input::pointer::CursorImageStatus,
-    input::{Seat, SeatState},
+    output::Output,
     reexports::{
-        calloop::{EventLoop, Interest, LoopSignal, Mode, PostAction, generic::Generic},
+        calloop::{
+            EventLoop, Interest, LoopHandle, LoopSignal, Mode, PostAction, generic::Generic,
+        },
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
         wayland_server::{
-            Display, DisplayHandle,
+            Display, DisplayHandle, Resource,
             backend::{ClientData, ClientId, DisconnectReason},
             protocol::wl_surface::WlSurface,
         },
     },
-    utils::{Logical, Point, Rectangle},
+    utils::{Logical, Point, Rectangle, SERIAL_COUNTER},
     wayland::{
-        compositor::{CompositorClientState, CompositorState},
+        alpha_modifier::AlphaModifierState,
+        compositor::{CompositorClientState, CompositorState, SurfaceData},
+        idle_inhibit::IdleInhibitManagerState,
+        idle_notify::IdleNotifierState,
         output::OutputManagerState,
         selection::data_device::DataDeviceState,
-        shell::xdg::{XdgShellState, decoration::XdgDecorationState},
+        shell::xdg::{ToplevelSurface, XdgShellState, decoration::XdgDecorationState},
         shm::ShmState,
         socket::ListeningSocketSource,
+        xdg_foreign::XdgForeignState,
     },
 };
 
+/// Time between two frames of `output` at its current refresh rate, 60Hz if unknown.
+pub fn frame_interval(output: &Output) -> Duration {
+    let refresh = output.current_mode().map_or(0, |mode| mode.refresh);
+    if refresh > 0 {
+        Duration::from_secs_f64(1000.0 / f64::from(refresh))
+    } else {
+        Duration::from_micros(16_667)
+    }
+}
+
 pub struct Smallvil {
     pub start_time: std::time::Instant,
     pub socket_name: OsString,
     pub display_handle: DisplayHandle,
 
     pub space: Space<Window>,
+    pub loop_handle: LoopHandle<'static, Self>,
     pub loop_signal: LoopSignal,
 
     pub compositor_state: CompositorState,
@@ -38,25 +70,81 @@ pub struct Smallvil {
     pub output_manager_state: OutputManagerState,
     pub seat_state: SeatState<Self>,
     pub data_device_state: DataDeviceState,
+    pub alpha_modifier_state: AlphaModifierState,
+    pub xdg_foreign_state: XdgForeignState,
+    pub toplevel_drag_state: crate::handlers::toplevel_drag::ToplevelDragState,
+    pub ext_workspace_state: crate::handlers::ext_workspace::ExtWorkspaceState,
+    pub foreign_toplevel_state: crate::handlers::foreign_toplevel::ForeignToplevelState,
+    pub virtual_pointer_state: crate::handlers::virtual_pointer::VirtualPointerManagerState,
     pub popups: PopupManager,
-    pub cursor_status: CursorImageStatus,
-
-    pub seat: Seat<Self>,
-    pub wallpaper: crate::config::WallpaperState,
+    pub cursor_hiding: crate::cursor::CursorHiding,
+    /// `software_cursor` from the config, the nested window draws the pointer itself.
+    pub software_cursor: bool,
+    pub idle: crate::idle::IdleState,
+    pub idle_notifier_state: IdleNotifierState<Self>,
+    pub idle_inhibit_manager_state: IdleInhibitManagerState,
+
+    pub seat: crate::seat::SeatData,
+    pub workspaces: crate::workspace::Workspaces,
+    /// Minimized windows, most recently minimized last. They are unmapped from the space.
+    pub minimized: Vec<Window>,
+    pub pending_pings: Vec<crate::ping::PendingPing>,
+    pub clipboard: crate::clipboard::ClipboardCache,
+    pub wallpaper: crate::wallpaper::Wallpapers,
     pub active_surface: Option<WlSurface>,
     pub active_border_color: [f32; 4],
     pub inactive_border_color: [f32; 4],
+    pub unresponsive_border_color: [f32; 4],
     pub border_width: i32,
+    /// Borders surround windows instead of covering their edges, tiles shrink to fit them.
+    pub border_outside: bool,
+    pub color_depth: crate::config::ColorDepth,
+    pub window_rules: Vec<crate::config::WindowRule>,
+    pub output_configs: Vec<crate::config::OutputConfig>,
+    pub inactive_dim: f32,
+    pub corner_radius: i32,
+    pub toplevel_drop_mode: crate::config::ToplevelDropMode,
+    pub maximize_mode: crate::config::MaximizeMode,
+    /// Command line Super+Return starts.
+    pub terminal: String,
+    /// The config's `[map_to_output]`: outputs of absolute pointer devices by name.
+    pub map_to_output: Vec<(String, String)>,
+    /// Outputs libinput associates absolute devices with, by device id.
+    pub device_outputs: Vec<(String, String)>,
+    /// The first client, unless `-c` gave one.
+    pub startup_command: Option<String>,
+    /// The config's `[env]`, set for every program started.
+    pub spawn_env: Vec<(String, String)>,
+    /// Command lines started once, at startup.
+    pub exec: Vec<String>,
+    /// Command lines started at startup and again on every reload.
+    pub exec_always: Vec<String>,
     pub config_path: PathBuf,
-    pub ipc_socket_path: PathBuf,
+    /// Watches the config file while `auto_reload` is on.
+    pub config_watch: Option<crate::autoreload::ConfigWatch>,
+    /// The IPC socket, once this instance listens on it.
+    pub ipc_socket_path: Option<PathBuf>,
+    /// IPC connections kept open by `subscribe`, each gets a line of JSON per event.
+    pub ipc_subscribers: Vec<UnixStream>,
+    pub debug_overlay: crate::debug::DebugOverlay,
+    pub screenshot_dir: Option<PathBuf>,
+    pub pending_screenshot: Option<crate::screenshot::ScreenshotRequest>,
     pub udev: Option<crate::udev::UdevData>,
+    pub winit: Option<crate::winit::WinitData>,
+    pub headless: Option<crate::headless::HeadlessData>,
 }
 
 impl Smallvil {
-    pub fn new(event_loop: &mut EventLoop<Self>, display: Display<Self>) -> Self {
+    /// Creates the compositor state with one seat called `seat_name`, configured from the
+    /// file at `config_path`.
+    pub fn new(
+        event_loop: &mut EventLoop<'static, Self>,
+        display: Display<Self>,
+        seat_name: &str,
+        config_path: PathBuf,
+    ) -> Self {
         let start_time = std::time::Instant::now();
-        let config_path = crate::config::config_path();
-        let config = crate::config::load_or_create_config();
+        let config = crate::config::load_or_create_config(&config_path);
 
         let dh = display.handle();
 
@@ -69,35 +157,38 @@ impl Smallvil {
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
 
         let data_device_state = DataDeviceState::new::<Self>(&dh);
+        let alpha_modifier_state = AlphaModifierState::new::<Self>(&dh);
+        let xdg_foreign_state = XdgForeignState::new::<Self>(&dh);
+        let idle_notifier_state = IdleNotifierState::new(&dh, event_loop.handle());
+        let idle_inhibit_manager_state = IdleInhibitManagerState::new::<Self>(&dh);
+        let toplevel_drag_state = crate::handlers::toplevel_drag::ToplevelDragState::new(&dh);
+        let ext_workspace_state = crate::handlers::ext_workspace::ExtWorkspaceState::new(&dh);
+        let foreign_toplevel_state =
+            crate::handlers::foreign_toplevel::ForeignToplevelState::new(&dh);
+        let virtual_pointer_state =
+            crate::handlers::virtual_pointer::VirtualPointerManagerState::new(&dh);
 
         let mut seat_state = SeatState::new();
-        let mut seat: Seat<Self> = seat_state.new_wl_seat(&dh, "winit");
-
         let xkb_config = smithay::input::keyboard::XkbConfig {
             layout: &config.keyboard_layout,
             variant: &config.keyboard_variant,
             ..Default::default()
         };
-
-        if let Err(err) = seat.add_keyboard(xkb_config, 200, 25) {
-            tracing::error!("Failed to add keyboard to seat: {err}");
-        }
-
-        seat.add_pointer();
+        let seat = crate::seat::SeatData::new(&mut seat_state, &dh, seat_name, xkb_config);
 
         let space = Space::default();
 
         let socket_name = Self::init_wayland_listener(display, event_loop);
 
         let loop_signal = event_loop.get_signal();
-        let wallpaper = crate::config::WallpaperState::from_config(&config);
-        let ipc_socket_path = ipc_socket_path();
+        let wallpaper = crate::wallpaper::Wallpapers::new(&config, &event_loop.handle());
 
         let mut state = Self {
             start_time,
             display_handle: dh,
 
             space,
+            loop_handle: event_loop.handle(),
             loop_signal,
             socket_name,
 
@@ -108,49 +199,109 @@ impl Smallvil {
             output_manager_state,
             seat_state,
             data_device_state,
+            alpha_modifier_state,
+            xdg_foreign_state,
+            toplevel_drag_state,
+            ext_workspace_state,
+            foreign_toplevel_state,
+            virtual_pointer_state,
             popups,
-            cursor_status: CursorImageStatus::default_named(),
+            cursor_hiding: crate::cursor::CursorHiding::new(
+                config.cursor_timeout,
+                config.hide_cursor_on_typing,
+            ),
+            software_cursor: config.software_cursor,
+            idle: crate::idle::IdleState::new(config.dpms_timeout),
+            idle_notifier_state,
+            idle_inhibit_manager_state,
             seat,
+            workspaces: crate::workspace::Workspaces::new(config.workspace_back_and_forth),
+            minimized: Vec::new(),
+            pending_pings: Vec::new(),
             wallpaper,
             active_surface: None,
             active_border_color: config.active_border_color,
             inactive_border_color: config.inactive_border_color,
-            border_width: 2,
+            unresponsive_border_color: config.unresponsive_border_color,
+            border_width: config.border_width,
+            border_outside: config.border_outside,
+            color_depth: config.color_depth,
+            window_rules: config.window_rules,
+            output_configs: config.outputs,
+            inactive_dim: config.inactive_dim,
+            corner_radius: config.corner_radius,
+            toplevel_drop_mode: config.toplevel_drag_drop,
+            maximize_mode: config.maximize,
+            terminal: config.terminal,
+            map_to_output: config.map_to_output,
+            device_outputs: Vec::new(),
+            startup_command: config.startup_command,
+            spawn_env: config.env,
+            exec: config.exec,
+            exec_always: config.exec_always,
+            clipboard: crate::clipboard::ClipboardCache::new(config.persist_clipboard),
             config_path,
-            ipc_socket_path,
+            config_watch: None,
+            ipc_socket_path: None,
+            ipc_subscribers: Vec::new(),
+            debug_overlay: crate::debug::DebugOverlay::default(),
+            screenshot_dir: config.screenshot_dir,
+            pending_screenshot: None,
             udev: None,
+            winit: None,
+            headless: None,
         };
 
         state.init_ipc_listener(event_loop);
+        state.arm_dpms_timer();
+        state.set_auto_reload(config.auto_reload);
 
         state
     }
 
     fn init_ipc_listener(&mut self, event_loop: &EventLoop<Self>) {
-        if let Some(parent) = self.ipc_socket_path.parent()
+        let default_path = default_ipc_socket_path(&self.socket_name);
+        let mut path =
+            std::env::var_os("RIPWM_IPC_SOCKET").map_or(default_path.clone(), PathBuf::from);
+        // An inherited path, from a ripwm nested inside another, is still in use by the
+        // outer instance.
+        if path != default_path && UnixStream::connect(&path).is_ok() {
+            tracing::warn!(
+                "IPC socket {} belongs to another running instance, using {}",
+                path.display(),
+                default_path.display()
+            );
+            path = default_path;
+        }
+
+        if let Some(parent) = path.parent()
             && let Err(err) = std::fs::create_dir_all(parent)
         {
             tracing::warn!("Failed to create IPC directory {}: {err}", parent.display());
             return;
         }
 
-        if self.ipc_socket_path.exists()
-            && let Err(err) = std::fs::remove_file(&self.ipc_socket_path)
-        {
-            tracing::warn!(
-                "Failed to remove old IPC socket {}: {err}",
-                self.ipc_socket_path.display()
-            );
-            return;
+        if path.exists() {
+            // Something still answering there is another instance, whose socket is left
+            // alone. One nobody answers on was left behind by a crash.
+            if UnixStream::connect(&path).is_ok() {
+                tracing::warn!(
+                    "IPC socket {} belongs to another running instance, not listening",
+                    path.display()
+                );
+                return;
+            }
+            if let Err(err) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to remove stale IPC socket {}: {err}", path.display());
+                return;
+            }
+            tracing::info!("Removed stale IPC socket {}", path.display());
         }
 
-        let listener = match UnixListener::bind(&self.ipc_socket_path) {
+        let listener = match UnixListener::bind(&path) {
             Ok(listener) => listener,
             Err(err) => {
-                tracing::warn!(
-                    "Failed to bind IPC socket {}: {err}",
-                    self.ipc_socket_path.display()
-                );
+                tracing::warn!("Failed to bind IPC socket {}: {err}", path.display());
                 return;
             }
         };
@@ -173,7 +324,7 @@ impl Smallvil {
                         }
                     };
 
-                    state.handle_ipc_client(stream);
+                    state.accept_ipc_client(stream);
                 }
 
                 Ok(PostAction::Continue)
@@ -182,7 +333,8 @@ impl Smallvil {
 
         match result {
             Ok(_) => {
-                tracing::info!("IPC socket listening at {}", self.ipc_socket_path.display());
+                tracing::info!("IPC socket listening at {}", path.display());
+                self.ipc_socket_path = Some(path);
             }
             Err(err) => {
                 tracing::warn!("Failed to initialize IPC event source: {err}");
@@ -190,58 +342,134 @@ impl Smallvil {
         }
     }
 
-    fn handle_ipc_client(&mut self, mut stream: std::os::unix::net::UnixStream) {
-        if let Err(err) = stream.set_nonblocking(false) {
-            tracing::warn!("Failed to configure IPC stream: {err}");
-            return;
+    /// Marks every output dirty, each is drawn again with its next frame.
+    pub fn request_redraw_all(&mut self) {
+        if let Some(winit) = self.winit.as_mut() {
+            winit.request_redraw();
         }
+        self.request_headless_redraw(None);
+        self.request_udev_redraw(None);
+    }
 
-        let mut command = String::new();
-        if let Err(err) = stream.read_to_string(&mut command) {
-            tracing::warn!("Failed to read IPC command: {err}");
-            return;
+    /// Marks `output` dirty, it is drawn again with its next frame.
+    pub fn request_redraw(&mut self, output: &Output) {
+        if let Some(winit) = self.winit.as_mut() {
+            winit.request_redraw();
         }
+        self.request_headless_redraw(Some(output));
+        self.request_udev_redraw(Some(output));
+    }
 
-        let command = command.trim();
+    /// Shows or hides the debug overlay on every output.
+    pub fn set_debug_overlay(&mut self, enabled: bool) {
+        self.debug_overlay.set_enabled(enabled);
+        self.request_redraw_all();
+    }
 
-        if command == "reload" {
-            self.reload_config();
-            return;
+    /// The border colour of `window`: unresponsive beats focused beats inactive.
+    pub fn border_color(&self, window: &Window) -> [f32; 4] {
+        if self.is_unresponsive(window) {
+            return self.unresponsive_border_color;
         }
 
-        if let Some(layout_args) = command.strip_prefix("keyboard ") {
-            let mut parts = layout_args.splitn(2, ' ');
-            let Some(layout) = parts.next().map(str::trim).filter(|part| !part.is_empty()) else {
-                tracing::warn!("Invalid keyboard IPC command, missing layout");
-                return;
-            };
-            let variant = parts.next().map(str::trim).unwrap_or("");
+        let focused = window.toplevel().is_some_and(|toplevel| {
+            self.active_surface.as_ref().is_some_and(|active| active == toplevel.wl_surface())
+        });
+        if focused { self.active_border_color } else { self.inactive_border_color }
+    }
 
-            let xkb_config =
-                smithay::input::keyboard::XkbConfig { layout, variant, ..Default::default() };
+    /// Notes which output each surface drawn in a frame of `output` is mainly shown on,
+    /// so surfaces only get frame callbacks at the pace of that output.
+    pub fn update_primary_scanout_output(&self, output: &Output, states: &RenderElementStates) {
+        let update = |surface: &WlSurface, surface_data: &SurfaceData| {
+            update_surface_primary_scanout_output(
+                surface,
+                output,
+                surface_data,
+                states,
+                default_primary_scanout_output_compare,
+            );
+        };
 
-            match self.seat.add_keyboard(xkb_config, 200, 25) {
-                Ok(_) => {
-                    tracing::info!(
-                        "Updated keyboard layout via IPC: layout={layout}, variant={variant}"
-                    );
-                }
-                Err(err) => {
-                    tracing::error!("Failed to update keyboard layout via IPC: {err}");
-                }
-            }
+        for window in self.space.elements() {
+            window.with_surfaces(update);
+        }
+        if let CursorImageStatus::Surface(surface) = &self.seat.cursor_status {
+            with_surfaces_surface_tree(surface, update);
+        }
+        if let Some(icon) = &self.seat.dnd_icon {
+            with_surfaces_surface_tree(&icon.surface, update);
+        }
+    }
 
-            return;
+    /// Sends frame callbacks for a frame of `output` that reached the screen at `time`.
+    /// Surfaces mainly shown elsewhere or hidden behind others are throttled to the
+    /// refresh rate of the output instead of being asked to draw again right away.
+    pub fn send_frame_callbacks(&mut self, output: &Output, time: Duration) {
+        let throttle = Some(frame_interval(output));
+
+        for window in self.space.elements() {
+            window.send_frame(output, time, throttle, surface_primary_scanout_output);
+        }
+        if let CursorImageStatus::Surface(surface) = &self.seat.cursor_status {
+            send_frames_surface_tree(
+                surface,
+                output,
+                time,
+                throttle,
+                surface_primary_scanout_output,
+            );
+        }
+        if let Some(icon) = &self.seat.dnd_icon {
+            send_frames_surface_tree(
+                &icon.surface,
+                output,
+                time,
+                throttle,
+                surface_primary_scanout_output,
+            );
         }
 
-        tracing::warn!("Unknown IPC command: {command}");
+        let _ = self.display_handle.flush_clients();
     }
 
-    pub fn reload_config(&mut self) {
-        let config = crate::config::load_or_create_config();
-        self.wallpaper = crate::config::WallpaperState::from_config(&config);
+    /// Applies the config file again. A file that can't be read or parsed leaves the
+    /// current settings alone.
+    pub fn reload_config(&mut self) -> Result<(), String> {
+        let (config, problems) =
+            crate::config::load_config(&self.config_path).map_err(|problem| {
+                format!("Invalid config at {}: {problem}", self.config_path.display())
+            })?;
+        crate::config::log_problems(&self.config_path, &problems);
+        self.wallpaper.reload(&config);
         self.active_border_color = config.active_border_color;
         self.inactive_border_color = config.inactive_border_color;
+        self.unresponsive_border_color = config.unresponsive_border_color;
+        self.window_rules = config.window_rules;
+        self.inactive_dim = config.inactive_dim;
+        self.corner_radius = config.corner_radius;
+        self.border_width = config.border_width;
+        self.border_outside = config.border_outside;
+        self.screenshot_dir = config.screenshot_dir;
+        self.cursor_hiding.timeout = config.cursor_timeout;
+        self.cursor_hiding.hide_on_typing = config.hide_cursor_on_typing;
+        self.software_cursor = config.software_cursor;
+        self.workspaces.back_and_forth = config.workspace_back_and_forth;
+        self.set_auto_reload(config.auto_reload);
+        self.update_winit_cursor();
+        self.idle.dpms_timeout = config.dpms_timeout;
+        self.arm_dpms_timer();
+        self.toplevel_drop_mode = config.toplevel_drag_drop;
+        self.maximize_mode = config.maximize;
+        self.terminal = config.terminal;
+        self.map_to_output = config.map_to_output;
+        self.startup_command = config.startup_command;
+        self.spawn_env = config.env;
+        self.exec = config.exec;
+        self.exec_always = config.exec_always;
+        self.set_persist_clipboard(config.persist_clipboard);
+        self.output_configs = config.outputs;
+        self.apply_output_configs();
 
         let xkb_config = smithay::input::keyboard::XkbConfig {
             layout: &config.keyboard_layout,
@@ -249,32 +477,133 @@ impl Smallvil {
             ..Default::default()
         };
 
-        if let Err(err) = self.seat.add_keyboard(xkb_config, 200, 25) {
-            tracing::error!("Failed to update keyboard layout: {err}");
+        // A typo in the layout keeps the keyboard working with the old keymap.
+        let keymap = self.seat.set_keymap(xkb_config);
+        if let Err(err) = &keymap {
+            tracing::error!("Failed to update keyboard layout, keeping the current one: {err}");
         }
 
         self.arrange_windows_tiled();
 
         self.request_redraw_all();
         tracing::info!("Reloaded configuration from {}", self.config_path.display());
+        self.run_exec_entries("exec_always", &self.exec_always);
+        keymap.map_err(|err| format!("failed to set keyboard layout: {err}"))
     }
 
     pub fn arrange_windows_tiled(&mut self) {
         self.space.refresh();
 
-        let Some(output) = self.space.outputs().next().cloned() else {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        let dragged = self.toplevel_drag_state.dragged_surface().cloned();
+
+        for output in outputs {
+            let Some(output_geo) = self.space.output_geometry(&output) else {
+                continue;
+            };
+            let Some(workspace) = self.workspaces.active_on(&output) else {
+                continue;
+            };
+
+            let mut windows: Vec<Window> = self
+                .space
+                .elements()
+                .filter(|window| crate::window::workspace_of(window) == workspace)
+                .filter(|window| {
+                    !crate::window::is_floating(window)
+                        && !crate::window::is_fullscreen(window)
+                        && !crate::window::is_maximized(window)
+                })
+                .filter(|window| {
+                    window
+                        .toplevel()
+                        .is_none_or(|toplevel| Some(toplevel.wl_surface()) != dragged.as_ref())
+                })
+                .cloned()
+                .collect();
+            windows.sort_by_key(|window| crate::window::window_data(window).tile_order);
+
+            self.tile_windows(windows, output_geo);
+
+            let maximized: Vec<Window> = self
+                .space
+                .elements()
+                .filter(|window| crate::window::workspace_of(window) == workspace)
+                .filter(|window| {
+                    crate::window::is_maximized(window) && !crate::window::is_fullscreen(window)
+                })
+                .cloned()
+                .collect();
+            for window in maximized {
+                self.place_maximized(window, output_geo);
+            }
+
+            let fullscreen: Vec<Window> = self
+                .space
+                .elements()
+                .filter(|window| crate::window::workspace_of(window) == workspace)
+                .filter(|window| crate::window::is_fullscreen(window))
+                .cloned()
+                .collect();
+            for window in fullscreen {
+                self.place_fullscreen(window, &output, output_geo);
+            }
+        }
+
+        self.restack_windows();
+        self.space.refresh();
+        self.foreign_toplevels_changed();
+        self.request_redraw_all();
+    }
+
+    /// Covers the usable area of the output with `window`.
+    fn place_maximized(&mut self, window: Window, area: Rectangle<i32, Logical>) {
+        let Some(toplevel) = window.toplevel() else {
             return;
         };
-        let Some(output_geo) = self.space.output_geometry(&output) else {
+
+        let is_active =
+            self.active_surface.as_ref().is_some_and(|focused| focused == toplevel.wl_surface());
+        window.set_activated(is_active);
+
+        toplevel.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Maximized);
+            state.size = Some(area.size);
+        });
+        if toplevel.is_initial_configure_sent() {
+            toplevel.send_pending_configure();
+        }
+
+        self.space.map_element(window, area.loc, false);
+    }
+
+    fn place_fullscreen(&mut self, window: Window, output: &Output, area: Rectangle<i32, Logical>) {
+        let Some(toplevel) = window.toplevel() else {
             return;
         };
 
-        let windows: Vec<Window> = self.space.elements().cloned().collect();
-        if windows.is_empty() {
-            return;
+        let is_active =
+            self.active_surface.as_ref().is_some_and(|focused| focused == toplevel.wl_surface());
+        window.set_activated(is_active);
+
+        let wl_output = toplevel
+            .wl_surface()
+            .client()
+            .and_then(|client| output.client_outputs(&client).into_iter().next());
+        toplevel.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+            state.size = Some(area.size);
+            state.fullscreen_output = wl_output;
+        });
+        if toplevel.is_initial_configure_sent() {
+            toplevel.send_pending_configure();
         }
 
-        let mut remaining = output_geo;
+        self.space.map_element(window, area.loc, false);
+    }
+
+    fn tile_windows(&mut self, windows: Vec<Window>, area: Rectangle<i32, Logical>) {
+        let mut remaining = area;
         let count = windows.len();
 
         for (index, window) in windows.into_iter().enumerate() {
@@ -301,6 +630,7 @@ impl Smallvil {
             } else {
                 remaining
             };
+            let tile = if self.border_outside { inset(tile, self.border_width) } else { tile };
 
             if let Some(toplevel) = window.toplevel() {
                 let is_active = self
@@ -310,12 +640,8 @@ impl Smallvil {
                 window.set_activated(is_active);
 
                 toplevel.with_pending_state(|state| {
-                    state.states.unset(
-                        smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::State::Maximized,
-                    );
-                    state.states.unset(
-                        smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::State::Fullscreen,
-                    );
+                    state.states.unset(xdg_toplevel::State::Maximized);
+                    state.states.unset(xdg_toplevel::State::Fullscreen);
                     state.size = Some(tile.size);
                 });
                 toplevel.send_pending_configure();
@@ -323,8 +649,295 @@ impl Smallvil {
 
             self.space.map_element(window, tile.loc, false);
         }
+    }
 
-        self.space.refresh();
+    /// Stacks maximized windows above tiled ones, floating windows above those, then
+    /// fullscreen windows, dialogs and finally the window of an ongoing toplevel drag.
+    fn restack_windows(&mut self) {
+        let maximized: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|window| crate::window::is_maximized(window))
+            .cloned()
+            .collect();
+
+        for window in maximized {
+            self.space.raise_element(&window, false);
+        }
+
+        let floating: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|window| crate::window::is_floating(window))
+            .cloned()
+            .collect();
+
+        for window in floating {
+            self.space.raise_element(&window, false);
+        }
+
+        let fullscreen: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|window| crate::window::is_fullscreen(window))
+            .cloned()
+            .collect();
+
+        for window in fullscreen {
+            self.space.raise_element(&window, false);
+        }
+
+        // Dialogs follow their parent wherever it was placed and stay above it, even
+        // above a fullscreen parent.
+        let dialogs: Vec<Window> = self
+            .space
+            .elements()
+            .filter(|window| crate::window::is_dialog(window))
+            .cloned()
+            .collect();
+
+        for window in dialogs {
+            self.centre_over_parent(&window);
+            self.space.raise_element(&window, false);
+        }
+
+        if let Some(dragged) = self
+            .toplevel_drag_state
+            .dragged_surface()
+            .and_then(|surface| self.window_for_surface(surface))
+        {
+            self.space.raise_element(&dragged, false);
+        }
+    }
+
+    /// Finds the window for a toplevel surface, including windows on hidden workspaces
+    /// and minimized windows.
+    pub fn window_for_surface(&self, surface: &WlSurface) -> Option<Window> {
+        self.space
+            .elements()
+            .chain(self.workspaces.hidden_windows())
+            .chain(self.minimized.iter())
+            .find(|window| {
+                window.toplevel().is_some_and(|toplevel| toplevel.wl_surface() == surface)
+            })
+            .cloned()
+    }
+
+    pub fn set_keyboard_focus(&mut self, surface: Option<WlSurface>) {
+        let Some(keyboard) = self.seat.keyboard() else {
+            return;
+        };
+
+        self.active_surface.clone_from(&surface);
+        keyboard.set_focus(self, surface, SERIAL_COUNTER.next_serial());
+    }
+
+    /// Floats a toplevel that has a parent (xdg_toplevel.set_parent or an imported
+    /// xdg-foreign handle) at the size it asks for, centred over the parent window.
+    pub fn float_over_parent(&mut self, window: &Window) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+
+        crate::window::window_data(window).floating = true;
+
+        toplevel.with_pending_state(|state| {
+            state.size = None;
+        });
+        if toplevel.is_initial_configure_sent() {
+            toplevel.send_pending_configure();
+        }
+
+        self.centre_over_parent(window);
+    }
+
+    /// Centres a dialog over the current geometry of its parent, if the parent is shown.
+    /// A dialog being resized or dragged stays where the pointer puts it.
+    pub fn centre_over_parent(&mut self, window: &Window) {
+        if crate::window::window_data(window).resize.is_some()
+            || window.toplevel().is_some_and(|toplevel| {
+                self.toplevel_drag_state.dragged_surface() == Some(toplevel.wl_surface())
+            })
+        {
+            return;
+        }
+        let Some(parent) =
+            crate::window::parent_of(window).and_then(|parent| self.window_for_surface(&parent))
+        else {
+            return;
+        };
+        let Some(parent_geo) = self.space.element_geometry(&parent) else {
+            return;
+        };
+
+        // Before its first buffer the dialog has no size to centre yet.
+        let size = window.geometry().size;
+        if size.is_empty() {
+            return;
+        }
+        crate::window::window_data(window).centred = true;
+
+        let location = (
+            parent_geo.loc.x + (parent_geo.size.w - size.w) / 2,
+            parent_geo.loc.y + (parent_geo.size.h - size.h) / 2,
+        );
+        if self.space.element_location(window) == Some(location.into()) {
+            return;
+        }
+        self.space.map_element(window.clone(), location, false);
+    }
+
+    /// Puts `window` into fullscreen on `output`, or takes it out of fullscreen when
+    /// `output` is `None`. Leaving fullscreen restores the previous tile or floating geometry.
+    pub fn set_fullscreen(&mut self, window: &Window, output: Option<Output>) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+
+        if let Some(output) = output {
+            self.save_restore_geometry(window);
+
+            // A window can only be shown on the output of its workspace, so fullscreening
+            // it on another output moves it to the workspace visible there.
+            if let Some(workspace) = self.workspaces.active_on(&output)
+                && workspace != crate::window::workspace_of(window)
+            {
+                self.move_window_to_workspace(window, workspace);
+            }
+
+            crate::window::window_data(window).fullscreen = true;
+        } else if crate::window::is_fullscreen(window) {
+            crate::window::window_data(window).fullscreen = false;
+            toplevel.with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Fullscreen);
+                state.fullscreen_output = None;
+            });
+            self.restore_geometry(window);
+        }
+
+        self.arrange_windows_tiled();
+        Self::acknowledge_state_request(toplevel);
+        self.request_redraw_all();
+    }
+
+    /// Maximizes or unmaximizes `window`. With `maximize = "ignore"` the request is only
+    /// acknowledged and the window keeps its tile.
+    pub fn set_maximized(&mut self, window: &Window, maximized: bool) {
+        let Some(toplevel) = window.toplevel() else {
+            return;
+        };
+
+        if maximized {
+            if self.maximize_mode == crate::config::MaximizeMode::Honour {
+                self.save_restore_geometry(window);
+                crate::window::window_data(window).maximized = true;
+            }
+        } else if crate::window::is_maximized(window) {
+            crate::window::window_data(window).maximized = false;
+            toplevel.with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Maximized);
+            });
+            self.restore_geometry(window);
+        }
+
+        self.arrange_windows_tiled();
+        Self::acknowledge_state_request(toplevel);
+        self.request_redraw_all();
+    }
+
+    /// Takes `window` out of the layout until it is unminimized. It stays alive but gets no
+    /// frame callbacks while unmapped.
+    pub fn minimize_window(&mut self, window: &Window) {
+        if !self.space.elements().any(|mapped| mapped == window) {
+            return;
+        }
+
+        let was_focused = window
+            .toplevel()
+            .is_some_and(|toplevel| self.active_surface.as_ref() == Some(toplevel.wl_surface()));
+
+        self.unmap_window(window);
+        window.set_activated(false);
+        self.minimized.push(window.clone());
+        self.arrange_windows_tiled();
+
+        if was_focused {
+            match self.current_workspace() {
+                Some(current) => self.focus_top_window(current),
+                None => self.set_keyboard_focus(None),
+            }
+        }
+
+        self.request_redraw_all();
+    }
+
+    /// Brings a minimized window back onto the current workspace and focuses it.
+    pub fn unminimize_window(&mut self, window: &Window) {
+        let Some(index) = self.minimized.iter().position(|minimized| minimized == window) else {
+            return;
+        };
+        let window = self.minimized.remove(index);
+
+        if let Some(workspace) = self.current_workspace() {
+            crate::window::window_data(&window).workspace = workspace;
+        }
+        self.remap_window(window.clone());
+        self.arrange_windows_tiled();
+        self.set_keyboard_focus(window.toplevel().map(|toplevel| toplevel.wl_surface().clone()));
+        self.request_redraw_all();
+    }
+
+    /// Brings `window` into view, switching to its workspace or unminimizing it, then
+    /// raises and focuses it.
+    pub fn focus_window(&mut self, window: &Window) {
+        if self.minimized.contains(window) {
+            self.unminimize_window(window);
+        } else {
+            let workspace = crate::window::workspace_of(window);
+            if !self.workspaces.get(workspace).is_some_and(|workspace| workspace.active) {
+                self.switch_workspace(workspace);
+            }
+        }
+
+        self.space.raise_element(window, false);
+        self.set_keyboard_focus(window.toplevel().map(|toplevel| toplevel.wl_surface().clone()));
+        self.arrange_windows_tiled();
+    }
+
+    /// Remembers where `window` is before it is maximized or goes fullscreen.
+    fn save_restore_geometry(&mut self, window: &Window) {
+        if crate::window::is_fullscreen(window) || crate::window::is_maximized(window) {
+            return;
+        }
+
+        let geometry = self.space.element_geometry(window);
+        crate::window::window_data(window).restore_geometry = geometry;
+    }
+
+    /// Puts a floating window back where it was once it is neither maximized nor fullscreen.
+    /// Tiled windows go back to their tile through the tiling order.
+    fn restore_geometry(&mut self, window: &Window) {
+        if crate::window::is_fullscreen(window) || crate::window::is_maximized(window) {
+            return;
+        }
+
+        let restore = crate::window::window_data(window).restore_geometry.take();
+        if crate::window::is_floating(window)
+            && let Some(geometry) = restore
+            && let Some(toplevel) = window.toplevel()
+        {
+            toplevel.with_pending_state(|state| {
+                state.size = Some(geometry.size);
+            });
+            self.space.map_element(window.clone(), geometry.loc, false);
+        }
+    }
+
+    /// xdg-shell wants a configure in response to state requests even if nothing changed.
+    fn acknowledge_state_request(toplevel: &ToplevelSurface) {
+        if toplevel.is_initial_configure_sent() && toplevel.send_pending_configure().is_none() {
+            toplevel.send_configure();
+        }
     }
 
     fn init_wayland_listener(display: Display<Self>, event_loop: &EventLoop<Self>) -> OsString {
@@ -366,36 +979,58 @@ impl Smallvil {
         &self,
         pos: Point<f64, Logical>,
     ) -> Option<(WlSurface, Point<f64, Logical>)> {
-        self.space.element_under(pos).and_then(|(window, location)| {
-            window
-                .surface_under(pos - location.to_f64(), WindowSurfaceType::ALL)
-                .map(|(s, p)| (s, (p + location).to_f64()))
-        })
+        // A toplevel attached to a drag must not be picked as the drop target.
+        let dragged = self.toplevel_drag_state.dragged_surface();
+
+        self.space
+            .elements()
+            .rev()
+            .filter(|window| {
+                window.toplevel().is_none_or(|toplevel| Some(toplevel.wl_surface()) != dragged)
+            })
+            .find_map(|window| {
+                let location = self.space.element_location(window)? - window.geometry().loc;
+                window
+                    .surface_under(pos - location.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(s, p)| (s, (p + location).to_f64()))
+            })
     }
 }
 
+/// `rect` shrunk by `width` on every side, leaving at least a pixel either way.
+fn inset(rect: Rectangle<i32, Logical>, width: i32) -> Rectangle<i32, Logical> {
+    let dx = width.clamp(0, (rect.size.w - 1).max(0) / 2);
+    let dy = width.clamp(0, (rect.size.h - 1).max(0) / 2);
+    Rectangle::new(
+        (rect.loc.x + dx, rect.loc.y + dy).into(),
+        (rect.size.w - dx * 2, rect.size.h - dy * 2).into(),
+    )
+}
+
 impl Drop for Smallvil {
     fn drop(&mut self) {
-        if self.ipc_socket_path.exists() {
-            let _ = std::fs::remove_file(&self.ipc_socket_path);
+        if let Some(path) = self.ipc_socket_path.as_ref() {
+            let _ = std::fs::remove_file(path);
         }
     }
 }
 
-fn ipc_socket_path() -> PathBuf {
-    if let Some(path) = std::env::var_os("RIPWM_IPC_SOCKET") {
-        return PathBuf::from(path);
-    }
+/// The IPC socket of an instance, named after its Wayland socket so instances running
+/// side by side each get one of their own. `$RIPWM_IPC_SOCKET` overrides it.
+fn default_ipc_socket_path(socket_name: &OsStr) -> PathBuf {
+    let mut file_name = OsString::from("ripwm-");
+    file_name.push(socket_name);
+    file_name.push(".sock");
 
     if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR") {
-        return PathBuf::from(runtime_dir).join("ripwm.sock");
+        return PathBuf::from(runtime_dir).join(file_name);
     }
 
     if let Some(home) = std::env::var_os("HOME") {
-        return PathBuf::from(home).join(".config/ripwm/ripwm.sock");
+        return PathBuf::from(home).join(".config/ripwm").join(file_name);
     }
 
-    PathBuf::from("/tmp/ripwm.sock")
+    PathBuf::from("/tmp").join(file_name)
 }
 
 #[derive(Default)]
@@ -1,4 +1,9 @@
-use std::{collections::HashMap, path::Path, time::Duration};
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
 
 use smithay::{
     backend::{
@@ -8,45 +13,53 @@ use smithay::{
             gbm::{GbmAllocator, GbmBufferFlags, GbmDevice},
         },
         drm::{
-            DrmDevice, DrmDeviceFd, DrmEvent, DrmEventMetadata, DrmNode, NodeType,
+            DrmDevice, DrmDeviceFd, DrmEvent, DrmEventMetadata, DrmEventTime, DrmNode, NodeType,
+            compositor::FrameFlags,
             output::{DrmOutput, DrmOutputManager, DrmOutputRenderElements},
         },
         egl::{EGLContext, EGLDevice, EGLDisplay, context::ContextPriority},
+        input::InputEvent,
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
             ImportAll, ImportMem,
-            element::surface::WaylandSurfaceRenderElement,
             element::{
-                AsRenderElements,
-                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                AsRenderElements, Element, memory::MemoryRenderBufferRenderElement,
                 solid::SolidColorRenderElement,
             },
-            gles::GlesRenderer,
-            multigpu::{GpuManager, MultiRenderer, gbm::GbmGlesBackend},
+            gles::{GlesError, GlesFrame, GlesRenderer},
+            multigpu::{Error as MultiError, GpuManager, MultiRenderer, gbm::GbmGlesBackend},
         },
         session::{Event as SessionEvent, Session, libseat::LibSeatSession},
         udev::{UdevBackend, UdevEvent, all_gpus, primary_gpu},
     },
-    output::{Mode as WlMode, Output, PhysicalProperties},
+    output::{Mode as WlMode, Output, PhysicalProperties, Scale as OutputScale},
     reexports::{
-        calloop::{EventLoop, LoopHandle, RegistrationToken},
-        drm::control::{ModeTypeFlags, connector, crtc},
+        calloop::{
+            EventLoop, LoopHandle, RegistrationToken,
+            timer::{TimeoutAction, Timer},
+        },
+        drm::control::{Device as ControlDevice, Mode as DrmMode, ModeTypeFlags, connector, crtc},
         input::Libinput,
         rustix::fs::OFlags,
     },
-    utils::{DeviceFd, IsAlive, Scale, Transform},
-    wayland::compositor,
+    utils::{Clock, DeviceFd, Monotonic, Point, Scale, Transform},
 };
 use smithay_drm_extras::drm_scanner::{DrmScanEvent, DrmScanner};
 
-use crate::{Smallvil, drawing::PointerElement};
+use crate::{
+    Smallvil,
+    config::ColorDepth,
+    rounded::{AsGlesRenderer, RoundedSurfaceElement, Rounding},
+};
 
 smithay::backend::renderer::element::render_elements! {
-    pub UdevOutputRenderElements<R, E> where R: ImportAll + ImportMem;
-    Wallpaper=MemoryRenderBufferRenderElement<R>,
+    pub UdevOutputRenderElements<R, E> where R: ImportAll + ImportMem + AsGlesRenderer;
+    Wallpaper=crate::wallpaper::WallpaperRenderElement<R>,
     Space=smithay::desktop::space::SpaceRenderElements<R, E>,
     Border=SolidColorRenderElement,
+    RoundedBorder=crate::rounded::RoundedBorderElement,
     Pointer=crate::drawing::PointerRenderElement<R>,
+    Debug=MemoryRenderBufferRenderElement<R>,
 }
 
 type UdevRenderer<'a> = MultiRenderer<
@@ -56,6 +69,26 @@ type UdevRenderer<'a> = MultiRenderer<
     GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
 >;
 
+impl AsGlesRenderer for UdevRenderer<'_> {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer {
+        self.as_mut()
+    }
+
+    fn gles_frame<'a, 'frame, 'buffer>(
+        frame: &'a mut Self::Frame<'frame, 'buffer>,
+    ) -> &'a mut GlesFrame<'frame, 'buffer>
+    where
+        'buffer: 'frame,
+        Self: 'frame,
+    {
+        frame.as_mut()
+    }
+
+    fn from_gles_error(err: GlesError) -> Self::Error {
+        MultiError::Render(err)
+    }
+}
+
 type DrmOutputType = DrmOutput<
     GbmAllocator<DrmDeviceFd>,
     smithay::backend::drm::exporter::gbm::GbmFramebufferExporter<DrmDeviceFd>,
@@ -72,6 +105,14 @@ pub struct UdevOutputId {
 pub struct SurfaceData {
     pub output: Output,
     pub drm_output: DrmOutputType,
+    pub connector: connector::Info,
+    /// `make model serial` from the EDID, if the monitor has a readable one.
+    pub identifier: Option<String>,
+    pub serial: Option<String>,
+    /// Something on the output changed since its last frame.
+    pub dirty: bool,
+    /// A frame is queued and its vblank has not arrived yet.
+    pub frame_pending: bool,
 }
 
 pub struct BackendData {
@@ -83,6 +124,8 @@ pub struct BackendData {
     >,
     pub drm_scanner: DrmScanner,
     pub surfaces: HashMap<crtc::Handle, SurfaceData>,
+    /// Connected outputs left off by the config, kept to turn them on again on reload.
+    pub disabled_connectors: HashMap<crtc::Handle, connector::Info>,
     pub registration_token: RegistrationToken,
     pub render_node: Option<DrmNode>,
 }
@@ -93,25 +136,132 @@ pub struct UdevData {
     pub primary_gpu: DrmNode,
     pub gpus: GpuManager<GbmGlesBackend<GlesRenderer, DrmDeviceFd>>,
     pub backends: HashMap<DrmNode, BackendData>,
-    pub pointer_image: crate::cursor::Cursor,
-    pub pointer_images: Vec<(xcursor::parser::Image, MemoryRenderBuffer)>,
-    pub pointer_element: PointerElement,
+    pub cursor: crate::drawing::SoftwareCursor,
+    /// An idle callback will render the dirty outputs.
+    pub render_scheduled: bool,
+    /// When the session was paused, on both clocks. The wall clock keeps going while the
+    /// system sleeps and the monotonic one doesn't, which tells a suspend from a VT switch.
+    pub paused_at: Option<(Instant, SystemTime)>,
 }
 
 fn u32_to_i32_saturating(value: u32) -> i32 {
     i32::try_from(value).unwrap_or(i32::MAX)
 }
 
-pub fn run_udev() -> Result<(), Box<dyn std::error::Error>> {
+/// Framebuffer formats for outputs, in order of preference. The compositor falls back to
+/// the next one when it can't allocate or modeset with a format, so 10-bit formats always
+/// end with the 8-bit ones.
+fn color_formats(depth: ColorDepth, render_formats: &FormatSet) -> Vec<Fourcc> {
+    const EIGHT_BIT: [Fourcc; 2] = [Fourcc::Abgr8888, Fourcc::Argb8888];
+    const TEN_BIT: [Fourcc; 2] = [Fourcc::Abgr2101010, Fourcc::Argb2101010];
+
+    if depth == ColorDepth::Eight {
+        return EIGHT_BIT.to_vec();
+    }
+
+    let ten_bit: Vec<Fourcc> = TEN_BIT
+        .into_iter()
+        .filter(|code| render_formats.iter().any(|format| format.code == *code))
+        .collect();
+    if ten_bit.is_empty() {
+        tracing::warn!("The GPU can't render to 10-bit formats, using 8 bits per channel");
+    }
+    ten_bit.into_iter().chain(EIGHT_BIT).collect()
+}
+
+/// When a vblank happened, on the clock of frame callbacks. Falls back to now without a
+/// monotonic timestamp from the kernel.
+fn vblank_time(start_time: Instant, metadata: Option<&DrmEventMetadata>) -> Duration {
+    let now = start_time.elapsed();
+    let Some(DrmEventTime::Monotonic(at)) = metadata.map(|metadata| metadata.time) else {
+        return now;
+    };
+    let monotonic_now: Duration = Clock::<Monotonic>::new().now().into();
+    now.saturating_sub(monotonic_now.saturating_sub(at))
+}
+
+/// The output name of a connector, e.g. `DP-1`.
+fn connector_name(connector: &connector::Info) -> String {
+    format!("{}-{}", connector.interface().as_str(), connector.interface_id())
+}
+
+/// The mode the connector prefers, or its first one.
+fn preferred_mode(connector: &connector::Info) -> Option<DrmMode> {
+    connector
+        .modes()
+        .iter()
+        .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+        .or_else(|| connector.modes().first())
+        .copied()
+}
+
+/// The connector mode closest to what an `[[output]]` section asks for: the requested
+/// size at the nearest refresh rate, or the highest one if none was given.
+fn configured_mode(
+    connector: &connector::Info,
+    name: &str,
+    wanted: crate::config::OutputMode,
+) -> Option<DrmMode> {
+    let refresh_hz = |mode: &DrmMode| f64::from(WlMode::from(*mode).refresh) / 1000.0;
+    let sizes =
+        connector.modes().iter().filter(|mode| mode.size() == (wanted.width, wanted.height));
+    let mode = match wanted.refresh {
+        Some(refresh) => sizes.min_by(|a, b| {
+            (refresh_hz(a) - refresh).abs().total_cmp(&(refresh_hz(b) - refresh).abs())
+        }),
+        None => sizes.max_by(|a, b| refresh_hz(a).total_cmp(&refresh_hz(b))),
+    };
+
+    if mode.is_none() {
+        tracing::warn!(
+            "Output {name} has no {}x{} mode. Using the preferred mode",
+            wanted.width,
+            wanted.height
+        );
+    }
+    mode.copied()
+}
+
+/// The value of the property `name` of `connector`, `None` if it has no such property.
+pub(crate) fn connector_property(
+    drm: &impl ControlDevice,
+    connector: connector::Handle,
+    name: &str,
+) -> Option<u64> {
+    let properties = drm.get_properties(connector).ok()?;
+    properties.iter().find_map(|(property, value)| {
+        let info = drm.get_property(*property).ok()?;
+        (info.name().to_bytes() == name.as_bytes()).then_some(*value)
+    })
+}
+
+/// Whether the kernel marks `connector` as not part of the desktop, like VR headsets
+/// that are meant to be driven by a single application.
+fn is_non_desktop(drm: &impl ControlDevice, connector: connector::Handle) -> bool {
+    connector_property(drm, connector, "non-desktop").is_some_and(|value| value != 0)
+}
+
+/// Whether a connector gets an output: unless its section turns it off, or it is
+/// non-desktop and its section doesn't force it on.
+fn output_enabled(config: Option<&crate::config::OutputConfig>, non_desktop: bool) -> bool {
+    match config {
+        Some(config) => config.enabled && (!non_desktop || config.force_enable),
+        None => !non_desktop,
+    }
+}
+
+pub fn run_udev(options: &crate::cli::Options) -> Result<(), Box<dyn std::error::Error>> {
     let mut event_loop: EventLoop<Smallvil> = EventLoop::try_new()?;
     let display = smithay::reexports::wayland_server::Display::new()?;
 
-    let mut state = Smallvil::new(&mut event_loop, display);
-
     let (session, notifier) = LibSeatSession::new()?;
 
-    let primary_gpu = if let Ok(var) = std::env::var("SMALLVIL_DRM_DEVICE") {
-        DrmNode::from_path(var)?
+    // Named after the libseat seat, which is also the one libinput devices come from.
+    let mut state = Smallvil::new(&mut event_loop, display, &session.seat(), options.config_path());
+
+    let primary_gpu = if let Some(path) = options.drm_device() {
+        DrmNode::from_path(&path)
+            .map_err(|err| format!("Invalid DRM device {}: {err}", path.display()))?
     } else {
         primary_gpu(session.seat())?
             .and_then(|x| DrmNode::from_path(x).ok()?.node_with_type(NodeType::Render)?.ok())
@@ -136,9 +286,9 @@ pub fn run_udev() -> Result<(), Box<dyn std::error::Error>> {
         primary_gpu,
         gpus,
         backends: HashMap::new(),
-        pointer_image: crate::cursor::Cursor::load(),
-        pointer_images: Vec::new(),
-        pointer_element: PointerElement::default(),
+        cursor: crate::drawing::SoftwareCursor::default(),
+        render_scheduled: false,
+        paused_at: None,
     });
 
     let mut libinput_context = Libinput::new_with_udev::<LibinputSessionInterface<LibSeatSession>>(
@@ -148,17 +298,32 @@ pub fn run_udev() -> Result<(), Box<dyn std::error::Error>> {
         .udev_assign_seat(&state.udev.as_ref().unwrap().session.seat())
         .map_err(|()| "Failed to assign libinput seat")?;
     let libinput_backend = LibinputInputBackend::new(libinput_context.clone());
+    crate::crash::set_session(
+        state.udev.as_ref().unwrap().session.clone(),
+        libinput_context.clone(),
+    );
 
     event_loop.handle().insert_source(libinput_backend, move |event, (), data| {
+        match &event {
+            InputEvent::DeviceAdded { device } => {
+                data.set_device_output(device.sysname(), device.output_name());
+            }
+            InputEvent::DeviceRemoved { device } => data.set_device_output(device.sysname(), None),
+            _ => {}
+        }
         data.process_input_event(event);
     })?;
 
-    event_loop.handle().insert_source(notifier, move |event, (), _data| match event {
+    event_loop.handle().insert_source(notifier, move |event, (), data| match event {
         SessionEvent::PauseSession => {
             libinput_context.suspend();
+            data.pause_drm();
         }
         SessionEvent::ActivateSession => {
-            let _ = libinput_context.resume();
+            if libinput_context.resume().is_err() {
+                tracing::error!("Failed to resume libinput context");
+            }
+            data.activate_drm();
         }
     })?;
 
@@ -172,24 +337,159 @@ pub fn run_udev() -> Result<(), Box<dyn std::error::Error>> {
         .handle()
         .insert_source(udev_backend, move |event, (), data| data.on_udev_event(event))?;
 
-    crate::set_wayland_display(&state.socket_name);
-    crate::spawn_client();
+    state.run_autostart();
+    if !options.no_spawn {
+        state.spawn_startup_client(options.command.as_deref());
+    }
 
-    event_loop.run(None, &mut state, |_| {})?;
+    // A panic gave the devices back in the hook already, unwinding would drop outputs
+    // on a session that is gone. Debug builds abort then too, like release ones.
+    let run =
+        std::panic::catch_unwind(AssertUnwindSafe(|| event_loop.run(None, &mut state, |_| {})));
+    match run {
+        Ok(result) => result?,
+        Err(_) => std::process::abort(),
+    }
 
     Ok(())
 }
 
 impl Smallvil {
-    pub(crate) fn request_redraw_all(&mut self) {
+    /// Marks `output`, or every output without one, dirty on the udev backend.
+    pub(crate) fn request_udev_redraw(&mut self, output: Option<&Output>) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+
+        if let Some(output) = output {
+            let Some(id) = output.user_data().get::<UdevOutputId>() else {
+                return;
+            };
+            let Some(surface) = udev
+                .backends
+                .get_mut(&id.device_id)
+                .and_then(|backend| backend.surfaces.get_mut(&id.crtc))
+            else {
+                return;
+            };
+            surface.dirty = true;
+        } else {
+            for surface in
+                udev.backends.values_mut().flat_map(|backend| backend.surfaces.values_mut())
+            {
+                surface.dirty = true;
+            }
+        }
+        self.schedule_render();
+    }
+
+    /// The framebuffer format and monitor serial of a udev `output`.
+    pub(crate) fn udev_output_details(&self, output: &Output) -> Option<(String, Option<String>)> {
+        let id = output.user_data().get::<UdevOutputId>()?;
+        let surface = self.udev.as_ref()?.backends.get(&id.device_id)?.surfaces.get(&id.crtc)?;
+        Some((surface.drm_output.format().to_string(), surface.serial.clone()))
+    }
+
+    /// The EDID identifier of the udev output named `name`, which `[[output]]` sections
+    /// can match instead of the connector name.
+    pub(crate) fn udev_output_identifier(&self, name: &str) -> Option<String> {
+        self.udev
+            .as_ref()?
+            .backends
+            .values()
+            .flat_map(|backend| backend.surfaces.values())
+            .find(|surface| surface.output.name() == name)
+            .and_then(|surface| surface.identifier.clone())
+    }
+
+    /// Whether the connector of the udev output `name`, on or turned off, has a mode of the
+    /// size `mode` asks for.
+    pub(crate) fn udev_supports_mode(&self, name: &str, mode: crate::config::OutputMode) -> bool {
+        let Some(udev) = self.udev.as_ref() else {
+            return false;
+        };
+        udev.backends
+            .values()
+            .flat_map(|backend| {
+                backend
+                    .surfaces
+                    .values()
+                    .map(|surface| &surface.connector)
+                    .chain(backend.disabled_connectors.values())
+            })
+            .find(|connector| connector_name(connector) == name)
+            .is_some_and(|connector| {
+                connector
+                    .modes()
+                    .iter()
+                    .any(|drm_mode| drm_mode.size() == (mode.width, mode.height))
+            })
+    }
+
+    /// The DRM devices in use and the outputs on each, for `ripctl debug`.
+    pub(crate) fn udev_gpus(&self) -> Vec<crate::ipc::GpuInfo> {
         let Some(udev) = self.udev.as_ref() else {
+            return Vec::new();
+        };
+        let path = |node: &DrmNode| {
+            node.dev_path().map_or_else(|| format!("{node:?}"), |path| path.display().to_string())
+        };
+        udev.backends
+            .iter()
+            .map(|(node, backend)| crate::ipc::GpuInfo {
+                node: path(node),
+                render_node: backend.render_node.as_ref().map(path),
+                primary: backend.render_node.unwrap_or(*node) == udev.primary_gpu,
+                outputs: backend.surfaces.values().map(|surface| surface.output.name()).collect(),
+            })
+            .collect()
+    }
+
+    /// Names of the connected outputs the config turns off.
+    pub(crate) fn udev_disabled_outputs(&self) -> Vec<String> {
+        let Some(udev) = self.udev.as_ref() else {
+            return Vec::new();
+        };
+        udev.backends
+            .values()
+            .flat_map(|backend| backend.disabled_connectors.values())
+            .map(connector_name)
+            .collect()
+    }
+
+    /// Renders the dirty outputs once the event loop is done with the current batch of
+    /// events, so a burst of commits and input only costs one frame.
+    fn schedule_render(&mut self) {
+        let Some(udev) = self.udev.as_mut() else {
             return;
         };
+        if udev.render_scheduled {
+            return;
+        }
+
+        udev.render_scheduled = true;
+        udev.handle.insert_idle(Smallvil::render_dirty_surfaces);
+    }
+
+    /// Starts a frame on every dirty output that is not already waiting for a vblank.
+    /// Those that are get drawn from [`Self::frame_finish`] instead.
+    fn render_dirty_surfaces(&mut self) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+        udev.render_scheduled = false;
+        // Outputs stay dirty while on another VT or turned off, and get drawn once the
+        // session or the displays are back.
+        if !udev.session.is_active() || self.idle.displays_off {
+            return;
+        }
 
         let mut targets = Vec::new();
         for (node, backend) in &udev.backends {
-            for crtc in backend.surfaces.keys() {
-                targets.push((*node, *crtc));
+            for (crtc, surface) in &backend.surfaces {
+                if surface.dirty && !surface.frame_pending {
+                    targets.push((*node, *crtc));
+                }
             }
         }
 
@@ -198,6 +498,223 @@ impl Smallvil {
         }
     }
 
+    /// Gives up the DRM devices when switching away from our VT or going to sleep. Their
+    /// events are ignored until the session comes back, nothing renders in between.
+    fn pause_drm(&mut self) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+        udev.paused_at = Some((Instant::now(), SystemTime::now()));
+
+        for backend in udev.backends.values_mut() {
+            backend.drm_output_manager.pause();
+            if let Err(err) = udev.handle.disable(&backend.registration_token) {
+                tracing::warn!("Failed to pause DRM events: {err}");
+            }
+
+            for surface in backend.surfaces.values_mut() {
+                // The vblank of a frame queued before the pause never arrives.
+                surface.frame_pending = false;
+            }
+        }
+    }
+
+    /// Takes the DRM devices back after switching to our VT. Whoever had them in between
+    /// may have changed modes or planes, so every output gets a full modeset with its
+    /// current mode on the next frame, which is rendered from scratch.
+    ///
+    /// After a suspend the devices are reset completely and their connectors scanned
+    /// again, since monitors may have gone away or come back under another connector.
+    fn activate_drm(&mut self) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+
+        let resumed = udev.paused_at.take().is_some_and(|(monotonic, wall)| {
+            let slept = wall.elapsed().unwrap_or_default().saturating_sub(monotonic.elapsed());
+            slept > Duration::from_secs(1)
+        });
+        if resumed {
+            tracing::info!("Resuming from suspend, resetting outputs");
+        }
+
+        for backend in udev.backends.values_mut() {
+            if let Err(err) = backend.drm_output_manager.activate(resumed) {
+                tracing::error!("Failed to activate DRM device: {err}");
+            }
+            if let Err(err) = udev.handle.enable(&backend.registration_token) {
+                tracing::warn!("Failed to resume DRM events: {err}");
+            }
+
+            for surface in backend.surfaces.values_mut() {
+                surface.frame_pending = false;
+                surface.drm_output.reset_buffers();
+            }
+        }
+
+        if resumed {
+            let nodes: Vec<DrmNode> = udev.backends.keys().copied().collect();
+            for node in nodes {
+                self.device_changed(node);
+            }
+            // Outputs that survived get their configured modes back.
+            self.apply_output_configs();
+        }
+
+        // Coming back counts as activity, displays turned off in the meantime wake up.
+        self.idle_activity();
+        self.request_redraw_all();
+    }
+
+    /// Turns every output off by clearing the active state of its crtc, or back on. The
+    /// mode stays set, so waking up is a plain commit instead of a full modeset.
+    pub(crate) fn set_displays_powered(&mut self, powered: bool) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+        self.idle.displays_off = !powered;
+
+        let surfaces = udev.backends.values_mut().flat_map(|backend| backend.surfaces.values_mut());
+        if powered {
+            tracing::info!("Turning displays on");
+            // Nothing may have changed while they were off, the first frame has to be drawn
+            // in full for it to be queued at all.
+            for surface in surfaces {
+                surface.drm_output.reset_buffers();
+            }
+            self.request_redraw_all();
+            return;
+        }
+
+        tracing::info!("Turning displays off after being idle");
+        for surface in surfaces {
+            if let Err(err) = surface.drm_output.with_compositor(|compositor| compositor.clear()) {
+                tracing::warn!("Failed to turn off output {}: {err}", surface.output.name());
+            }
+            surface.frame_pending = false;
+        }
+    }
+
+    /// Applies the `[[output]]` sections again after a config reload. Outputs are turned
+    /// on and off, the others get their mode, position, scale and transform updated.
+    pub(crate) fn apply_output_configs(&mut self) {
+        let Some(udev) = self.udev.as_ref() else {
+            return;
+        };
+
+        let enabled =
+            |backend: &BackendData, connector: &connector::Info, identifier: Option<&str>| {
+                let config = crate::config::output_config(
+                    &self.output_configs,
+                    &connector_name(connector),
+                    identifier,
+                );
+                let non_desktop =
+                    is_non_desktop(backend.drm_output_manager.device(), connector.handle());
+                output_enabled(config, non_desktop)
+            };
+        let mut disable = Vec::new();
+        let mut enable = Vec::new();
+        let mut update = Vec::new();
+        for (node, backend) in &udev.backends {
+            for (crtc, surface) in &backend.surfaces {
+                if enabled(backend, &surface.connector, surface.identifier.as_deref()) {
+                    update.push((*node, *crtc));
+                } else {
+                    disable.push((*node, *crtc, surface.connector.clone()));
+                }
+            }
+            for (crtc, connector) in &backend.disabled_connectors {
+                let identifier =
+                    crate::edid::read(backend.drm_output_manager.device(), connector.handle())
+                        .map(|edid| edid.identifier());
+                if enabled(backend, connector, identifier.as_deref()) {
+                    enable.push((*node, *crtc, connector.clone()));
+                }
+            }
+        }
+
+        for (node, crtc, connector) in disable {
+            tracing::info!("Turning output {} off", connector_name(&connector));
+            self.connector_disconnected(node, &connector, crtc);
+            if let Some(device) = self.udev.as_mut().and_then(|udev| udev.backends.get_mut(&node)) {
+                device.disabled_connectors.insert(crtc, connector);
+            }
+        }
+        for (node, crtc) in update {
+            self.reconfigure_output(node, crtc);
+        }
+        for (node, crtc, connector) in enable {
+            self.connector_connected(node, &connector, crtc);
+        }
+
+        self.output_modes_changed();
+    }
+
+    /// Brings an enabled output in line with its `[[output]]` section, or back to the
+    /// defaults once the section is gone. Outputs without a configured position stay put.
+    fn reconfigure_output(&mut self, node: DrmNode, crtc: crtc::Handle) {
+        let Some(udev) = self.udev.as_mut() else {
+            return;
+        };
+        let primary_gpu = udev.primary_gpu;
+        let Some(device) = udev.backends.get_mut(&node) else {
+            return;
+        };
+        let Some(surface) = device.surfaces.get_mut(&crtc) else {
+            return;
+        };
+
+        let output = surface.output.clone();
+        let config = crate::config::output_config(
+            &self.output_configs,
+            &output.name(),
+            surface.identifier.as_deref(),
+        );
+        let Some(preferred_mode) = preferred_mode(&surface.connector) else {
+            return;
+        };
+        let drm_mode = config
+            .and_then(|config| config.mode)
+            .and_then(|mode| configured_mode(&surface.connector, &output.name(), mode))
+            .unwrap_or(preferred_mode);
+        let wl_mode = WlMode::from(drm_mode);
+
+        if output.current_mode() != Some(wl_mode) {
+            let render_node = device.render_node.unwrap_or(primary_gpu);
+            let mut renderer = match udev.gpus.single_renderer(&render_node) {
+                Ok(renderer) => renderer,
+                Err(err) => {
+                    tracing::warn!("Failed to get renderer: {err}");
+                    return;
+                }
+            };
+            if let Err(err) = surface
+                .drm_output
+                .use_mode::<_, smithay::desktop::space::SpaceRenderElements<
+                    UdevRenderer<'_>,
+                    RoundedSurfaceElement<UdevRenderer<'_>>,
+                >>(drm_mode, &mut renderer, &DrmOutputRenderElements::default())
+            {
+                tracing::warn!("Failed to change the mode of output {}: {err}", output.name());
+                return;
+            }
+        }
+
+        let transform = config.and_then(|config| config.transform).unwrap_or(Transform::Normal);
+        let scale = config
+            .and_then(|config| config.scale)
+            .map_or(OutputScale::Integer(1), OutputScale::Fractional);
+        let position = config
+            .and_then(|config| config.position)
+            .map(Point::from)
+            .or_else(|| self.space.output_geometry(&output).map(|geo| geo.loc))
+            .unwrap_or_default();
+
+        output.change_current_state(Some(wl_mode), Some(transform), Some(scale), Some(position));
+        self.space.map_output(&output, position);
+    }
+
     fn on_udev_event(&mut self, event: UdevEvent) {
         match event {
             UdevEvent::Added { device_id, path } => {
@@ -253,7 +770,7 @@ impl Smallvil {
             GbmAllocator::new(gbm.clone(), GbmBufferFlags::RENDERING | GbmBufferFlags::SCANOUT);
         let framebuffer_exporter =
             smithay::backend::drm::exporter::gbm::GbmFramebufferExporter::new(
-                gbm,
+                gbm.clone(),
                 Some(render_node),
             );
 
@@ -266,12 +783,17 @@ impl Smallvil {
             .copied()
             .collect::<FormatSet>();
 
+        let color_formats = color_formats(self.color_depth, &render_formats);
+
+        // With the gbm device the compositor can put the pointer on the cursor plane, so
+        // pointer motion only moves the plane instead of recompositing the output. Cursors
+        // too large for the plane are composited as before.
         let drm_output_manager = DrmOutputManager::new(
             drm,
             allocator,
             framebuffer_exporter,
-            None,
-            [Fourcc::Abgr8888, Fourcc::Argb8888],
+            Some(gbm),
+            color_formats,
             render_formats,
         );
 
@@ -281,6 +803,7 @@ impl Smallvil {
                 drm_output_manager,
                 drm_scanner: DrmScanner::new(),
                 surfaces: HashMap::new(),
+                disabled_connectors: HashMap::new(),
                 registration_token,
                 render_node: Some(render_node),
             },
@@ -297,6 +820,8 @@ impl Smallvil {
         connector: &connector::Info,
         crtc: crtc::Handle,
     ) {
+        let output_name = connector_name(connector);
+
         let Some(udev) = self.udev.as_mut() else {
             return;
         };
@@ -305,6 +830,30 @@ impl Smallvil {
             return;
         };
 
+        let edid = crate::edid::read(device.drm_output_manager.device(), connector.handle());
+        let identifier = edid.as_ref().map(crate::edid::EdidInfo::identifier);
+        if let Some(identifier) = &identifier {
+            tracing::info!("Output {output_name} is {identifier}");
+        }
+        let config =
+            crate::config::output_config(&self.output_configs, &output_name, identifier.as_deref())
+                .cloned();
+
+        let non_desktop = is_non_desktop(device.drm_output_manager.device(), connector.handle());
+        if !output_enabled(config.as_ref(), non_desktop) {
+            if non_desktop {
+                tracing::info!(
+                    "Skipping non-desktop output {output_name}, set force_enable in its \
+                     [[output]] section to use it anyway"
+                );
+            } else {
+                tracing::info!("Output {output_name} is disabled in the config");
+            }
+            // Its CRTC stays unused, so the connector is left for whoever leases it.
+            device.disabled_connectors.insert(crtc, connector.clone());
+            return;
+        }
+
         let render_node = device.render_node.unwrap_or(udev.primary_gpu);
         let mut renderer = match udev.gpus.single_renderer(&render_node) {
             Ok(renderer) => renderer,
@@ -314,20 +863,23 @@ impl Smallvil {
             }
         };
 
-        let output_name =
-            format!("{}-{}", connector.interface().as_str(), connector.interface_id());
-
-        let make = String::from("Unknown");
-        let model = String::from("Unknown");
-
-        let mode_id = connector
-            .modes()
-            .iter()
-            .position(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
-            .unwrap_or(0);
-        let drm_mode = connector.modes()[mode_id];
+        let Some(preferred_mode) = preferred_mode(connector) else {
+            tracing::warn!("Output {output_name} has no modes");
+            return;
+        };
+        let drm_mode = config
+            .as_ref()
+            .and_then(|config| config.mode)
+            .and_then(|mode| configured_mode(connector, &output_name, mode))
+            .unwrap_or(preferred_mode);
         let wl_mode = WlMode::from(drm_mode);
 
+        let serial = edid.as_ref().and_then(|edid| edid.serial.clone());
+        let (make, model) = edid.map_or_else(
+            || (String::from("Unknown"), String::from("Unknown")),
+            |edid| (edid.make, edid.model),
+        );
+
         let (phys_w, phys_h) = connector.size().unwrap_or((0, 0));
         let output = Output::new(
             output_name,
@@ -340,24 +892,32 @@ impl Smallvil {
         );
         let _global = output.create_global::<Self>(&self.display_handle);
 
-        let x = self
-            .space
-            .outputs()
-            .filter_map(|o| self.space.output_geometry(o).map(|geo| geo.size.w))
-            .sum();
-        let position = (x, 0).into();
-
-        output.set_preferred(wl_mode);
-        output.change_current_state(Some(wl_mode), None, None, Some(position));
-        self.space.map_output(&output, position);
+        let position = config.as_ref().and_then(|config| config.position).map_or_else(
+            || {
+                // Unconfigured outputs go to the right of everything else.
+                let x = self
+                    .space
+                    .outputs()
+                    .filter_map(|o| self.space.output_geometry(o))
+                    .map(|geo| geo.loc.x + geo.size.w)
+                    .max()
+                    .unwrap_or(0);
+                (x, 0).into()
+            },
+            Point::from,
+        );
+        let transform = config.as_ref().and_then(|config| config.transform);
+        let scale = config.as_ref().and_then(|config| config.scale).map(OutputScale::Fractional);
 
+        output.set_preferred(WlMode::from(preferred_mode));
+        output.change_current_state(Some(wl_mode), transform, scale, Some(position));
         output.user_data().insert_if_missing(|| UdevOutputId { device_id: node, crtc });
 
         let drm_output = match device
             .drm_output_manager
             .initialize_output::<_, smithay::desktop::space::SpaceRenderElements<
                 UdevRenderer<'_>,
-                WaylandSurfaceRenderElement<UdevRenderer<'_>>,
+                RoundedSurfaceElement<UdevRenderer<'_>>,
             >>(
                 crtc,
                 drm_mode,
@@ -374,7 +934,23 @@ impl Smallvil {
             }
         };
 
-        device.surfaces.insert(crtc, SurfaceData { output, drm_output });
+        tracing::info!("Output {} renders in {}", output.name(), drm_output.format());
+        device.surfaces.insert(
+            crtc,
+            SurfaceData {
+                output: output.clone(),
+                drm_output,
+                connector: connector.clone(),
+                identifier,
+                serial,
+                dirty: true,
+                frame_pending: false,
+            },
+        );
+
+        self.space.map_output(&output, position);
+        self.ensure_output_workspace(&output);
+        self.output_modes_changed();
 
         self.arrange_windows_tiled();
         self.render_surface(node, crtc);
@@ -394,8 +970,10 @@ impl Smallvil {
             return;
         };
 
+        device.disabled_connectors.remove(&crtc);
         if let Some(surface) = device.surfaces.remove(&crtc) {
-            self.space.unmap_output(&surface.output);
+            self.remove_output(&surface.output);
+            self.output_modes_changed();
             self.arrange_windows_tiled();
             self.space.refresh();
         }
@@ -433,7 +1011,7 @@ impl Smallvil {
     }
 
     fn device_removed(&mut self, node: DrmNode) {
-        let registration_token = {
+        let (outputs, registration_token) = {
             let Some(udev) = self.udev.as_mut() else {
                 return;
             };
@@ -442,16 +1020,15 @@ impl Smallvil {
                 return;
             };
 
-            let crtcs: Vec<_> = device.surfaces.keys().copied().collect();
-            for crtc in crtcs {
-                if let Some(surface) = device.surfaces.remove(&crtc) {
-                    self.space.unmap_output(&surface.output);
-                }
-            }
-
-            device.registration_token
+            let outputs: Vec<Output> =
+                device.surfaces.drain().map(|(_, surface)| surface.output).collect();
+            (outputs, device.registration_token)
         };
 
+        for output in &outputs {
+            self.remove_output(output);
+        }
+        self.output_modes_changed();
         self.arrange_windows_tiled();
 
         if let Some(udev) = self.udev.as_mut() {
@@ -463,7 +1040,7 @@ impl Smallvil {
         &mut self,
         node: DrmNode,
         crtc: crtc::Handle,
-        _metadata: &mut Option<DrmEventMetadata>,
+        metadata: &mut Option<DrmEventMetadata>,
     ) {
         let Some(udev) = self.udev.as_mut() else {
             return;
@@ -477,17 +1054,52 @@ impl Smallvil {
             return;
         };
 
+        surface.frame_pending = false;
         if let Err(err) = surface.drm_output.frame_submitted() {
             tracing::warn!("Failed to submit frame: {err}");
             return;
         }
+        let output = surface.output.clone();
+        let dirty = surface.dirty;
 
-        self.render_surface(node, crtc);
+        // The frame is on screen, clients may start drawing the next one.
+        let time = vblank_time(self.start_time, metadata.as_ref());
+        self.send_frame_callbacks(&output, time);
+
+        // Nothing changed during the last frame: stay idle until something does.
+        if dirty {
+            self.render_surface(node, crtc);
+        }
+    }
+
+    /// Sends the frame callbacks of a frame that didn't reach the screen about when its
+    /// vblank would have been, so clients whose commits changed nothing don't spin.
+    fn send_frame_callbacks_later(&mut self, output: Output) {
+        let Some(udev) = self.udev.as_ref() else {
+            return;
+        };
+
+        let timer = Timer::from_duration(crate::state::frame_interval(&output));
+        let result = udev.handle.insert_source(timer, move |_, (), state| {
+            let time = state.start_time.elapsed();
+            state.send_frame_callbacks(&output, time);
+            TimeoutAction::Drop
+        });
+        if let Err(err) = result {
+            tracing::warn!("Failed to schedule frame callbacks: {err}");
+        }
     }
 
     #[allow(clippy::too_many_lines)]
     fn render_surface(&mut self, node: DrmNode, crtc: crtc::Handle) {
-        let (output, render_result) = {
+        // Resolved up front, the backend state stays mutably borrowed while rendering.
+        let border_colors: Vec<(smithay::desktop::Window, [f32; 4])> = self
+            .space
+            .elements()
+            .map(|window| (window.clone(), self.border_color(window)))
+            .collect();
+
+        let (output, is_empty, states) = {
             let Some(udev) = self.udev.as_mut() else {
                 return;
             };
@@ -500,6 +1112,8 @@ impl Smallvil {
                 return;
             };
 
+            surface.dirty = false;
+
             let Some(output_geometry) = self.space.output_geometry(&surface.output) else {
                 return;
             };
@@ -526,139 +1140,156 @@ impl Smallvil {
                 }
             };
 
-            let space_elements = match smithay::desktop::space::space_render_elements(
+            let rounding = Rounding::new(&mut renderer, self.corner_radius);
+            let space_elements = crate::drawing::space_render_elements(
                 &mut renderer,
-                [&self.space],
+                &self.space,
                 &surface.output,
-                1.0,
-            ) {
-                Ok(elements) => elements,
-                Err(err) => {
-                    tracing::warn!("Failed to collect render elements: {err}");
-                    return;
-                }
+                &self.window_rules,
+                self.active_surface.as_ref(),
+                self.inactive_dim,
+                rounding.as_ref(),
+            );
+
+            // A fullscreen window hides the wallpaper and every border on its output.
+            let fullscreen = crate::window::has_fullscreen_window(&self.space, &surface.output);
+
+            // Translucent windows have to be blended, so they can never go straight to a plane.
+            // An opaque fullscreen window is all there is on its output, its buffer may go to
+            // the primary plane whatever its format; the compositor falls back to compositing
+            // on its own once anything shows up above it.
+            let frame_flags = if space_elements.iter().any(|element| element.alpha() < 1.0) {
+                FrameFlags::DEFAULT
+                    & !(FrameFlags::ALLOW_PRIMARY_PLANE_SCANOUT
+                        | FrameFlags::ALLOW_PRIMARY_PLANE_SCANOUT_ANY
+                        | FrameFlags::ALLOW_OVERLAY_PLANE_SCANOUT)
+            } else if fullscreen {
+                FrameFlags::DEFAULT | FrameFlags::ALLOW_PRIMARY_PLANE_SCANOUT_ANY
+            } else {
+                FrameFlags::DEFAULT
             };
 
             let mut elements: Vec<
-                UdevOutputRenderElements<
-                    UdevRenderer<'_>,
-                    WaylandSurfaceRenderElement<UdevRenderer<'_>>,
-                >,
+                UdevOutputRenderElements<UdevRenderer<'_>, RoundedSurfaceElement<UdevRenderer<'_>>>,
             > = Vec::new();
-            let wallpaper_element = surface
-                .output
-                .current_mode()
-                .and_then(|mode| self.wallpaper.render_element(&mut renderer, mode.size));
-
-            let frame = udev.pointer_image.get_image(1, self.start_time.elapsed());
-            let pointer_image = udev
-                .pointer_images
-                .iter()
-                .find_map(
-                    |(image, texture)| {
-                        if image == &frame { Some(texture.clone()) } else { None }
-                    },
-                )
-                .unwrap_or_else(|| {
-                    let buffer = MemoryRenderBuffer::from_slice(
-                        &frame.pixels_rgba,
-                        Fourcc::Argb8888,
-                        (u32_to_i32_saturating(frame.width), u32_to_i32_saturating(frame.height)),
-                        1,
-                        Transform::Normal,
-                        None,
-                    );
-                    udev.pointer_images.push((frame.clone(), buffer.clone()));
-                    buffer
-                });
-
-            if let smithay::input::pointer::CursorImageStatus::Surface(ref cursor_surface) =
-                self.cursor_status
-                && !cursor_surface.alive()
+            if let Some(overlay) = self.debug_overlay.render_element(&mut renderer, &surface.output)
             {
-                self.cursor_status = smithay::input::pointer::CursorImageStatus::default_named();
+                elements.push(UdevOutputRenderElements::Debug(overlay));
             }
+            let wallpaper_element = (!fullscreen)
+                .then(|| {
+                    self.wallpaper
+                        .for_output(&surface.output)
+                        .render_element(&mut renderer, &surface.output)
+                })
+                .flatten();
+
+            let output_scale = surface.output.current_scale().fractional_scale();
+            elements.extend(
+                udev.cursor
+                    .render_elements(
+                        &mut renderer,
+                        &mut self.seat,
+                        &surface.output,
+                        output_geometry,
+                        self.start_time.elapsed(),
+                    )
+                    .into_iter()
+                    .map(UdevOutputRenderElements::Pointer),
+            );
+            elements.extend(
+                crate::drawing::dnd_icon_elements(
+                    &mut renderer,
+                    &self.seat,
+                    &surface.output,
+                    output_geometry,
+                )
+                .into_iter()
+                .map(UdevOutputRenderElements::Pointer),
+            );
 
-            let hotspot =
-                if let smithay::input::pointer::CursorImageStatus::Surface(ref cursor_surface) =
-                    self.cursor_status
-                {
-                    compositor::with_states(cursor_surface, |states| {
-                        states
-                        .data_map
-                        .get::<std::sync::Mutex<smithay::input::pointer::CursorImageAttributes>>()
-                        .and_then(|attrs| attrs.lock().ok().map(|guard| guard.hotspot))
-                        .unwrap_or_else(|| {
-                            (
-                                u32_to_i32_saturating(frame.xhot),
-                                u32_to_i32_saturating(frame.yhot),
-                            )
-                                .into()
-                        })
-                    })
-                } else {
-                    (u32_to_i32_saturating(frame.xhot), u32_to_i32_saturating(frame.yhot)).into()
+            if !fullscreen {
+                let border_color = |window: &smithay::desktop::Window| {
+                    border_colors
+                        .iter()
+                        .find_map(|(w, color)| (w == window).then_some(*color))
+                        .unwrap_or(self.inactive_border_color)
                 };
-
-            let Some(pointer) = self.seat.get_pointer() else {
-                return;
-            };
-
-            let pointer_location = pointer.current_location();
-            if output_geometry.to_f64().contains(pointer_location) {
-                let cursor_pos = pointer_location - output_geometry.loc.to_f64();
-                udev.pointer_element.set_buffer(pointer_image);
-                udev.pointer_element.set_status(self.cursor_status.clone());
-                elements.extend(
-                    udev.pointer_element
-                        .render_elements(
-                            &mut renderer,
-                            (cursor_pos - hotspot.to_f64())
-                                .to_physical(Scale::from(
-                                    surface.output.current_scale().fractional_scale(),
-                                ))
-                                .to_i32_round(),
-                            Scale::from(surface.output.current_scale().fractional_scale()),
-                            1.0,
-                        )
-                        .into_iter()
-                        .map(UdevOutputRenderElements::Pointer),
-                );
+                if let Some(rounding) = &rounding {
+                    let border_elements = crate::drawing::rounded_border_elements(
+                        output_geometry,
+                        &self.space,
+                        border_color,
+                        self.border_width,
+                        self.border_outside,
+                        rounding,
+                    );
+                    elements.extend(
+                        border_elements.into_iter().map(UdevOutputRenderElements::RoundedBorder),
+                    );
+                } else {
+                    let border_elements = crate::drawing::tiled_border_elements(
+                        output_geometry,
+                        &self.space,
+                        border_color,
+                        self.border_width,
+                        self.border_outside,
+                        Scale::from(output_scale),
+                    );
+                    elements
+                        .extend(border_elements.into_iter().map(UdevOutputRenderElements::Border));
+                }
             }
 
-            let border_elements = crate::drawing::tiled_border_elements(
-                output_geometry,
-                &self.space,
-                self.active_surface.as_ref(),
-                self.active_border_color,
-                self.inactive_border_color,
-                self.border_width,
-            );
-            elements.extend(border_elements.into_iter().map(UdevOutputRenderElements::Border));
-
             elements.extend(space_elements.into_iter().map(UdevOutputRenderElements::Space));
 
             if let Some(wallpaper_element) = wallpaper_element {
                 elements.push(UdevOutputRenderElements::Wallpaper(wallpaper_element));
             }
 
-            let is_empty = match surface.drm_output.render_frame(
+            let render_start = Instant::now();
+            let (is_empty, states) = match surface.drm_output.render_frame(
                 &mut renderer,
                 &elements,
                 [0.1, 0.1, 0.1, 1.0],
-                smithay::backend::drm::compositor::FrameFlags::DEFAULT,
+                frame_flags,
             ) {
-                Ok(result) => result.is_empty,
+                Ok(result) => (result.is_empty, result.states),
                 Err(err) => {
                     tracing::warn!("Render failed: {err}");
                     return;
                 }
             };
+            self.debug_overlay.record(&surface.output, render_start.elapsed(), elements.len());
+
+            if let Some(request) =
+                self.pending_screenshot.take_if(|request| request.output == surface.output)
+            {
+                let shot: Vec<_> = elements
+                    .iter()
+                    .filter(|element| {
+                        !matches!(
+                            element,
+                            UdevOutputRenderElements::Debug(_)
+                                | UdevOutputRenderElements::Pointer(_)
+                        )
+                    })
+                    .collect();
+                match crate::screenshot::capture(&mut renderer, &request, &shot) {
+                    Ok(image) => crate::screenshot::save(
+                        image,
+                        crate::screenshot::directory(self.screenshot_dir.as_deref()),
+                    ),
+                    Err(err) => tracing::warn!("Failed to take screenshot: {err}"),
+                }
+            }
 
-            (surface.output.clone(), is_empty)
+            (surface.output.clone(), is_empty, states)
         };
+        self.update_primary_scanout_output(&output, &states);
 
-        if !render_result {
+        let mut queued = false;
+        if !is_empty {
             let Some(udev) = self.udev.as_mut() else {
                 return;
             };
@@ -668,16 +1299,19 @@ impl Smallvil {
             let Some(surface) = device.surfaces.get_mut(&crtc) else {
                 return;
             };
-            if let Err(err) = surface.drm_output.queue_frame(()) {
-                tracing::warn!("Failed to queue frame: {err}");
+            match surface.drm_output.queue_frame(()) {
+                Ok(()) => {
+                    surface.frame_pending = true;
+                    queued = true;
+                }
+                Err(err) => tracing::warn!("Failed to queue frame: {err}"),
             }
         }
 
-        self.space.elements().for_each(|window| {
-            window.send_frame(&output, self.start_time.elapsed(), Some(Duration::ZERO), |_, _| {
-                Some(output.clone())
-            });
-        });
+        // Queued frames send their callbacks on vblank.
+        if !queued {
+            self.send_frame_callbacks_later(output);
+        }
 
         self.space.refresh();
         self.popups.cleanup();
@@ -0,0 +1,571 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use image::{DynamicImage, Rgba, RgbaImage, imageops, imageops::FilterType};
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            ImportMem, Renderer,
+            element::{
+                Kind,
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+            },
+        },
+    },
+    output::Output,
+    reexports::calloop::{
+        LoopHandle, RegistrationToken, channel,
+        timer::{TimeoutAction, Timer},
+    },
+    render_elements,
+    utils::{Logical, Physical, Scale, Size, Transform},
+};
+
+use crate::{
+    Smallvil,
+    config::{RipwmConfig, WallpaperMode, WallpaperSetting},
+};
+
+/// How many output sizes keep a scaled wallpaper around.
+const MAX_CACHED_SIZES: usize = 4;
+
+/// How long an output has to keep its size before the wallpaper is scaled for it, so an
+/// interactive resize of the nested window doesn't scale once per intermediate size.
+const SCALE_DELAY: Duration = Duration::from_millis(150);
+
+enum WallpaperSource {
+    Off,
+    /// Decoded and scaled by the worker thread.
+    Image,
+    Color([f32; 4]),
+}
+
+/// What was built for one output size. Solid colours skip the image pipeline entirely.
+enum CachedWallpaper {
+    Image(MemoryRenderBuffer),
+    Solid(SolidColorBuffer),
+}
+
+struct CacheEntry {
+    wallpaper: CachedWallpaper,
+    /// Value of [`WallpaperState::uses`] when the entry was last drawn.
+    last_used: u64,
+}
+
+/// Work for the wallpaper thread, handled in order.
+enum WallpaperJob {
+    Load { generation: u64, path: PathBuf, mode: WallpaperMode, background: [f32; 4] },
+    Scale { generation: u64, size: (i32, i32) },
+}
+
+/// A scaled wallpaper coming back from the worker thread.
+struct WallpaperReady {
+    generation: u64,
+    size: (i32, i32),
+    buffer: MemoryRenderBuffer,
+}
+
+render_elements! {
+    pub WallpaperRenderElement<R> where R: ImportMem;
+    Image=MemoryRenderBufferRenderElement<R>,
+    Solid=SolidColorRenderElement,
+}
+
+impl<R: Renderer> std::fmt::Debug for WallpaperRenderElement<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Image(arg0) => f.debug_tuple("Image").field(arg0).finish(),
+            Self::Solid(arg0) => f.debug_tuple("Solid").field(arg0).finish(),
+            Self::_GenericCatcher(arg0) => f.debug_tuple("_GenericCatcher").field(arg0).finish(),
+        }
+    }
+}
+
+/// The wallpaper of every output, and those set for single outputs at runtime.
+pub struct Wallpapers {
+    default: WallpaperState,
+    /// Wallpapers of single outputs by output name, until the next reload.
+    outputs: HashMap<String, WallpaperState>,
+    loop_handle: LoopHandle<'static, Smallvil>,
+    mode: WallpaperMode,
+    background: [f32; 4],
+}
+
+impl Wallpapers {
+    pub fn new(config: &RipwmConfig, loop_handle: &LoopHandle<'static, Smallvil>) -> Self {
+        let mut default = WallpaperState::new(loop_handle, None);
+        default.reload(config);
+        Self {
+            default,
+            outputs: HashMap::new(),
+            loop_handle: loop_handle.clone(),
+            mode: config.wallpaper_mode,
+            background: config.wallpaper_background,
+        }
+    }
+
+    /// Switches to the wallpaper of `config`, forgetting the ones set for single outputs.
+    pub fn reload(&mut self, config: &RipwmConfig) {
+        self.default.reload(config);
+        self.outputs.clear();
+        self.mode = config.wallpaper_mode;
+        self.background = config.wallpaper_background;
+    }
+
+    /// Shows `setting` on the output named `output`, or on all of them, with the
+    /// configured mode and background.
+    pub fn set(&mut self, output: Option<&str>, setting: &WallpaperSetting) {
+        let (mode, background) = (self.mode, self.background);
+        match output {
+            Some(name) => self
+                .outputs
+                .entry(name.to_string())
+                .or_insert_with(|| WallpaperState::new(&self.loop_handle, Some(name.to_string())))
+                .set(setting, mode, background),
+            None => {
+                self.outputs.clear();
+                self.default.set(setting, mode, background);
+            }
+        }
+    }
+
+    /// The wallpaper drawn on `output`.
+    pub fn for_output(&mut self, output: &Output) -> &mut WallpaperState {
+        match self.outputs.get_mut(&output.name()) {
+            Some(wallpaper) => wallpaper,
+            None => &mut self.default,
+        }
+    }
+
+    /// The wallpaper of the output named `output`, or the default one for `None`.
+    fn get_mut(&mut self, output: Option<&str>) -> Option<&mut WallpaperState> {
+        match output {
+            Some(name) => self.outputs.get_mut(name),
+            None => Some(&mut self.default),
+        }
+    }
+
+    /// How many scaled buffers are kept, for `ripctl debug`.
+    pub fn cached_buffers(&self) -> usize {
+        self.outputs
+            .values()
+            .chain([&self.default])
+            .map(|wallpaper| wallpaper.cached_by_size.len())
+            .sum()
+    }
+
+    /// Forgets buffers and pending work for sizes no output has anymore.
+    pub fn retain_sizes(&mut self, sizes: &[Size<i32, Physical>]) {
+        self.default.retain_sizes(sizes);
+        for wallpaper in self.outputs.values_mut() {
+            wallpaper.retain_sizes(sizes);
+        }
+    }
+}
+
+/// A wallpaper and a buffer for each of the last few output sizes. Images are decoded
+/// and scaled on a worker thread. Until the buffer for a size arrives, the last scaled
+/// buffer is stretched over the output, or nothing is drawn.
+pub struct WallpaperState {
+    /// The output this wallpaper was set for, `None` for the default one.
+    output: Option<String>,
+    source: WallpaperSource,
+    /// Bumped on every reload so results of jobs for an older config are dropped.
+    generation: u64,
+    jobs: Option<mpsc::Sender<WallpaperJob>>,
+    loop_handle: LoopHandle<'static, Smallvil>,
+    results: Option<RegistrationToken>,
+    cached_by_size: HashMap<(i32, i32), CacheEntry>,
+    /// Counts draws, for evicting the least recently used size.
+    uses: u64,
+    /// Sizes waiting for [`SCALE_DELAY`] or for the worker, in the current generation.
+    pending: HashSet<(i32, i32)>,
+    /// The most recently scaled buffer, drawn while another size is pending.
+    fallback: Option<MemoryRenderBuffer>,
+}
+
+impl WallpaperState {
+    fn new(loop_handle: &LoopHandle<'static, Smallvil>, output: Option<String>) -> Self {
+        let (results_sender, results) = channel::channel::<WallpaperReady>();
+        let owner = output.clone();
+        let inserted = loop_handle.insert_source(results, move |event, (), state| {
+            if let channel::Event::Msg(ready) = event
+                && state
+                    .wallpaper
+                    .get_mut(owner.as_deref())
+                    .is_some_and(|wallpaper| wallpaper.finish(ready))
+            {
+                state.request_redraw_all();
+            }
+        });
+        let results = inserted
+            .map_err(|err| tracing::error!("Failed to listen for wallpaper buffers: {err}"))
+            .ok();
+
+        let (jobs, job_receiver) = mpsc::channel();
+        let jobs = match thread::Builder::new()
+            .name(String::from("wallpaper"))
+            .spawn(move || run_worker(&job_receiver, &results_sender))
+        {
+            Ok(_) => Some(jobs),
+            Err(err) => {
+                tracing::error!("Failed to start wallpaper thread: {err}");
+                None
+            }
+        };
+
+        Self {
+            output,
+            source: WallpaperSource::Off,
+            generation: 0,
+            jobs,
+            loop_handle: loop_handle.clone(),
+            results,
+            cached_by_size: HashMap::new(),
+            uses: 0,
+            pending: HashSet::new(),
+            fallback: None,
+        }
+    }
+
+    /// Switches to the wallpaper of `config`, dropping every cached buffer.
+    pub fn reload(&mut self, config: &RipwmConfig) {
+        self.set(&config.wallpaper, config.wallpaper_mode, config.wallpaper_background);
+    }
+
+    /// Switches to `setting`, dropping every cached buffer.
+    pub fn set(&mut self, setting: &WallpaperSetting, mode: WallpaperMode, background: [f32; 4]) {
+        self.generation += 1;
+        self.cached_by_size.clear();
+        self.pending.clear();
+        self.fallback = None;
+
+        self.source = match setting {
+            WallpaperSetting::Off => WallpaperSource::Off,
+            WallpaperSetting::Color(color) => WallpaperSource::Color(*color),
+            WallpaperSetting::Path(path) => {
+                let job = WallpaperJob::Load {
+                    generation: self.generation,
+                    path: path.clone(),
+                    mode,
+                    background,
+                };
+                if self.send_job(job) { WallpaperSource::Image } else { WallpaperSource::Off }
+            }
+        };
+    }
+
+    fn send_job(&self, job: WallpaperJob) -> bool {
+        let sent = self.jobs.as_ref().is_some_and(|jobs| jobs.send(job).is_ok());
+        if !sent {
+            tracing::warn!("Wallpaper thread is not running");
+        }
+        sent
+    }
+
+    /// Stores a buffer from the worker. Returns whether it is for the current wallpaper
+    /// and a size that is still wanted.
+    fn finish(&mut self, ready: WallpaperReady) -> bool {
+        if ready.generation != self.generation || !self.pending.remove(&ready.size) {
+            return false;
+        }
+
+        self.fallback = Some(ready.buffer.clone());
+        self.insert(ready.size, CachedWallpaper::Image(ready.buffer));
+        true
+    }
+
+    fn insert(&mut self, size: (i32, i32), wallpaper: CachedWallpaper) {
+        self.uses += 1;
+        self.cached_by_size.insert(size, CacheEntry { wallpaper, last_used: self.uses });
+
+        while self.cached_by_size.len() > MAX_CACHED_SIZES {
+            let Some(oldest) = self
+                .cached_by_size
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(size, _)| *size)
+            else {
+                break;
+            };
+            self.cached_by_size.remove(&oldest);
+        }
+    }
+
+    /// Forgets buffers and pending work for sizes no output has anymore.
+    pub fn retain_sizes(&mut self, sizes: &[Size<i32, Physical>]) {
+        let wanted = |size: &(i32, i32)| sizes.iter().any(|s| (s.w, s.h) == *size);
+        self.cached_by_size.retain(|size, _| wanted(size));
+        self.pending.retain(wanted);
+    }
+
+    /// Asks the worker to scale for `size` once it has been pending for [`SCALE_DELAY`].
+    fn schedule_scale(&mut self, size: (i32, i32)) {
+        let generation = self.generation;
+        let owner = self.output.clone();
+        let scheduled = self.loop_handle.insert_source(
+            Timer::from_duration(SCALE_DELAY),
+            move |_, (), state| {
+                let Some(wallpaper) = state.wallpaper.get_mut(owner.as_deref()) else {
+                    return TimeoutAction::Drop;
+                };
+                if wallpaper.generation == generation && wallpaper.pending.contains(&size) {
+                    wallpaper.send_job(WallpaperJob::Scale { generation, size });
+                }
+                TimeoutAction::Drop
+            },
+        );
+
+        if let Err(err) = scheduled {
+            tracing::warn!("Failed to schedule wallpaper scaling: {err}");
+            self.send_job(WallpaperJob::Scale { generation, size });
+        }
+    }
+
+    /// The wallpaper covering `output`.
+    pub fn render_element<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Option<WallpaperRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        R::TextureId: Send + Clone + 'static,
+    {
+        let size = output_size(output)?;
+        if size.w <= 0 || size.h <= 0 {
+            return None;
+        }
+        let scale = output.current_scale().fractional_scale();
+        // Image elements are sized in logical units, rounded up so no pixel row is left
+        // uncovered at a fractional scale.
+        let logical_size: Size<i32, Logical> = size.to_f64().to_logical(scale).to_i32_ceil();
+
+        let key = (size.w, size.h);
+        if !self.cached_by_size.contains_key(&key) {
+            match self.source {
+                WallpaperSource::Off => return None,
+                WallpaperSource::Color(color) => {
+                    let buffer = SolidColorBuffer::new((size.w, size.h), color);
+                    self.insert(key, CachedWallpaper::Solid(buffer));
+                }
+                WallpaperSource::Image => {
+                    if self.pending.insert(key) {
+                        self.schedule_scale(key);
+                    }
+
+                    let fallback = self.fallback.as_ref()?;
+                    return MemoryRenderBufferRenderElement::from_buffer(
+                        renderer,
+                        (0.0, 0.0),
+                        fallback,
+                        None,
+                        None,
+                        Some(logical_size),
+                        Kind::Unspecified,
+                    )
+                    .ok()
+                    .map(WallpaperRenderElement::Image);
+                }
+            }
+        }
+
+        self.uses += 1;
+        let entry = self.cached_by_size.get_mut(&key)?;
+        entry.last_used = self.uses;
+
+        match &entry.wallpaper {
+            CachedWallpaper::Image(buffer) => MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                (0.0, 0.0),
+                buffer,
+                None,
+                None,
+                Some(logical_size),
+                Kind::Unspecified,
+            )
+            .ok()
+            .map(WallpaperRenderElement::Image),
+            CachedWallpaper::Solid(buffer) => {
+                Some(WallpaperRenderElement::Solid(SolidColorRenderElement::from_buffer(
+                    buffer,
+                    (0, 0),
+                    Scale::from(1.0),
+                    1.0,
+                    Kind::Unspecified,
+                )))
+            }
+        }
+    }
+}
+
+impl Drop for WallpaperState {
+    fn drop(&mut self) {
+        // Dropping the job sender ends the worker thread.
+        if let Some(results) = self.results.take() {
+            self.loop_handle.remove(results);
+        }
+    }
+}
+
+impl Smallvil {
+    /// Shows the wallpaper `value` on the output named `output`, or on all of them, and
+    /// with `save` writes it to the config file. An image that can't be read is an error
+    /// and leaves the current wallpaper alone.
+    pub(crate) fn set_wallpaper(
+        &mut self,
+        value: &str,
+        output: Option<&str>,
+        save: bool,
+    ) -> Result<(), String> {
+        let setting = crate::config::parse_wallpaper(value)?;
+        if let WallpaperSetting::Path(path) = &setting {
+            check_image(path)?;
+        }
+
+        let target = match output {
+            Some(name) => Some(
+                self.space
+                    .outputs()
+                    .find(|output| output.name() == name)
+                    .cloned()
+                    .ok_or_else(|| format!("no output named {name}"))?,
+            ),
+            None => None,
+        };
+        if save {
+            if output.is_some() {
+                return Err("only a wallpaper for every output can be saved".into());
+            }
+            crate::config::save_wallpaper(&self.config_path, value)?;
+        }
+
+        self.wallpaper.set(output, &setting);
+        tracing::info!("Set wallpaper of {} to {value}", output.unwrap_or("every output"));
+        match target {
+            Some(output) => self.request_redraw(&output),
+            None => self.request_redraw_all(),
+        }
+        Ok(())
+    }
+
+    /// Call after an output was added, removed or changed its mode.
+    pub fn output_modes_changed(&mut self) {
+        let sizes: Vec<_> = self.space.outputs().filter_map(output_size).collect();
+        self.wallpaper.retain_sizes(&sizes);
+    }
+}
+
+struct LoadedWallpaper {
+    generation: u64,
+    image: DynamicImage,
+    mode: WallpaperMode,
+    background: [f32; 4],
+}
+
+/// The size of `output` in pixels as shown, so rotated by its transform.
+fn output_size(output: &Output) -> Option<Size<i32, Physical>> {
+    let mode = output.current_mode()?;
+    Some(output.current_transform().transform_size(mode.size))
+}
+
+fn run_worker(jobs: &mpsc::Receiver<WallpaperJob>, results: &channel::Sender<WallpaperReady>) {
+    let mut loaded: Option<LoadedWallpaper> = None;
+
+    while let Ok(job) = jobs.recv() {
+        match job {
+            WallpaperJob::Load { generation, path, mode, background } => {
+                loaded = match image::open(&path) {
+                    Ok(image) => Some(LoadedWallpaper { generation, image, mode, background }),
+                    Err(err) => {
+                        tracing::warn!("Failed to load wallpaper {}: {err}", path.display());
+                        None
+                    }
+                };
+            }
+            WallpaperJob::Scale { generation, size } => {
+                let Some(loaded) = loaded.as_ref().filter(|loaded| loaded.generation == generation)
+                else {
+                    continue;
+                };
+                let Some(buffer) = create_buffer(loaded, size) else {
+                    continue;
+                };
+                if results.send(WallpaperReady { generation, size, buffer }).is_err() {
+                    // The event loop is gone.
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn create_buffer(loaded: &LoadedWallpaper, size: (i32, i32)) -> Option<MemoryRenderBuffer> {
+    let width = u32::try_from(size.0).ok()?;
+    let height = u32::try_from(size.1).ok()?;
+    let image = &loaded.image;
+
+    let mut canvas = background_canvas(loaded.background, width, height);
+    match loaded.mode {
+        WallpaperMode::Fill => {
+            let resized = image.resize_to_fill(width, height, FilterType::Lanczos3).to_rgba8();
+            imageops::overlay(&mut canvas, &resized, 0, 0);
+        }
+        WallpaperMode::Fit => {
+            let resized = image.resize(width, height, FilterType::Lanczos3).to_rgba8();
+            let x = (i64::from(width) - i64::from(resized.width())) / 2;
+            let y = (i64::from(height) - i64::from(resized.height())) / 2;
+            imageops::overlay(&mut canvas, &resized, x, y);
+        }
+        WallpaperMode::Center => {
+            let image = image.to_rgba8();
+            let x = (i64::from(width) - i64::from(image.width())) / 2;
+            let y = (i64::from(height) - i64::from(image.height())) / 2;
+            imageops::overlay(&mut canvas, &image, x, y);
+        }
+        WallpaperMode::Tile => {
+            let image = image.to_rgba8();
+            let step_x = usize::try_from(image.width()).ok().filter(|step| *step > 0)?;
+            let step_y = usize::try_from(image.height()).ok().filter(|step| *step > 0)?;
+            for y in (0..height).step_by(step_y) {
+                for x in (0..width).step_by(step_x) {
+                    imageops::overlay(&mut canvas, &image, i64::from(x), i64::from(y));
+                }
+            }
+        }
+    }
+
+    Some(MemoryRenderBuffer::from_slice(
+        canvas.as_raw(),
+        Fourcc::Abgr8888,
+        size,
+        1,
+        Transform::Normal,
+        None,
+    ))
+}
+
+fn background_canvas(background: [f32; 4], width: u32, height: u32) -> RgbaImage {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let [r, g, b, a] = background;
+    RgbaImage::from_pixel(width, height, Rgba([channel(r), channel(g), channel(b), channel(a)]))
+}
+
+/// Checks that the image at `path` can be opened and its format is one we decode, by
+/// reading its header.
+pub fn check_image(path: &Path) -> Result<(), String> {
+    image::ImageReader::open(path)
+        .and_then(image::ImageReader::with_guessed_format)
+        .map_err(|err| format!("failed to open wallpaper {}: {err}", path.display()))?
+        .into_dimensions()
+        .map_err(|err| format!("failed to read wallpaper {}: {err}", path.display()))?;
+    Ok(())
+}
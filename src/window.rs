@@ -0,0 +1,161 @@
+use std::{
+    cell::{RefCell, RefMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use smithay::{
+    desktop::{Space, Window},
+    output::Output,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, Rectangle},
+    wayland::{compositor::with_states, shell::xdg::XdgToplevelSurfaceData},
+};
+
+static NEXT_TILE_ORDER: AtomicU64 = AtomicU64::new(0);
+static NEXT_WINDOW_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_FOCUS: AtomicU64 = AtomicU64::new(1);
+
+/// Compositor-side bookkeeping attached to every mapped window.
+#[derive(Debug)]
+pub struct WindowData {
+    /// Floating windows are skipped by the tiler and stacked above tiled ones.
+    pub floating: bool,
+    /// Id of the workspace the window belongs to.
+    pub workspace: u32,
+    /// Fullscreen windows cover the output of their workspace and are stacked above
+    /// everything else.
+    pub fullscreen: bool,
+    /// Maximized windows cover the usable area of their output instead of a tile.
+    pub maximized: bool,
+    /// Geometry to go back to when the window is neither fullscreen nor maximized anymore.
+    pub restore_geometry: Option<Rectangle<i32, Logical>>,
+    /// Where the window was when it was unmapped, to map it back there.
+    pub hidden_location: Option<Point<i32, Logical>>,
+    /// The toplevel this one is a dialog of, from xdg_toplevel.set_parent or xdg-foreign.
+    pub parent: Option<WlSurface>,
+    /// A dialog was centred over its parent at its first committed size, later commits
+    /// leave it where it is.
+    pub centred: bool,
+    /// Set while an interactive resize is in progress and until its final size is committed.
+    pub resize: Option<crate::grabs::ResizeAnchor>,
+    /// Last committed xdg_toplevel title, may contain any UTF-8 including newlines.
+    pub title: Option<String>,
+    /// Last committed xdg_toplevel app_id, what window rules match against.
+    pub app_id: Option<String>,
+    /// Position in the tiling order. Independent of stacking so raising a window
+    /// doesn't move it to another tile.
+    pub tile_order: u64,
+    /// Identifies the window over IPC for as long as it lives.
+    pub id: u64,
+    /// When the window last had keyboard focus, larger is more recent and 0 is never.
+    pub focused_at: u64,
+}
+
+impl Default for WindowData {
+    fn default() -> Self {
+        Self {
+            floating: false,
+            workspace: 0,
+            fullscreen: false,
+            maximized: false,
+            restore_geometry: None,
+            hidden_location: None,
+            centred: false,
+            parent: None,
+            resize: None,
+            title: None,
+            app_id: None,
+            tile_order: NEXT_TILE_ORDER.fetch_add(1, Ordering::Relaxed),
+            id: NEXT_WINDOW_ID.fetch_add(1, Ordering::Relaxed),
+            focused_at: 0,
+        }
+    }
+}
+
+pub fn window_data(window: &Window) -> RefMut<'_, WindowData> {
+    let user_data = window.user_data();
+    user_data.insert_if_missing(|| RefCell::new(WindowData::default()));
+    user_data.get::<RefCell<WindowData>>().unwrap().borrow_mut()
+}
+
+pub fn is_floating(window: &Window) -> bool {
+    window_data(window).floating
+}
+
+pub fn workspace_of(window: &Window) -> u32 {
+    window_data(window).workspace
+}
+
+pub fn id(window: &Window) -> u64 {
+    window_data(window).id
+}
+
+pub fn focused_at(window: &Window) -> u64 {
+    window_data(window).focused_at
+}
+
+/// Records that `window` just got keyboard focus.
+pub fn mark_focused(window: &Window) {
+    window_data(window).focused_at = NEXT_FOCUS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn parent_of(window: &Window) -> Option<WlSurface> {
+    window_data(window).parent.clone()
+}
+
+/// Dialogs are floating windows with a parent. They follow the parent around and stay
+/// stacked above it.
+pub fn is_dialog(window: &Window) -> bool {
+    let data = window_data(window);
+    data.floating && data.parent.is_some()
+}
+
+pub fn is_fullscreen(window: &Window) -> bool {
+    window_data(window).fullscreen
+}
+
+pub fn is_maximized(window: &Window) -> bool {
+    window_data(window).maximized
+}
+
+/// Whether a fullscreen window covers `output`, in which case nothing below it is drawn.
+pub fn has_fullscreen_window(space: &Space<Window>, output: &Output) -> bool {
+    space
+        .elements()
+        .filter(|window| is_fullscreen(window))
+        .any(|window| space.outputs_for_element(window).contains(output))
+}
+
+pub fn title(window: &Window) -> Option<String> {
+    window_data(window).title.clone()
+}
+
+pub fn app_id(window: &Window) -> Option<String> {
+    window_data(window).app_id.clone()
+}
+
+/// Picks up the title and app_id from the toplevel's committed state.
+pub fn refresh_metadata(window: &Window) {
+    let Some(toplevel) = window.toplevel() else {
+        return;
+    };
+
+    let (title, app_id) = with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .and_then(|data| {
+                data.lock().ok().map(|guard| (guard.title.clone(), guard.app_id.clone()))
+            })
+            .unwrap_or_default()
+    });
+
+    let mut data = window_data(window);
+    if data.title == title && data.app_id == app_id {
+        return;
+    }
+
+    tracing::debug!(?title, ?app_id, "Window metadata changed");
+    data.title = title;
+    data.app_id = app_id;
+}
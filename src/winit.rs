@@ -1,152 +1,363 @@
-use std::time::Duration;
-
 use smithay::{
     backend::{
+        allocator::Fourcc,
         renderer::{
-            ImportAll, ImportMem, damage::OutputDamageTracker,
-            element::memory::MemoryRenderBufferRenderElement,
-            element::solid::SolidColorRenderElement, element::surface::WaylandSurfaceRenderElement,
-            gles::GlesRenderer,
+            Bind, Color32F, Frame, Offscreen, Renderer,
+            damage::OutputDamageTracker,
+            gles::{GlesError, GlesRenderer, GlesTarget, GlesTexture},
         },
-        winit::{self, WinitEvent},
+        winit::{self, WinitEvent, WinitGraphicsBackend},
     },
-    output::{Mode, Output, PhysicalProperties, Subpixel},
+    output::{Mode, Output, PhysicalProperties, Scale as OutputScale, Subpixel},
     reexports::calloop::EventLoop,
-    utils::{Rectangle, Transform},
+    utils::{Logical, Physical, Point, Rectangle, Transform},
 };
 
-use crate::Smallvil;
+use crate::{Smallvil, cli::Options, drawing::SoftwareCursor};
+
+const REFRESH_MHZ: i32 = 60_000;
+
+/// The nested window. Frames are only drawn after something asked for a redraw, an idle
+/// session leaves the host alone.
+pub struct WinitData {
+    backend: WinitGraphicsBackend<GlesRenderer>,
+    /// The outputs the window is split into, left to right.
+    outputs: Vec<WinitOutput>,
+    /// A redraw was asked of the host and its event has not arrived yet.
+    dirty: bool,
+    cursor: SoftwareCursor,
+    /// `--software-cursor`, which draws the pointer whatever the config says.
+    force_software_cursor: bool,
+}
+
+/// One column of the window, with an output of its own.
+struct WinitOutput {
+    output: Output,
+    damage_tracker: OutputDamageTracker,
+    /// With several outputs each is drawn into a buffer of its own first, then the
+    /// buffers are copied side by side into the window.
+    buffer: Option<GlesTexture>,
+}
+
+impl WinitData {
+    pub fn request_redraw(&mut self) {
+        if !self.dirty {
+            self.dirty = true;
+            self.backend.window().request_redraw();
+        }
+    }
+
+    pub fn cursor(&self) -> &SoftwareCursor {
+        &self.cursor
+    }
 
-smithay::backend::renderer::element::render_elements! {
-    pub WinitOutputRenderElements<R, E> where R: ImportAll + ImportMem;
-    Space=smithay::desktop::space::SpaceRenderElements<R, E>,
-    Wallpaper=MemoryRenderBufferRenderElement<R>,
-    Border=SolidColorRenderElement,
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter().map(|target| &target.output)
+    }
 }
 
 pub fn init_winit(
     event_loop: &EventLoop<Smallvil>,
     state: &mut Smallvil,
+    options: &Options,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let (mut backend, winit) = winit::init()?;
+    let (backend, winit) = winit::init()?;
 
-    let mode = Mode { size: backend.window_size(), refresh: 60_000 };
+    let count = options.winit_outputs.unwrap_or(1);
+    let mut outputs = Vec::new();
+    for index in 0..count {
+        // The first output keeps its old name, so configs written for it still apply.
+        let name = if index == 0 { "winit".to_string() } else { format!("winit-{}", index + 1) };
+        let output = Output::new(
+            name,
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Smithay".into(),
+                model: "Winit".into(),
+            },
+        );
+        let _global = output.create_global::<Smallvil>(&state.display_handle);
+        let damage_tracker = OutputDamageTracker::from_output(&output);
+        outputs.push(WinitOutput { output, damage_tracker, buffer: None });
+    }
 
-    let output = Output::new(
-        "winit".to_string(),
-        PhysicalProperties {
-            size: (0, 0).into(),
-            subpixel: Subpixel::Unknown,
-            make: "Smithay".into(),
-            model: "Winit".into(),
-        },
-    );
-    let _global = output.create_global::<Smallvil>(&state.display_handle);
-    output.change_current_state(Some(mode), Some(Transform::Flipped180), None, Some((0, 0).into()));
-    output.set_preferred(mode);
+    state.winit = Some(WinitData {
+        backend,
+        outputs,
+        dirty: false,
+        cursor: SoftwareCursor::default(),
+        force_software_cursor: options.software_cursor,
+    });
+    state.layout_winit_outputs();
+    if let Some(winit) = state.winit.as_ref() {
+        let outputs: Vec<_> = winit.outputs().cloned().collect();
+        for output in outputs {
+            if let Some(mode) = output.current_mode() {
+                output.set_preferred(mode);
+            }
+            state.ensure_output_workspace(&output);
+        }
+    }
+    state.update_winit_cursor();
+    state.request_redraw_all();
+
+    event_loop.handle().insert_source(winit, move |event, (), state| match event {
+        // Also sent when the window moves to a host output with another scale.
+        WinitEvent::Resized { .. } => state.layout_winit_outputs(),
+        WinitEvent::Input(event) => state.process_input_event(event),
+        WinitEvent::Redraw => state.render_winit(),
+        WinitEvent::CloseRequested => state.close_winit_output(),
+        WinitEvent::Focus(_) => {}
+    })?;
 
-    state.space.map_output(&output, (0, 0));
+    Ok(())
+}
 
-    let mut damage_tracker = OutputDamageTracker::from_output(&output);
+impl Smallvil {
+    /// Splits the window into equal columns, one per output, at the host's scale factor.
+    fn layout_winit_outputs(&mut self) {
+        let Some(winit) = self.winit.as_mut() else {
+            return;
+        };
 
-    event_loop.handle().insert_source(winit, move |event, (), state| match event {
-        WinitEvent::Resized { size, .. } => {
-            output.change_current_state(Some(Mode { size, refresh: 60_000 }), None, None, None);
-            state.arrange_windows_tiled();
+        let size = winit.backend.window_size();
+        // The host's scale factor, so the nested session isn't tiny or blurry on HiDPI.
+        let scale = winit.backend.scale_factor();
+        // A single output is drawn straight into the window, whose framebuffer is upside
+        // down. Split outputs are drawn into buffers, which are not.
+        let transform =
+            if winit.outputs.len() == 1 { Transform::Flipped180 } else { Transform::Normal };
+        let count = i32::try_from(winit.outputs.len()).unwrap_or(i32::MAX).max(1);
+
+        let mut placed = Vec::new();
+        let mut x = 0;
+        for (index, target) in (0..count).zip(&mut winit.outputs) {
+            // The last column takes what the division leaves over.
+            let width = if index == count - 1 { size.w - x } else { size.w / count };
+            let mode = Mode { size: (width, size.h).into(), refresh: REFRESH_MHZ };
+            let position: Point<i32, Logical> =
+                Point::<i32, Physical>::from((x, 0)).to_f64().to_logical(scale).to_i32_round();
+            target.output.change_current_state(
+                Some(mode),
+                Some(transform),
+                Some(OutputScale::Fractional(scale)),
+                Some(position),
+            );
+            target.damage_tracker = OutputDamageTracker::from_output(&target.output);
+            target.buffer = None;
+            placed.push((target.output.clone(), position));
+            x += width;
         }
-        WinitEvent::Input(event) => state.process_input_event(event),
-        WinitEvent::Redraw => {
-            let size = backend.window_size();
-            let damage = Rectangle::from_size(size);
-
-            {
-                let (renderer, mut framebuffer) = match backend.bind() {
-                    Ok(bind) => bind,
-                    Err(err) => {
-                        tracing::error!("Failed to bind winit backend framebuffer: {err}");
-                        return;
-                    }
-                };
-
-                let mut elements: Vec<
-                    WinitOutputRenderElements<
-                        GlesRenderer,
-                        WaylandSurfaceRenderElement<GlesRenderer>,
-                    >,
-                > = Vec::new();
-
-                let space_elements = match smithay::desktop::space::space_render_elements(
-                    renderer,
-                    [&state.space],
-                    &output,
-                    1.0,
-                ) {
-                    Ok(elements) => elements,
-                    Err(err) => {
-                        tracing::error!("Failed to collect render elements: {err}");
-                        return;
-                    }
-                };
-
-                if let Some(output_geo) = state.space.output_geometry(&output) {
-                    let border_elements = crate::drawing::tiled_border_elements(
-                        output_geo,
-                        &state.space,
-                        state.active_surface.as_ref(),
-                        state.active_border_color,
-                        state.inactive_border_color,
-                        state.border_width,
-                    );
-                    elements
-                        .extend(border_elements.into_iter().map(WinitOutputRenderElements::Border));
-                }
 
-                elements.extend(space_elements.into_iter().map(WinitOutputRenderElements::Space));
+        for (output, position) in placed {
+            self.space.map_output(&output, position);
+        }
+        self.output_modes_changed();
+        self.arrange_windows_tiled();
+        self.request_redraw_all();
+    }
 
-                if let Some(mode) = output.current_mode()
-                    && let Some(wallpaper_element) =
-                        state.wallpaper.render_element(renderer, mode.size)
-                {
-                    elements.push(WinitOutputRenderElements::Wallpaper(wallpaper_element));
-                }
+    /// Closing the window takes away the output under the pointer, as if it had been
+    /// unplugged, and quits once only one is left.
+    fn close_winit_output(&mut self) {
+        let Some(winit) = self.winit.as_mut() else {
+            return;
+        };
+        if winit.outputs.len() <= 1 {
+            self.loop_signal.stop();
+            return;
+        }
+
+        let pointer_output = self.seat.pointer().and_then(|pointer| {
+            self.space.output_under(pointer.current_location()).next().cloned()
+        });
+        let index = pointer_output
+            .and_then(|output| winit.outputs.iter().position(|target| target.output == output))
+            .unwrap_or(winit.outputs.len() - 1);
+        let removed = winit.outputs.remove(index);
+        tracing::info!("Removed winit output {}", removed.output.name());
+
+        self.remove_output(&removed.output);
+        self.layout_winit_outputs();
+        self.space.refresh();
+    }
 
-                if let Err(err) = damage_tracker.render_output(
-                    renderer,
-                    &mut framebuffer,
-                    0,
-                    &elements,
-                    [0.0, 0.0, 0.0, 1.0],
-                ) {
-                    tracing::error!("Failed to render output: {err}");
+    /// Hides the host cursor over the nested window while the pointer is drawn in it.
+    pub(crate) fn update_winit_cursor(&mut self) {
+        let software_cursor = self.software_cursor;
+        let Some(winit) = self.winit.as_mut() else {
+            return;
+        };
+        let software_cursor = software_cursor || winit.force_software_cursor;
+        winit.backend.window().set_cursor_visible(!software_cursor);
+        winit.request_redraw();
+    }
+
+    fn render_winit(&mut self) {
+        // Taken out while drawing, so the rest of the state stays borrowable.
+        let Some(mut winit) = self.winit.take() else {
+            return;
+        };
+        winit.dirty = false;
+        if winit.outputs.len() == 1 {
+            self.render_winit_output(&mut winit);
+        } else {
+            self.render_winit_split(&mut winit);
+        }
+        let outputs: Vec<_> = winit.outputs().cloned().collect();
+        self.winit = Some(winit);
+
+        let time = self.start_time.elapsed();
+        for output in &outputs {
+            self.send_frame_callbacks(output, time);
+        }
+
+        self.space.refresh();
+        self.popups.cleanup();
+        let _ = self.display_handle.flush_clients();
+    }
+
+    fn render_winit_output(&mut self, winit: &mut WinitData) {
+        let software_cursor = self.software_cursor || winit.force_software_cursor;
+        let WinitData { backend, outputs, cursor, .. } = winit;
+        let Some(target) = outputs.first_mut() else {
+            return;
+        };
+        let cursor = software_cursor.then_some(cursor);
+
+        let damage = {
+            let age = backend.buffer_age().unwrap_or(0);
+            let (renderer, mut framebuffer) = match backend.bind() {
+                Ok(bind) => bind,
+                Err(err) => {
+                    tracing::error!("Failed to bind winit backend framebuffer: {err}");
                     return;
                 }
-            }
+            };
+            self.render_gles_output(
+                renderer,
+                &mut framebuffer,
+                age,
+                &target.output,
+                &mut target.damage_tracker,
+                cursor,
+            )
+        };
 
-            if let Err(err) = backend.submit(Some(&[damage])) {
-                tracing::error!("Failed to submit frame to winit backend: {err}");
-                return;
-            }
+        // Nothing changed on screen, there is nothing to show the host.
+        let Some(damage) = damage else {
+            return;
+        };
+        if let Err(err) = backend.submit(Some(damage.as_slice())) {
+            tracing::error!("Failed to submit frame to winit backend: {err}");
+        }
+    }
 
-            state.space.elements().for_each(|window| {
-                window.send_frame(
-                    &output,
-                    state.start_time.elapsed(),
-                    Some(Duration::ZERO),
-                    |_, _| Some(output.clone()),
-                );
-            });
+    /// Draws each output into its buffer, then the buffers into their columns of the
+    /// window.
+    fn render_winit_split(&mut self, winit: &mut WinitData) {
+        let software_cursor = self.software_cursor || winit.force_software_cursor;
+        let WinitData { backend, outputs, cursor, .. } = winit;
+        let mut cursor = software_cursor.then_some(cursor);
 
-            state.space.refresh();
-            state.popups.cleanup();
-            let _ = state.display_handle.flush_clients();
+        let mut changed = false;
+        for target in outputs.iter_mut() {
+            let renderer = backend.renderer();
+            changed |=
+                self.render_winit_offscreen(renderer, target, cursor.as_deref_mut()).is_some();
+        }
+        // Nothing changed on screen, there is nothing to show the host.
+        if !changed {
+            return;
+        }
 
-            backend.window().request_redraw();
+        // The columns are copied whole, so the whole window is damaged.
+        let window = Rectangle::from_size(backend.window_size());
+        let copied = {
+            let (renderer, mut framebuffer) = match backend.bind() {
+                Ok(bind) => bind,
+                Err(err) => {
+                    tracing::error!("Failed to bind winit backend framebuffer: {err}");
+                    return;
+                }
+            };
+            copy_columns(renderer, &mut framebuffer, window, outputs)
+        };
+        if let Err(err) = copied {
+            tracing::error!("Failed to draw winit outputs: {err}");
+            return;
         }
-        WinitEvent::CloseRequested => {
-            state.loop_signal.stop();
+
+        if let Err(err) = backend.submit(Some([window].as_slice())) {
+            tracing::error!("Failed to submit frame to winit backend: {err}");
         }
-        WinitEvent::Focus(_) => {}
-    })?;
+    }
+
+    /// Draws `target` into its buffer, created on first use. Returns the damage of the
+    /// frame, `None` if nothing changed or drawing failed.
+    fn render_winit_offscreen(
+        &mut self,
+        renderer: &mut GlesRenderer,
+        target: &mut WinitOutput,
+        cursor: Option<&mut SoftwareCursor>,
+    ) -> Option<Vec<Rectangle<i32, Physical>>> {
+        let mode = target.output.current_mode()?;
+
+        // The same buffer is drawn every frame, it holds the previous one.
+        let age = usize::from(target.buffer.is_some());
+        if target.buffer.is_none() {
+            let size = (mode.size.w, mode.size.h).into();
+            match Offscreen::<GlesTexture>::create_buffer(renderer, Fourcc::Abgr8888, size) {
+                Ok(buffer) => target.buffer = Some(buffer),
+                Err(err) => {
+                    tracing::error!("Failed to create winit output buffer: {err}");
+                    return None;
+                }
+            }
+        }
+        let buffer = target.buffer.as_mut()?;
+        let mut framebuffer = match renderer.bind(buffer) {
+            Ok(framebuffer) => framebuffer,
+            Err(err) => {
+                tracing::error!("Failed to bind winit output buffer: {err}");
+                return None;
+            }
+        };
+
+        self.render_gles_output(
+            renderer,
+            &mut framebuffer,
+            age,
+            &target.output,
+            &mut target.damage_tracker,
+            cursor,
+        )
+    }
+}
+
+/// Copies the output buffers side by side into the window framebuffer.
+fn copy_columns(
+    renderer: &mut GlesRenderer,
+    framebuffer: &mut GlesTarget<'_>,
+    window: Rectangle<i32, Physical>,
+    outputs: &[WinitOutput],
+) -> Result<(), GlesError> {
+    let mut frame = renderer.render(framebuffer, window.size, Transform::Flipped180)?;
+    frame.clear(Color32F::BLACK, &[window])?;
+
+    let mut x = 0;
+    for target in outputs {
+        let (Some(buffer), Some(mode)) = (target.buffer.as_ref(), target.output.current_mode())
+        else {
+            continue;
+        };
+        let src = Rectangle::from_size((f64::from(mode.size.w), f64::from(mode.size.h)).into());
+        let dst = Rectangle::new((x, 0).into(), mode.size);
+        frame.render_texture_from_to(buffer, src, dst, &[dst], &[], Transform::Normal, 1.0)?;
+        x += mode.size.w;
+    }
 
+    frame.finish()?;
     Ok(())
 }
@@ -0,0 +1,380 @@
+use smithay::{desktop::Window, output::Output, utils::IsAlive};
+
+use crate::{Smallvil, window::workspace_of};
+
+/// A numbered workspace living on one output.
+///
+/// Workspaces are created on demand and dropped again once they are neither shown
+/// nor hold any windows, the same way sway and i3 handle them.
+pub struct Workspace {
+    pub id: u32,
+    pub output: Output,
+    pub active: bool,
+    pub urgent: bool,
+    /// Windows of this workspace while it is not shown. The windows of an active
+    /// workspace are mapped in the space instead.
+    pub hidden_windows: Vec<Window>,
+}
+
+#[derive(Default)]
+pub struct Workspaces {
+    list: Vec<Workspace>,
+    /// The workspace shown before the last switch, for going back and forth.
+    previous: Option<u32>,
+    /// `workspace_back_and_forth` from the config.
+    pub back_and_forth: bool,
+}
+
+impl Workspaces {
+    pub fn new(back_and_forth: bool) -> Self {
+        Self { back_and_forth, ..Self::default() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Workspace> {
+        self.list.iter()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Workspace> {
+        self.list.iter().find(|workspace| workspace.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Workspace> {
+        self.list.iter_mut().find(|workspace| workspace.id == id)
+    }
+
+    pub fn active_on(&self, output: &Output) -> Option<u32> {
+        self.list
+            .iter()
+            .find(|workspace| workspace.active && &workspace.output == output)
+            .map(|workspace| workspace.id)
+    }
+
+    fn create(&mut self, id: u32, output: Output) {
+        let index = self.list.partition_point(|workspace| workspace.id < id);
+        self.list.insert(
+            index,
+            Workspace { id, output, active: false, urgent: false, hidden_windows: Vec::new() },
+        );
+    }
+
+    fn lowest_free_id(&self) -> u32 {
+        (1..).find(|id| self.get(*id).is_none()).unwrap_or(1)
+    }
+
+    pub fn hidden_windows(&self) -> impl Iterator<Item = &Window> {
+        self.list.iter().flat_map(|workspace| workspace.hidden_windows.iter())
+    }
+
+    pub fn prune_dead_windows(&mut self) {
+        for workspace in &mut self.list {
+            workspace.hidden_windows.retain(IsAlive::alive);
+        }
+    }
+}
+
+impl Smallvil {
+    /// Makes sure a newly mapped output shows a workspace, adopting workspaces left
+    /// behind by outputs that are gone.
+    pub fn ensure_output_workspace(&mut self, output: &Output) {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        let orphans: Vec<u32> = self
+            .workspaces
+            .iter()
+            .filter(|workspace| !outputs.contains(&workspace.output))
+            .map(|workspace| workspace.id)
+            .collect();
+
+        for id in orphans {
+            let was_active = self.workspaces.get(id).is_some_and(|workspace| workspace.active);
+            if was_active && self.workspaces.active_on(output).is_some() {
+                self.hide_workspace(id);
+            }
+            if let Some(workspace) = self.workspaces.get_mut(id) {
+                workspace.output = output.clone();
+            }
+        }
+
+        if self.workspaces.active_on(output).is_none() {
+            let id = self.workspaces.lowest_free_id();
+            self.workspaces.create(id, output.clone());
+            if let Some(workspace) = self.workspaces.get_mut(id) {
+                workspace.active = true;
+            }
+        }
+
+        self.workspaces_changed();
+    }
+
+    /// Moves the workspaces of an unmapped output to the first remaining one.
+    pub fn output_removed(&mut self) {
+        match self.space.outputs().next().cloned() {
+            Some(output) => self.ensure_output_workspace(&output),
+            None => self.workspaces_changed(),
+        }
+    }
+
+    /// Takes a disconnected `output` out of the layout. Its workspaces move to the first
+    /// remaining output, the windows it was showing join the workspace shown there, and a
+    /// pointer left outside every output moves to the nearest point of the others.
+    pub fn remove_output(&mut self, output: &Output) {
+        let shown = self.workspaces.active_on(output);
+        self.space.unmap_output(output);
+        self.output_removed();
+
+        let Some(survivor) = self.space.outputs().next().cloned() else {
+            return;
+        };
+
+        if let Some(shown) = shown
+            && let Some(target) = self.workspaces.active_on(&survivor)
+            && shown != target
+        {
+            let windows: Vec<Window> = self
+                .workspaces
+                .get(shown)
+                .map(|workspace| workspace.hidden_windows.clone())
+                .unwrap_or_default();
+            for window in &windows {
+                self.move_window_to_workspace(window, target);
+            }
+        }
+
+        let Some(pointer) = self.seat.pointer() else {
+            return;
+        };
+        let location = pointer.current_location();
+        if self.space.output_under(location).next().is_none() {
+            let time = u32::try_from(self.start_time.elapsed().as_millis()).unwrap_or(u32::MAX);
+            self.pointer_motion_to(self.constrain_to_outputs(location), time);
+            self.pointer_frame();
+        }
+    }
+
+    /// The output new windows and workspaces go to: the one holding the focused window,
+    /// then the one under the pointer, then the first one.
+    pub fn current_output(&self) -> Option<Output> {
+        let focused_output = self
+            .active_surface
+            .as_ref()
+            .and_then(|surface| self.window_for_surface(surface))
+            .and_then(|window| self.space.outputs_for_element(&window).into_iter().next());
+        if focused_output.is_some() {
+            return focused_output;
+        }
+
+        let pointer_output = self.seat.pointer().and_then(|pointer| {
+            self.space.output_under(pointer.current_location()).next().cloned()
+        });
+        if pointer_output.is_some() {
+            return pointer_output;
+        }
+
+        self.space.outputs().next().cloned()
+    }
+
+    pub fn current_workspace(&self) -> Option<u32> {
+        self.current_output().and_then(|output| self.workspaces.active_on(&output))
+    }
+
+    /// Switches to workspace `id` on behalf of the user, from a keybinding or IPC. With
+    /// `workspace_back_and_forth`, asking for the current workspace goes back to the
+    /// previous one.
+    pub fn select_workspace(&mut self, id: u32) {
+        let current = self.current_workspace();
+        if self.workspaces.back_and_forth
+            && current == Some(id)
+            && let Some(previous) = self.workspaces.previous
+        {
+            self.switch_workspace(previous);
+            return;
+        }
+        self.switch_workspace(id);
+    }
+
+    /// The workspace `offset` places after the current one, by id and across outputs,
+    /// wrapping around at either end.
+    pub fn relative_workspace(&self, offset: isize) -> Option<u32> {
+        let ids: Vec<u32> = self.workspaces.iter().map(|workspace| workspace.id).collect();
+        let current = self.current_workspace()?;
+        let index = ids.iter().position(|id| *id == current)?;
+        let len = isize::try_from(ids.len()).ok()?;
+        let index = (isize::try_from(index).ok()? + offset).rem_euclid(len);
+        ids.get(usize::try_from(index).ok()?).copied()
+    }
+
+    /// Shows workspace `id`, creating it on the current output if it doesn't exist yet,
+    /// and focuses its topmost window. Keybindings, IPC and ext-workspace all end up here.
+    pub fn switch_workspace(&mut self, id: u32) {
+        let current = self.current_workspace();
+        if self.workspaces.get(id).is_none() {
+            let Some(output) = self.current_output() else {
+                return;
+            };
+            self.workspaces.create(id, output);
+        }
+
+        let Some(output) = self.workspaces.get(id).map(|workspace| workspace.output.clone()) else {
+            return;
+        };
+
+        if let Some(previous) = self.workspaces.active_on(&output)
+            && previous != id
+        {
+            self.hide_workspace(previous);
+        }
+
+        self.show_workspace(id);
+        self.remove_empty_workspaces();
+        self.arrange_windows_tiled();
+
+        self.focus_top_window(id);
+        if current != Some(id) {
+            self.workspaces.previous = current;
+        }
+
+        self.workspaces_changed();
+        self.request_redraw_all();
+    }
+
+    /// Moves `window` to workspace `id`, creating it on the current output if needed.
+    pub fn move_window_to_workspace(&mut self, window: &Window, id: u32) {
+        if workspace_of(window) == id {
+            return;
+        }
+
+        if self.workspaces.get(id).is_none() {
+            let Some(output) = self.current_output() else {
+                return;
+            };
+            self.workspaces.create(id, output);
+        }
+
+        let is_mapped = self.space.elements().any(|mapped| mapped == window);
+        for workspace in self.workspaces.list.iter_mut() {
+            workspace.hidden_windows.retain(|hidden| hidden != window);
+        }
+        crate::window::window_data(window).workspace = id;
+
+        let target_visible = self.workspaces.get(id).is_some_and(|workspace| workspace.active);
+        if target_visible {
+            if !is_mapped {
+                self.remap_window(window.clone());
+            }
+        } else {
+            if is_mapped {
+                self.unmap_window(window);
+            }
+            if let Some(workspace) = self.workspaces.get_mut(id) {
+                workspace.hidden_windows.push(window.clone());
+            }
+        }
+
+        let was_focused = window
+            .toplevel()
+            .is_some_and(|toplevel| self.active_surface.as_ref() == Some(toplevel.wl_surface()));
+        if was_focused && !target_visible {
+            match self.current_workspace() {
+                Some(current) => self.focus_top_window(current),
+                None => self.set_keyboard_focus(None),
+            }
+        }
+
+        // Dialogs go along with the window they belong to.
+        let children: Vec<Window> = window
+            .toplevel()
+            .map(|toplevel| {
+                self.space
+                    .elements()
+                    .chain(self.workspaces.hidden_windows())
+                    .filter(|child| {
+                        crate::window::parent_of(child).as_ref() == Some(toplevel.wl_surface())
+                    })
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        for child in children {
+            self.move_window_to_workspace(&child, id);
+        }
+
+        self.remove_empty_workspaces();
+        self.arrange_windows_tiled();
+        self.workspaces_changed();
+        self.request_redraw_all();
+    }
+
+    /// Focuses the topmost window of workspace `id`, or nothing if it has no visible windows.
+    pub fn focus_top_window(&mut self, id: u32) {
+        let focus = self
+            .space
+            .elements()
+            .rev()
+            .find(|window| workspace_of(window) == id)
+            .and_then(|window| window.toplevel().map(|toplevel| toplevel.wl_surface().clone()));
+        self.set_keyboard_focus(focus);
+    }
+
+    fn hide_workspace(&mut self, id: u32) {
+        let windows: Vec<Window> =
+            self.space.elements().filter(|window| workspace_of(window) == id).cloned().collect();
+
+        for window in &windows {
+            self.unmap_window(window);
+        }
+
+        if let Some(workspace) = self.workspaces.get_mut(id) {
+            workspace.active = false;
+            workspace.hidden_windows.extend(windows);
+        }
+    }
+
+    fn show_workspace(&mut self, id: u32) {
+        let Some(workspace) = self.workspaces.get_mut(id) else {
+            return;
+        };
+        workspace.active = true;
+        workspace.urgent = false;
+        let windows = std::mem::take(&mut workspace.hidden_windows);
+
+        for window in windows {
+            self.remap_window(window);
+        }
+    }
+
+    /// Unmaps `window`, remembering where it was for [`Self::remap_window`].
+    pub fn unmap_window(&mut self, window: &Window) {
+        crate::window::window_data(window).hidden_location = self.space.element_location(window);
+        self.space.unmap_elem(window);
+    }
+
+    /// Maps `window` back where it was unmapped, at the same spot of its workspace's
+    /// output if that moved to another output in between. Tiled windows are placed by
+    /// the next retile anyway.
+    pub fn remap_window(&mut self, window: Window) {
+        let saved = crate::window::window_data(&window).hidden_location.take();
+        let target = self
+            .workspaces
+            .get(workspace_of(&window))
+            .and_then(|workspace| self.space.output_geometry(&workspace.output));
+
+        let location = match (saved, target) {
+            (Some(location), Some(target)) if !target.contains(location) => self
+                .space
+                .outputs()
+                .filter_map(|output| self.space.output_geometry(output))
+                .find(|geometry| geometry.contains(location))
+                .map_or(target.loc, |from| target.loc + (location - from.loc)),
+            (Some(location), _) => location,
+            (None, target) => target.map(|target| target.loc).unwrap_or_default(),
+        };
+        self.space.map_element(window, location, false);
+    }
+
+    /// Drops workspaces that are neither shown nor hold any live windows.
+    pub fn remove_empty_workspaces(&mut self) {
+        self.workspaces.prune_dead_windows();
+        self.workspaces
+            .list
+            .retain(|workspace| workspace.active || !workspace.hidden_windows.is_empty());
+    }
+}
@@ -0,0 +1,733 @@
+//! Starts ripwm on the headless backend, maps windows with a small shm client and checks
+//! where the tiler put them through the IPC window list.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::{fd::AsFd, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use wayland_client::{
+    Connection, Dispatch, EventQueue, QueueHandle, delegate_noop,
+    globals::{GlobalListContents, registry_queue_init},
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_compositor::WlCompositor,
+        wl_registry::{self, WlRegistry},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+};
+use wayland_protocols::xdg::shell::client::{
+    xdg_surface::{self, XdgSurface},
+    xdg_toplevel::{self, XdgToplevel},
+    xdg_wm_base::{self, XdgWmBase},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A ripwm process with its own runtime and home directory, killed on drop.
+struct Compositor {
+    child: Child,
+    dir: TempDir,
+}
+
+impl Compositor {
+    fn start(args: &[&str]) -> Self {
+        Self::start_in(tempfile::tempdir().expect("failed to create runtime dir"), args)
+    }
+
+    /// Like `start`, with `config` as the config file.
+    fn start_with_config(config: &str, args: &[&str]) -> Self {
+        let dir = tempfile::tempdir().expect("failed to create runtime dir");
+        let config_dir = dir.path().join(".config/ripwm");
+        std::fs::create_dir_all(&config_dir).expect("failed to create config dir");
+        std::fs::write(config_dir.join("ripwm.toml"), config).expect("failed to write config");
+        Self::start_in(dir, args)
+    }
+
+    fn start_in(dir: TempDir, args: &[&str]) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_ripwm"))
+            .arg("--headless")
+            .args(args)
+            .env("XDG_RUNTIME_DIR", dir.path())
+            .env("HOME", dir.path())
+            .env("RIPWM_IPC_SOCKET", dir.path().join("ripwm.sock"))
+            .env_remove("WAYLAND_DISPLAY")
+            .env_remove("DISPLAY")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start ripwm");
+
+        let compositor = Self { child, dir };
+        wait_for(|| compositor.wayland_socket().is_some() && compositor.ipc_socket().exists());
+        compositor
+    }
+
+    fn wayland_socket(&self) -> Option<PathBuf> {
+        std::fs::read_dir(self.dir.path()).ok()?.filter_map(Result::ok).map(|e| e.path()).find(
+            |path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("wayland-") && !name.ends_with(".lock"))
+            },
+        )
+    }
+
+    fn ipc_socket(&self) -> PathBuf {
+        self.dir.path().join("ripwm.sock")
+    }
+
+    fn connect(&self) -> Client {
+        let socket = self.wayland_socket().expect("no wayland socket");
+        let stream = UnixStream::connect(socket).expect("failed to connect to ripwm");
+        Client::new(Connection::from_socket(stream).expect("failed to set up connection"))
+    }
+
+    /// Sends a JSON request and returns the response, which must be successful.
+    fn ipc(&self, request: &Value) -> Value {
+        let response = send_ipc(&self.ipc_socket(), &request.to_string()).expect("IPC failed");
+        let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+        assert_eq!(response["ok"], true, "IPC request failed: {response}");
+        response
+    }
+
+    /// The mapped windows as listed by the `windows` command.
+    fn window_list(&self) -> Vec<Value> {
+        let response = self.ipc(&json!({ "cmd": "windows" }));
+        response["windows"].as_array().cloned().unwrap_or_default()
+    }
+
+    /// The mapped windows as `(x, y, width, height, app_id)`, left to right.
+    fn windows(&self) -> Vec<(i32, i32, i32, i32, String)> {
+        let mut windows: Vec<_> = self.window_list().iter().filter_map(parse_window).collect();
+        windows.sort();
+        windows
+    }
+}
+
+impl Drop for Compositor {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn send_ipc(socket: &Path, command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(command.as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+fn parse_window(window: &Value) -> Option<(i32, i32, i32, i32, String)> {
+    let geometry = &window["geometry"];
+    let field = |name: &str| geometry[name].as_i64().and_then(|value| i32::try_from(value).ok());
+    let app_id = window["app_id"].as_str()?.to_string();
+    Some((field("x")?, field("y")?, field("width")?, field("height")?, app_id))
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for ripwm");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// A Wayland client whose toplevels draw a buffer of whatever size they are configured to.
+struct Client {
+    queue: EventQueue<ClientState>,
+    state: ClientState,
+}
+
+struct ClientState {
+    compositor: WlCompositor,
+    shm: WlShm,
+    wm_base: XdgWmBase,
+    windows: Vec<TestWindow>,
+}
+
+struct TestWindow {
+    surface: WlSurface,
+    _xdg_surface: XdgSurface,
+    _toplevel: XdgToplevel,
+    size: (i32, i32),
+    buffer: Option<WlBuffer>,
+}
+
+impl Client {
+    fn new(conn: Connection) -> Self {
+        let (globals, queue) = registry_queue_init::<ClientState>(&conn).expect("no registry");
+        let qh = queue.handle();
+        let state = ClientState {
+            compositor: globals.bind(&qh, 4..=6, ()).expect("no wl_compositor"),
+            shm: globals.bind(&qh, 1..=1, ()).expect("no wl_shm"),
+            wm_base: globals.bind(&qh, 1..=6, ()).expect("no xdg_wm_base"),
+            windows: Vec::new(),
+        };
+        Self { queue, state }
+    }
+
+    fn open_window(&mut self, app_id: &str) {
+        self.open_window_titled(app_id, "");
+    }
+
+    fn open_window_titled(&mut self, app_id: &str, title: &str) {
+        let qh = self.queue.handle();
+        let index = self.state.windows.len();
+        let surface = self.state.compositor.create_surface(&qh, ());
+        let xdg_surface = self.state.wm_base.get_xdg_surface(&surface, &qh, index);
+        let toplevel = xdg_surface.get_toplevel(&qh, index);
+        toplevel.set_app_id(app_id.to_string());
+        if !title.is_empty() {
+            toplevel.set_title(title.to_string());
+        }
+        surface.commit();
+
+        self.state.windows.push(TestWindow {
+            surface,
+            _xdg_surface: xdg_surface,
+            _toplevel: toplevel,
+            size: (0, 0),
+            buffer: None,
+        });
+        self.roundtrip();
+    }
+
+    fn roundtrip(&mut self) {
+        self.queue.roundtrip(&mut self.state).expect("connection to ripwm lost");
+    }
+}
+
+impl TestWindow {
+    /// Attaches a transparent buffer of the configured size, 100x100 if the compositor
+    /// left it up to the client.
+    fn draw(&mut self, shm: &WlShm, qh: &QueueHandle<ClientState>) {
+        let (width, height) = match self.size {
+            (width, height) if width > 0 && height > 0 => (width, height),
+            _ => (100, 100),
+        };
+        let stride = width * 4;
+        let len = stride * height;
+
+        let file = tempfile::tempfile().expect("failed to create shm file");
+        file.set_len(u64::try_from(len).expect("negative buffer size"))
+            .expect("failed to size shm file");
+        let pool = shm.create_pool(file.as_fd(), len, qh, ());
+        let buffer = pool.create_buffer(0, width, height, stride, wl_shm::Format::Argb8888, qh, ());
+        pool.destroy();
+
+        self.surface.attach(Some(&buffer), 0, 0);
+        self.surface.damage_buffer(0, 0, width, height);
+        self.surface.commit();
+        if let Some(old) = self.buffer.replace(buffer) {
+            old.destroy();
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for ClientState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<XdgWmBase, ()> for ClientState {
+    fn event(
+        _: &mut Self,
+        wm_base: &XdgWmBase,
+        event: xdg_wm_base::Event,
+        (): &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, usize> for ClientState {
+    fn event(
+        state: &mut Self,
+        xdg_surface: &XdgSurface,
+        event: xdg_surface::Event,
+        index: &usize,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+            if let Some(window) = state.windows.get_mut(*index) {
+                window.draw(&state.shm, qh);
+            }
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, usize> for ClientState {
+    fn event(
+        state: &mut Self,
+        _: &XdgToplevel,
+        event: xdg_toplevel::Event,
+        index: &usize,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_toplevel::Event::Configure { width, height, .. } = event
+            && let Some(window) = state.windows.get_mut(*index)
+        {
+            window.size = (width, height);
+        }
+    }
+}
+
+delegate_noop!(ClientState: WlCompositor);
+delegate_noop!(ClientState: WlShmPool);
+delegate_noop!(ClientState: ignore WlSurface);
+delegate_noop!(ClientState: ignore WlShm);
+delegate_noop!(ClientState: ignore WlBuffer);
+
+/// Pumps `client` until the window list matches `expected`.
+fn wait_for_windows(
+    compositor: &Compositor,
+    client: &mut Client,
+    expected: &[(i32, i32, i32, i32, &str)],
+) {
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|(x, y, width, height, app_id)| (*x, *y, *width, *height, (*app_id).to_string()))
+        .collect();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        client.roundtrip();
+        let windows = compositor.windows();
+        if windows == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "expected windows {expected:?}, got {windows:?}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn starts_without_windows() {
+    let compositor = Compositor::start(&[]);
+    assert_eq!(compositor.windows(), Vec::new());
+}
+
+#[test]
+fn single_window_fills_the_output() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window("one");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "one")]);
+}
+
+#[test]
+fn two_windows_split_the_output() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window("left");
+    client.open_window("right");
+    wait_for_windows(
+        &compositor,
+        &mut client,
+        &[(0, 0, 640, 720, "left"), (640, 0, 640, 720, "right")],
+    );
+}
+
+#[test]
+fn outputs_are_laid_out_side_by_side() {
+    let compositor = Compositor::start(&["--headless-outputs", "2", "--headless-size", "800x600"]);
+    let mut client = compositor.connect();
+
+    let outputs = compositor.ipc(&json!({ "cmd": "outputs" }));
+    let outputs: Vec<_> = outputs["outputs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|output| (output["name"].clone(), output["geometry"].clone()))
+        .collect();
+    assert_eq!(
+        outputs,
+        [
+            (json!("HEADLESS-1"), json!({ "x": 0, "y": 0, "width": 800, "height": 600 })),
+            (json!("HEADLESS-2"), json!({ "x": 800, "y": 0, "width": 800, "height": 600 })),
+        ]
+    );
+
+    // New windows open on the first output, which has the pointer.
+    client.open_window("first");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 800, 600, "first")]);
+}
+
+#[test]
+fn window_list_reports_titles_and_focus() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    let title = "say \"hi\"\nthen\\leave";
+    client.open_window_titled("titled", title);
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "titled")]);
+
+    let windows = compositor.window_list();
+    let [window] = windows.as_slice() else {
+        panic!("expected one window, got {windows:?}");
+    };
+    assert_eq!(window["title"], title);
+    assert_eq!(window["output"], "HEADLESS-1");
+    assert_eq!(window["focused"], true);
+    assert_eq!(window["floating"], false);
+    assert_eq!(window["fullscreen"], false);
+}
+
+#[test]
+fn bare_text_commands_still_work() {
+    let compositor = Compositor::start(&[]);
+    let response = send_ipc(&compositor.ipc_socket(), "minimized").expect("IPC failed");
+    assert_eq!(response, "");
+
+    let response = send_ipc(&compositor.ipc_socket(), "bogus").expect("IPC failed");
+    assert!(response.starts_with("error: "), "unexpected response {response:?}");
+}
+
+#[test]
+fn invalid_requests_get_an_error() {
+    let compositor = Compositor::start(&[]);
+    let response = send_ipc(&compositor.ipc_socket(), r#"{"cmd": "bogus"}"#).expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+    assert!(response["error"].is_string(), "no error in {response}");
+}
+
+#[test]
+fn focus_picks_a_window_by_app_id_or_title() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window_titled("left", "Left window");
+    client.open_window_titled("right", "Right window");
+    wait_for_windows(
+        &compositor,
+        &mut client,
+        &[(0, 0, 640, 720, "left"), (640, 0, 640, 720, "right")],
+    );
+
+    let focused = || {
+        compositor
+            .window_list()
+            .iter()
+            .find(|window| window["focused"] == true)
+            .map(|window| window["app_id"].clone())
+    };
+    assert_eq!(focused(), Some(json!("right")));
+
+    compositor.ipc(&json!({ "cmd": "focus", "app_id": "left" }));
+    assert_eq!(focused(), Some(json!("left")));
+
+    compositor.ipc(&json!({ "cmd": "focus", "title": "^Right", "regex": true }));
+    assert_eq!(focused(), Some(json!("right")));
+
+    let response = send_ipc(&compositor.ipc_socket(), r#"{"cmd": "focus", "app_id": "none"}"#)
+        .expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+}
+
+#[test]
+fn output_changes_apply_at_runtime() {
+    let compositor = Compositor::start(&["--headless-outputs", "2", "--headless-size", "800x600"]);
+
+    compositor.ipc(&json!({ "cmd": "output", "name": "HEADLESS-2", "scale": 2.0 }));
+    compositor.ipc(&json!({ "cmd": "output", "name": "HEADLESS-2", "position": [0, 600] }));
+    let outputs = compositor.ipc(&json!({ "cmd": "outputs" }));
+    let second = outputs["outputs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|output| output["name"] == "HEADLESS-2")
+        .cloned()
+        .expect("HEADLESS-2 is gone");
+    assert_eq!(second["geometry"], json!({ "x": 0, "y": 600, "width": 400, "height": 300 }));
+
+    let response = send_ipc(
+        &compositor.ipc_socket(),
+        r#"{"cmd": "output", "name": "HEADLESS-9", "scale": 2.0}"#,
+    )
+    .expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+}
+
+#[test]
+fn workspaces_switch_and_list() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window("one");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "one")]);
+
+    compositor.ipc(&json!({ "cmd": "workspace", "id": 2 }));
+    let workspaces = compositor.ipc(&json!({ "cmd": "workspaces" }));
+    let listed: Vec<_> = workspaces["workspaces"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|workspace| {
+            (workspace["id"].clone(), workspace["windows"].clone(), workspace["active"].clone())
+        })
+        .collect();
+    assert_eq!(listed, [(json!(1), json!(1), json!(false)), (json!(2), json!(0), json!(true))]);
+    assert_eq!(compositor.windows(), Vec::new());
+
+    compositor.ipc(&json!({ "cmd": "workspace", "direction": "next" }));
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "one")]);
+}
+
+#[test]
+fn subscribers_hear_about_moved_windows() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window("mover");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "mover")]);
+
+    let mut subscriber = UnixStream::connect(compositor.ipc_socket()).expect("failed to connect");
+    subscriber.set_read_timeout(Some(TIMEOUT)).expect("failed to set a read timeout");
+    subscriber.write_all(b"{\"cmd\": \"subscribe\"}\n").expect("failed to subscribe");
+    let mut events = BufReader::new(subscriber).lines().map(|line| {
+        let line = line.expect("no event from ripwm");
+        serde_json::from_str::<Value>(&line).expect("invalid event")
+    });
+    assert_eq!(events.next(), Some(json!({ "ok": true })));
+
+    compositor.ipc(&json!({ "cmd": "move", "app_id": "mover", "workspace": 2 }));
+    let event = events.next().expect("ripwm closed the subscription");
+    assert_eq!(event["event"], "window-moved");
+    assert_eq!(event["app_id"], "mover");
+    assert_eq!(event["workspace"], 2);
+}
+
+#[test]
+fn debug_dump_can_redact_titles() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    client.open_window_titled("secret", "private notes");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "secret")]);
+
+    let response = compositor.ipc(&json!({ "cmd": "debug", "redact_titles": true }));
+    let debug = &response["debug"];
+    assert_eq!(debug["backend"], "headless");
+    assert_eq!(debug["windows"][0]["app_id"], "secret");
+    assert!(!debug.to_string().contains("private notes"), "title leaked in {debug}");
+}
+
+#[test]
+fn unknown_keyboard_layouts_are_rejected() {
+    let compositor = Compositor::start(&[]);
+    let response =
+        send_ipc(&compositor.ipc_socket(), r#"{"cmd": "keyboard", "layout": "qwerty-lol"}"#)
+            .expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+    assert_eq!(response["error"], "unknown layout 'qwerty-lol'");
+}
+
+#[test]
+fn multibyte_border_colours_are_rejected() {
+    let compositor = Compositor::start(&[]);
+    let response = send_ipc(&compositor.ipc_socket(), r##"{"cmd": "border", "active": "#a€bc"}"##)
+        .expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+    assert_eq!(response["error"], "invalid colour: #a€bc");
+
+    // Still running.
+    compositor.ipc(&json!({ "cmd": "border", "active": "#ff0000" }));
+}
+
+#[test]
+fn multibyte_wallpaper_colours_are_rejected() {
+    let compositor = Compositor::start(&[]);
+    let response = send_ipc(&compositor.ipc_socket(), r##"{"cmd": "wallpaper", "value": "#€€"}"##)
+        .expect("IPC failed");
+    let response: Value = serde_json::from_str(&response).expect("invalid IPC response");
+    assert_eq!(response["ok"], false);
+    assert_eq!(response["error"], "Invalid wallpaper color: #€€");
+
+    // Still running.
+    compositor.ipc(&json!({ "cmd": "wallpaper", "value": "#336699" }));
+}
+
+#[test]
+fn silent_ipc_clients_dont_block_the_compositor() {
+    let compositor = Compositor::start(&["--headless-size", "1280x720"]);
+    let mut client = compositor.connect();
+
+    // Connects and sends nothing, which used to freeze everything until it went away.
+    let _idle = UnixStream::connect(compositor.ipc_socket()).expect("failed to connect");
+    let started = Instant::now();
+
+    client.open_window("busy");
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "busy")]);
+    assert!(started.elapsed() < Duration::from_secs(5), "ripwm stalled behind the idle client");
+
+    thread::sleep(Duration::from_secs(5).saturating_sub(started.elapsed()));
+    assert_eq!(compositor.windows(), [(0, 0, 1280, 720, "busy".to_string())]);
+}
+
+#[test]
+fn borders_outside_windows_shrink_the_tiles() {
+    let compositor = Compositor::start_with_config(
+        "border_width = 10\nborder_outside = true\n",
+        &["--headless-size", "1280x720"],
+    );
+    let mut client = compositor.connect();
+
+    client.open_window("inset");
+    wait_for_windows(&compositor, &mut client, &[(10, 10, 1260, 700, "inset")]);
+
+    compositor.ipc(&json!({ "cmd": "border", "width": 0 }));
+    wait_for_windows(&compositor, &mut client, &[(0, 0, 1280, 720, "inset")]);
+}
+
+#[test]
+fn check_config_reports_problems_with_their_lines() {
+    let dir = tempfile::tempdir().expect("failed to create config dir");
+    let config = dir.path().join("ripwm.toml");
+    let check = |contents: &str| {
+        std::fs::write(&config, contents).expect("failed to write config");
+        let output = Command::new(env!("CARGO_BIN_EXE_ripwm"))
+            .arg("--check-config")
+            .arg(&config)
+            .env("HOME", dir.path())
+            .output()
+            .expect("failed to run ripwm");
+        (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+
+    let (ok, _) = check("corner_radius = 4\n");
+    assert!(ok);
+
+    let (ok, stderr) = check(
+        "corner_radius = 4\nactive_border_color = \"#zz\"\n\n[[output]]\nname = \"DP-1\"\nscale = 40.0\n",
+    );
+    assert!(!ok);
+    assert!(stderr.contains("line 2: active_border_color: Invalid color"), "{stderr}");
+    assert!(stderr.contains("line 6: output[0].scale: Invalid value"), "{stderr}");
+
+    let (ok, stderr) = check("corner_radius = = 4\n");
+    assert!(!ok);
+    assert!(stderr.contains("line 1: "), "{stderr}");
+}
+
+#[test]
+fn config_flag_replaces_the_default_config() {
+    let config_dir = tempfile::tempdir().expect("failed to create config dir");
+    let config = config_dir.path().join("alternate.toml");
+    std::fs::write(&config, "corner_radius = 3\n").expect("failed to write config");
+
+    let compositor = Compositor::start(&["--config", config.to_str().expect("non-UTF-8 path")]);
+    let config_of = |compositor: &Compositor| {
+        compositor.ipc(&json!({ "cmd": "debug" }))["debug"]["config"].clone()
+    };
+    assert_eq!(config_of(&compositor)["path"], config.to_str().expect("non-UTF-8 path"));
+    assert_eq!(config_of(&compositor)["corner_radius"], 3);
+
+    std::fs::write(&config, "corner_radius = 7\n").expect("failed to write config");
+    compositor.ipc(&json!({ "cmd": "reload" }));
+    assert_eq!(config_of(&compositor)["corner_radius"], 7);
+    assert!(!compositor.dir.path().join(".config/ripwm/ripwm.toml").exists());
+}
+
+#[test]
+fn auto_reload_picks_up_replaced_config_files() {
+    let compositor = Compositor::start_with_config("auto_reload = true\ncorner_radius = 3\n", &[]);
+    let corner_radius =
+        || compositor.ipc(&json!({ "cmd": "debug" }))["debug"]["config"]["corner_radius"].clone();
+    assert_eq!(corner_radius(), 3);
+
+    // Written next to it and renamed over it, as many editors save.
+    let config_dir = compositor.dir.path().join(".config/ripwm");
+    let replacement = config_dir.join("ripwm.toml.tmp");
+    std::fs::write(&replacement, "auto_reload = true\ncorner_radius = 9\n")
+        .expect("failed to write config");
+    std::fs::rename(&replacement, config_dir.join("ripwm.toml")).expect("failed to replace config");
+    wait_for(|| corner_radius() == 9);
+}
+
+#[test]
+fn spawned_programs_get_the_configured_environment() {
+    let compositor = Compositor::start_with_config(
+        r#"
+exec = ["sh -c 'echo $GREETING $XCURSOR_SIZE $WAYLAND_DISPLAY > $HOME/env'"]
+
+[env]
+GREETING = "hello-${HOME}"
+XCURSOR_SIZE = 32
+WAYLAND_DISPLAY = "elsewhere"
+"#,
+        &[],
+    );
+    let log = compositor.dir.path().join("env");
+    wait_for(|| std::fs::read_to_string(&log).is_ok_and(|env| env.ends_with('\n')));
+
+    let env = std::fs::read_to_string(&log).expect("failed to read env");
+    let socket = compositor.wayland_socket().expect("no wayland socket");
+    let socket = socket.file_name().and_then(|name| name.to_str()).expect("no socket name");
+    let home = compositor.dir.path().display();
+    assert_eq!(env.trim(), format!("hello-{home} 32 {socket}"));
+}
+
+#[test]
+fn autostart_runs_exec_once_and_exec_always_on_reload() {
+    let compositor = Compositor::start_with_config(
+        r#"
+exec = ["sh -c 'echo exec >> $HOME/autostart'"]
+exec_always = ["sh -c 'echo exec_always >> $HOME/autostart'", "does-not-exist --flag"]
+"#,
+        &[],
+    );
+    let log = compositor.dir.path().join("autostart");
+    let lines = || {
+        let mut lines: Vec<String> =
+            std::fs::read_to_string(&log).unwrap_or_default().lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+
+    wait_for(|| lines().len() == 2);
+    assert_eq!(lines(), ["exec", "exec_always"]);
+
+    compositor.ipc(&json!({ "cmd": "reload" }));
+    wait_for(|| lines().len() == 3);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(lines(), ["exec", "exec_always", "exec_always"]);
+}
+
+#[test]
+fn log_file_gets_the_log_from_the_start() {
+    let compositor = Compositor::start_with_config("log_file = \"~/state/ripwm.log\"\n", &[]);
+    let log = compositor.dir.path().join("state/ripwm.log");
+    wait_for(|| std::fs::read_to_string(&log).is_ok_and(|log| log.contains("Selected backend")));
+}